@@ -0,0 +1,119 @@
+// Field tuning with the buttons, no host needed: hold A during reset. The
+// display shows the selected setting's header and then its value digit by
+// digit, over and over. A nudges the value down and B up, A and B pressed
+// together select the next setting. Holding a button for a second saves
+// the settings to flash and the car starts as after a reset.
+//
+//   K  kp, in tenths
+//   S  speed, in percent
+//   T  trim, in percent, positive steers left, a minus comes first
+//
+// The values are changed with the checks of `set` (params.rs) and stop at
+// the ends of their range.
+
+use microbit::display::nonblocking::BitImage;
+
+use crate::config::Config;
+use crate::images::{digits, Pacing, ADJUST_KP, ADJUST_SPEED, ADJUST_TRIM, BLANK, MINUS};
+use crate::params::Param;
+
+// The setting, its header and the step of a press, also the unit shown
+const SETTINGS: [(Param, &BitImage, i32); 3] = [
+    (Param::Kp, &ADJUST_KP, 100),
+    (Param::Speed, &ADJUST_SPEED, 5),
+    (Param::Trim, &ADJUST_TRIM, 1),
+];
+// A second at one update per frame
+const LONG_FRAMES: u32 = 50;
+const HEADER_FRAMES: u32 = 25;
+const DIGIT_FRAMES: u32 = 20;
+const GAP_FRAMES: u32 = 5;
+const PAUSE_FRAMES: u32 = 25;
+// The digits after the header and the sign
+const PACING: Pacing = Pacing {
+    header: 0,
+    digit: DIGIT_FRAMES,
+    gap: GAP_FRAMES,
+};
+
+pub struct Adjust {
+    selected: usize,
+    // Frames since the header was shown last
+    tick: u32,
+    // The buttons pressed since both were up and for how long
+    pressed: (bool, bool),
+    held: u32,
+}
+
+impl Adjust {
+    pub fn new() -> Self {
+        Adjust {
+            selected: 0,
+            tick: 0,
+            pressed: (false, false),
+            held: 0,
+        }
+    }
+
+    // Once per frame with the buttons, true after the long press. A press
+    // acts when the buttons are released.
+    pub fn update(&mut self, config: &mut Config, a_low: bool, b_low: bool) -> bool {
+        self.tick += 1;
+        if a_low || b_low {
+            self.pressed = (self.pressed.0 || a_low, self.pressed.1 || b_low);
+            self.held += 1;
+            return self.held == LONG_FRAMES && self.pressed != (true, true);
+        }
+        let pressed = core::mem::take(&mut self.pressed);
+        let short = core::mem::take(&mut self.held) < LONG_FRAMES;
+        match pressed {
+            _ if !short => (),
+            (true, true) => {
+                self.selected = (self.selected + 1) % SETTINGS.len();
+                self.tick = 0;
+            }
+            (true, false) => self.nudge(config, -1),
+            (false, true) => self.nudge(config, 1),
+            (false, false) => (),
+        }
+        false
+    }
+
+    fn nudge(&mut self, config: &mut Config, direction: i32) {
+        let (param, _, step) = SETTINGS[self.selected];
+        let (min, max) = param.range().unwrap_or((0, 0));
+        let value = param.get(config).unwrap_or(0) + direction * step;
+        param.set(config, value.clamp(min, max));
+        // Straight to the new value
+        self.tick = HEADER_FRAMES;
+    }
+
+    // The image of this frame
+    pub fn image(&self, config: &Config) -> &'static BitImage {
+        let (param, header, unit) = SETTINGS[self.selected];
+        let value = param.get(config).unwrap_or(0) / unit;
+        let mut tick = self.tick % cycle_frames(value);
+        if tick < HEADER_FRAMES {
+            return header;
+        }
+        tick -= HEADER_FRAMES;
+        if value < 0 {
+            if tick < DIGIT_FRAMES + GAP_FRAMES {
+                return if tick < DIGIT_FRAMES { &MINUS } else { &BLANK };
+            }
+            tick -= DIGIT_FRAMES + GAP_FRAMES;
+        }
+        // Blank for the pause
+        PACING
+            .image(&BLANK, value.unsigned_abs(), tick)
+            .unwrap_or(&BLANK)
+    }
+}
+
+// The header, the sign and the digits, then a pause
+fn cycle_frames(value: i32) -> u32 {
+    let signs = (value < 0) as u32;
+    HEADER_FRAMES
+        + (signs + digits(value.unsigned_abs())) * (DIGIT_FRAMES + GAP_FRAMES)
+        + PAUSE_FRAMES
+}
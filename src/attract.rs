@@ -0,0 +1,55 @@
+// Attract mode for exhibitions: after a minute stopped without any input the
+// car takes turns between scenes on the display and the LEDs to show that
+// it's alive. The wheels don't move, and any input ends it right away as it
+// restarts the idle time in main.
+
+use microbit::display::nonblocking::BitImage;
+
+use crate::fixed::Fix;
+use crate::images::{ARROW_DOWN, ARROW_LEFT, ARROW_RIGHT, ARROW_UP, BLANK, SMILE};
+use crate::leds::{Color, COUNT};
+use crate::lights::{self, Rainbow};
+
+pub const IDLE_MS: u32 = 60_000;
+const SCENE_MS: u32 = 5000;
+const SCENES: u32 = 2;
+const SPIN_MS: u32 = 200;
+const SPIN: [&BitImage; 4] = [&ARROW_UP, &ARROW_RIGHT, &ARROW_DOWN, &ARROW_LEFT];
+const WINK_MS: u32 = 500;
+
+#[derive(Clone, Copy)]
+enum Scene {
+    // Arrows turning round with a rainbow chase
+    Spin,
+    // A blinking smile over breathing LEDs
+    Smile,
+}
+
+// With the time into attract mode, idle_ms is the time since the last input
+fn scene(idle_ms: u32) -> (Scene, u32) {
+    let ms = idle_ms - IDLE_MS;
+    match ms / SCENE_MS % SCENES {
+        0 => (Scene::Spin, ms),
+        _ => (Scene::Smile, ms),
+    }
+}
+
+pub fn active(idle_ms: u32) -> bool {
+    idle_ms >= IDLE_MS
+}
+
+pub fn image(idle_ms: u32) -> &'static BitImage {
+    match scene(idle_ms) {
+        (Scene::Spin, ms) => SPIN[(ms / SPIN_MS) as usize % SPIN.len()],
+        (Scene::Smile, ms) if (ms / WINK_MS).is_multiple_of(2) => &SMILE,
+        (Scene::Smile, _) => &BLANK,
+    }
+}
+
+// Every frame, the rainbow chase and the breathing step with the frames
+pub fn colors(idle_ms: u32, frame: u32, rainbow: &mut Rainbow) -> [Color; COUNT] {
+    match scene(idle_ms) {
+        (Scene::Spin, _) => rainbow.update(Fix::ONE),
+        (Scene::Smile, _) => lights::idle(frame),
+    }
+}
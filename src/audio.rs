@@ -0,0 +1,140 @@
+// Square wave tones on the V2 speaker or a piezo buzzer add-on (`buzzer`).
+// TIMER2 toggles the pin through GPIOTE channel 2 and PPI channel 4, so a tone
+// plays on without any CPU time and line following isn't interrupted.
+//
+// Sounds play on one voice per priority. There is only one tone generator, so
+// the highest priority voice that has a sound is heard, while lower voices
+// pause and continue where they were once it is done.
+
+use microbit::hal::gpio::{Disconnected, Level, Pin};
+use microbit::hal::pac::{self, TIMER2};
+use microbit::hal::ppi::{ConfigurablePpi, Ppi, Ppi4};
+
+// 16 MHz / 2^4
+const TIMER_HZ: u32 = 1_000_000;
+const CHANNEL: usize = 2;
+// GPIOTE CONFIG: task mode, toggle polarity, initially low
+const TASK_MODE: u32 = 3;
+const TOGGLE: u32 = 3 << 16;
+
+// Frequency in Hz (0 is a rest) and duration in frames
+#[derive(Clone, Copy)]
+pub struct Tone {
+    pub hz: u16,
+    pub frames: u8,
+}
+
+const fn tone(hz: u16, frames: u8) -> Tone {
+    Tone { hz, frames }
+}
+
+// Double honk
+pub const HORN: &[Tone] = &[tone(392, 8), tone(0, 3), tone(392, 15)];
+// Short chirps when the car starts and stops
+pub const START: &[Tone] = &[tone(1047, 3), tone(1568, 3)];
+pub const STOP: &[Tone] = &[tone(1568, 3), tone(1047, 3)];
+
+// Lowest priority first
+#[derive(Clone, Copy)]
+pub enum Voice {
+    Signal,
+    Horn,
+}
+
+const VOICES: usize = 2;
+
+#[derive(Clone, Copy)]
+struct Playing {
+    sound: &'static [Tone],
+    index: usize,
+    frames: u8,
+}
+
+impl Playing {
+    // Frequency for this frame, None once the sound is over
+    fn next(&mut self) -> Option<u16> {
+        let tone = self.sound.get(self.index)?;
+        self.frames += 1;
+        if self.frames >= tone.frames {
+            self.index += 1;
+            self.frames = 0;
+        }
+        Some(tone.hz)
+    }
+}
+
+pub struct Audio {
+    timer: TIMER2,
+    psel: u32,
+    voices: [Option<Playing>; VOICES],
+    hz: u16,
+}
+
+impl Audio {
+    pub fn new(timer: TIMER2, mut ppi: Ppi4, pin: Pin<Disconnected>) -> Self {
+        // The pin is low whenever GPIOTE releases it
+        let pin = pin.into_push_pull_output(Level::Low);
+        // Safe as main only uses GPIOTE channels 0 and 1
+        let gpiote = unsafe { &*pac::GPIOTE::ptr() };
+        ppi.set_task_endpoint(&gpiote.tasks_out[CHANNEL]);
+        ppi.set_event_endpoint(&timer.events_compare[0]);
+        ppi.enable();
+        timer.mode.write(|w| unsafe { w.bits(0) });
+        // 16 bit is enough down to 8 Hz
+        timer.bitmode.write(|w| unsafe { w.bits(0) });
+        timer.prescaler.write(|w| unsafe { w.bits(4) });
+        // Clear on CC[0]
+        timer.shorts.write(|w| unsafe { w.bits(1) });
+        Audio {
+            timer,
+            psel: pin.psel_bits(),
+            voices: [None; VOICES],
+            hz: 0,
+        }
+    }
+
+    // Starts the sound from the beginning, replacing what the voice played
+    pub fn play(&mut self, voice: Voice, sound: &'static [Tone]) {
+        self.voices[voice as usize] = Some(Playing {
+            sound,
+            index: 0,
+            frames: 0,
+        });
+    }
+
+    // Called once per frame
+    pub fn update(&mut self) {
+        let mut hz = 0;
+        for slot in self.voices.iter_mut().rev() {
+            if let Some(playing) = slot {
+                match playing.next() {
+                    Some(tone_hz) => {
+                        hz = tone_hz;
+                        break;
+                    }
+                    None => *slot = None,
+                }
+            }
+        }
+        self.output(hz);
+    }
+
+    fn output(&mut self, hz: u16) {
+        if hz == self.hz {
+            return;
+        }
+        self.hz = hz;
+        let gpiote = unsafe { &*pac::GPIOTE::ptr() };
+        self.timer.tasks_stop.write(|w| unsafe { w.bits(1) });
+        self.timer.tasks_clear.write(|w| unsafe { w.bits(1) });
+        if hz == 0 {
+            gpiote.config[CHANNEL].write(|w| unsafe { w.bits(0) });
+            return;
+        }
+        // Two toggles per period
+        let half_period = TIMER_HZ / 2 / hz as u32;
+        self.timer.cc[0].write(|w| unsafe { w.bits(half_period) });
+        gpiote.config[CHANNEL].write(|w| unsafe { w.bits(TASK_MODE | self.psel << 8 | TOGGLE) });
+        self.timer.tasks_start.write(|w| unsafe { w.bits(1) });
+    }
+}
@@ -0,0 +1,37 @@
+// Supply voltage measurement on the photocell ADC.
+// The photocell is read relative to VDD, so the reference is switched to the
+// internal bandgap for this one conversion and restored afterwards. The HAL
+// has no API for that, the reference is changed in the PAC directly.
+
+use microbit::adc::Adc;
+use microbit::hal::pac;
+
+#[cfg(feature = "v1")]
+pub fn read_mv(adc: &mut Adc) -> u16 {
+    use microbit::hal::adc::InternalVddOneThird;
+
+    // Safe while we hold the only Adc handle
+    let regs = unsafe { &*pac::ADC::ptr() };
+    let refsel = regs.config.read().refsel().variant();
+    regs.config.modify(|_, w| w.refsel().vbg());
+    let raw = adc.read_channel(&InternalVddOneThird);
+    regs.config.modify(|_, w| w.refsel().variant(refsel));
+    // VDD / 3 against 1.2 V, 10 bit
+    (raw as u32 * 3 * 1200 / 1024) as u16
+}
+
+#[cfg(feature = "v2")]
+pub fn read_mv(adc: &mut Adc) -> u16 {
+    use microbit::hal::saadc::InternalVdd;
+
+    // Safe while we hold the only Adc handle
+    let regs = unsafe { &*pac::SAADC::ptr() };
+    let config = regs.ch[0].config.read().bits();
+    regs.ch[0]
+        .config
+        .modify(|_, w| w.refsel().internal().gain().gain1_6());
+    let raw = adc.read_channel(&mut InternalVdd).unwrap_or(0);
+    regs.ch[0].config.write(|w| unsafe { w.bits(config) });
+    // VDD against 0.6 V with gain 1/6, 10 bit
+    (raw.max(0) as u32 * 3600 / 1024) as u16
+}
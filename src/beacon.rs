@@ -0,0 +1,84 @@
+// BLE advertising beacon with the car state, so any phone with a BLE scanner
+// app or a Web Bluetooth page can watch the car without pairing. The
+// advertisement carries the local name "ringbit" and the telemetry record as
+// manufacturer specific data (test company id 0xFFFF). It is a one way
+// broadcast, the connectable service with characteristics is in gatt.rs.
+//
+// The record is little endian with naturally aligned fields, so a browser can
+// read it with a DataView straight from manufacturerData.get(0xffff), see
+// tools/dashboard.html:
+//
+// offset 0  u8   version (2)
+//        1  u8   sequence, increments with every advertisement
+//        2  u8   state: 0 stopped, 1 forward, 2 left, 3 right, 4 back
+//        3  u8   flags: bit 0 on, bit 1 remote control
+//        4  i16  photocell reading
+//        6  u16  supply voltage in mV
+
+use microbit::hal::pac::FICR;
+
+const VERSION: u8 = 2;
+const NAME: &[u8] = b"ringbit";
+const RECORD_LEN: usize = 8;
+const PAYLOAD_LEN: usize = 2 + NAME.len() + 4 + RECORD_LEN;
+
+pub struct Telemetry {
+    pub state: u8,
+    pub is_on: bool,
+    pub remote: bool,
+    pub photo_cell: i16,
+    pub battery_mv: u16,
+}
+
+impl Telemetry {
+    fn encode(&self, sequence: u8) -> [u8; RECORD_LEN] {
+        let photo_cell = self.photo_cell.to_le_bytes();
+        let battery = self.battery_mv.to_le_bytes();
+        [
+            VERSION,
+            sequence,
+            self.state,
+            self.is_on as u8 | (self.remote as u8) << 1,
+            photo_cell[0],
+            photo_cell[1],
+            battery[0],
+            battery[1],
+        ]
+    }
+}
+
+pub struct Beacon {
+    address: [u8; 6],
+    sequence: u8,
+}
+
+impl Beacon {
+    // Random static address from the factory programmed device address
+    pub fn new(ficr: &FICR) -> Self {
+        let low = ficr.deviceaddr[0].read().bits().to_le_bytes();
+        let high = ficr.deviceaddr[1].read().bits().to_le_bytes();
+        Beacon {
+            address: [low[0], low[1], low[2], low[3], high[0], high[1] | 0xc0],
+            sequence: 0,
+        }
+    }
+
+    pub fn address(&self) -> [u8; 6] {
+        self.address
+    }
+
+    // AD structures for the next advertisement
+    pub fn payload(&mut self, telemetry: &Telemetry) -> [u8; PAYLOAD_LEN] {
+        let mut data = [0; PAYLOAD_LEN];
+        data[0] = 1 + NAME.len() as u8;
+        data[1] = 0x09; // Complete local name
+        data[2..2 + NAME.len()].copy_from_slice(NAME);
+        let md = 2 + NAME.len();
+        data[md] = 3 + RECORD_LEN as u8;
+        data[md + 1] = 0xff; // Manufacturer specific data
+        data[md + 2..md + 4].copy_from_slice(&0xffffu16.to_le_bytes());
+        data[md + 4..].copy_from_slice(&telemetry.encode(self.sequence));
+        self.sequence = self.sequence.wrapping_add(1);
+        data
+    }
+}
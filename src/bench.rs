@@ -0,0 +1,60 @@
+// Controller cost benchmark. Every controller variant runs over the same
+// synthetic photocell sweep and the cost is reported in core clock cycles.
+// SysTick is used as the cycle counter because the V1 Cortex-M0 has no DWT.
+
+use core::hint::black_box;
+
+use cortex_m::peripheral::{syst::SystClkSource, SYST};
+
+use crate::classify;
+use crate::config::Config;
+use crate::control::{LowPass, Num, Pid};
+use crate::fixed::Fix;
+
+const SYST_MASK: u32 = 0x00ff_ffff;
+// Covers the full 10 bit ADC range
+const SWEEP: i32 = 1024;
+
+pub fn run(syst: &mut SYST) {
+    syst.set_clock_source(SystClkSource::Core);
+    syst.set_reload(SYST_MASK);
+    syst.clear_current();
+    syst.enable_counter();
+
+    let cycles = measure(|| {
+        for reading in 0..SWEEP {
+            black_box(classify(
+                black_box(reading as i16),
+                &Config::DEFAULT.thresholds,
+            ));
+        }
+    });
+    defmt::info!("bang-bang: {} cycles/iteration", cycles / SWEEP as u32);
+
+    let cycles = measure(|| sweep_pid(Fix::from_f32(1.2), Fix::from_f32(0.01), Fix::from_f32(2.0)));
+    defmt::info!(
+        "PID fixed-point: {} cycles/iteration",
+        cycles / SWEEP as u32
+    );
+
+    let cycles = measure(|| sweep_pid(1.2f32, 0.01, 2.0));
+    defmt::info!("PID float: {} cycles/iteration", cycles / SWEEP as u32);
+
+    syst.disable_counter();
+}
+
+fn measure(f: impl FnOnce()) -> u32 {
+    let start = SYST::get_current();
+    f();
+    // SysTick counts down
+    start.wrapping_sub(SYST::get_current()) & SYST_MASK
+}
+
+fn sweep_pid<T: Num>(kp: T, ki: T, kd: T) {
+    let mut pid = Pid::new(kp, ki, kd);
+    let mut filter = LowPass::new(T::ONE);
+    for reading in 0..SWEEP {
+        let error = T::from_i32(black_box(reading - SWEEP / 2));
+        black_box(pid.update(filter.update(error)).to_fix());
+    }
+}
@@ -0,0 +1,914 @@
+// Bare-metal BLE peripheral (feature `gatt`) for the GATT server in gatt.rs.
+// The link layer runs on RADIO, RTC0 and PPI channel 6 in their interrupts,
+// main only hands values over and takes requests, see Ble.
+//
+// Without a connection the car sends a connectable ADV_IND with the name
+// "ringbit" and the service UUID on the three advertising channels every
+// 100 ms, and listens for a CONNECT_IND after each. There are no scan
+// responses, the advertisement already carries everything.
+//
+// In a connection every event is one exchange: the central's packet and one
+// answer T_IFS after it, MD stays clear. The RTC0 compare for the event
+// starts the receiver through PPI, so a late interrupt can't miss the
+// anchor point, and the radio turns around to transmit on its own (DISABLED
+// to TXEN with TIFS 150 us). The ADDRESS interrupt points the radio at the
+// pending PDU and the END one only swaps in the next PDU when the central
+// acknowledged, so if it runs late the central just sees a retransmission.
+// LL control procedures: connection and channel map updates, feature,
+// version, ping and length exchange; encryption is rejected and everything
+// else answered unknown. ATT requests are answered from the interrupt, one
+// connection event after they came in. L2CAP messages have to fit one PDU,
+// which they do at the default MTU. The central's signalling requests are
+// rejected and SMP pairing isn't supported.
+//
+// Link times are in 1/256 RTC ticks, so a u32 wraps together with the 24 bit
+// counter. RTC0 runs from the same LFCLK as the monotonic clock, synthesized
+// from the crystal, which is well within the 50 ppm assumed for the window
+// widening.
+
+use core::cell::RefCell;
+use core::fmt;
+
+use cortex_m::interrupt::{CriticalSection, Mutex};
+use microbit::hal::{
+    pac::{FICR, RADIO, RTC0},
+    ppi::{ConfigurablePpi, Ppi, Ppi6},
+};
+
+use crate::beacon::Beacon;
+use crate::console::{Reply, Request};
+use crate::gatt::{self, Server, Values, MTU, NAME};
+use crate::params::Param;
+
+static LINK: Mutex<RefCell<Option<Link>>> = Mutex::new(RefCell::new(None));
+
+// Radio shorts and interrupts
+const READY_START: u32 = 1 << 0;
+const END_DISABLE: u32 = 1 << 1;
+const DISABLED_TXEN: u32 = 1 << 2;
+const DISABLED_RXEN: u32 = 1 << 3;
+const ADDRESS: u32 = 1 << 1;
+const DISABLED: u32 = 1 << 4;
+// Radio states
+const STATE_DISABLED: u32 = 0;
+const STATE_RX: u32 = 3;
+const STATE_TX_IDLE: u32 = 10;
+// RTC0 compares: 0 starts the receiver, 1 ends the listening, 2 the next
+// advertising event
+const COMPARE0: u32 = 1 << 16;
+const COMPARE1: u32 = 1 << 17;
+const COMPARE2: u32 = 1 << 18;
+
+const ADVERTISING_ADDRESS: u32 = 0x8e89_bed6;
+const ADVERTISING_CRC: u32 = 0x0055_5555;
+const ADV_IND: u8 = 0x00;
+const CONNECT_IND: u8 = 0x05;
+// Header flag of a random advertiser address
+const TX_ADD: u8 = 1 << 6;
+const RX_ADD: u8 = 1 << 7;
+const CONNECT_LEN: usize = 34;
+
+// Data PDU header
+const LLID_CONTINUATION: u8 = 1;
+const LLID_START: u8 = 2;
+const LLID_CONTROL: u8 = 3;
+const NESN: u8 = 1 << 2;
+const SN: u8 = 1 << 3;
+const PAYLOAD_LEN: usize = 27;
+// Header and payload, a PDU on air or in the queue
+const PDU_LEN: usize = 2 + PAYLOAD_LEN;
+const EMPTY: [u8; PDU_LEN] = {
+    let mut pdu = [0; PDU_LEN];
+    pdu[0] = LLID_CONTINUATION;
+    pdu
+};
+// Received PDUs, an advertising one is up to 37 bytes
+const RX_LEN: usize = 2 + 37;
+const QUEUE_LEN: usize = 4;
+
+// LL control opcodes
+const CONNECTION_UPDATE_IND: u8 = 0x00;
+const CHANNEL_MAP_IND: u8 = 0x01;
+const TERMINATE_IND: u8 = 0x02;
+const ENC_REQ: u8 = 0x03;
+const UNKNOWN_RSP: u8 = 0x07;
+const FEATURE_REQ: u8 = 0x08;
+const FEATURE_RSP: u8 = 0x09;
+const VERSION_IND: u8 = 0x0c;
+const REJECT_IND: u8 = 0x0d;
+const REJECT_EXT_IND: u8 = 0x11;
+const PING_REQ: u8 = 0x12;
+const PING_RSP: u8 = 0x13;
+const LENGTH_REQ: u8 = 0x14;
+const LENGTH_RSP: u8 = 0x15;
+// Bluetooth 4.0, no company
+const VERSION: [u8; 6] = [VERSION_IND, 6, 0xff, 0xff, 0, 0];
+const UNSUPPORTED_REMOTE_FEATURE: u8 = 0x1a;
+
+// L2CAP channels
+const ATT: u16 = 4;
+const SIGNALLING: u16 = 5;
+const SMP: u16 = 6;
+const COMMAND_REJECT: u8 = 0x01;
+const PARAMETER_UPDATE_RSP: u8 = 0x13;
+const PAIRING_REQUEST: u8 = 0x01;
+const PAIRING_FAILED: u8 = 0x05;
+const PAIRING_NOT_SUPPORTED: u8 = 0x05;
+
+const ADV_INTERVAL_US: u32 = 100_000;
+// Random extra delay per advertising event, up to 10 ms
+const ADV_DELAY_US: u32 = 10_000;
+// After an ADV_IND until the address of a CONNECT_IND must have come:
+// T_IFS, the ramp up and the address
+const ADV_LISTEN_US: u32 = 400;
+// Preamble, access address, header, payload and CRC
+const CONNECT_IND_US: u32 = 8 * (1 + 4 + 2 + CONNECT_LEN as u32 + 3);
+// Receiver ramp up, 130 us on both chips
+const RAMP_US: u32 = 140;
+// From the start of a packet to its ADDRESS event, preamble and address
+const ADDRESS_US: u32 = 40;
+const UNIT_US: u32 = 1250;
+const OWN_PPM: u32 = 50;
+// Worst case clock accuracy of the central per SCA field value
+const SCA_PPM: [u32; 8] = [500, 250, 150, 100, 75, 50, 30, 20];
+// The connection is lost if none of the first events is heard
+const FIRST_EVENTS: u16 = 6;
+
+const fn fine(us: u32) -> u32 {
+    (us as u64 * 32_768 * 256 / 1_000_000) as u32
+}
+
+const TICK: u32 = 256;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    // Between advertising events
+    Idle,
+    // ADV_IND on channel 37 + index going out
+    AdvTx(u8),
+    // Waiting for a CONNECT_IND after it
+    AdvRx(u8),
+    // Connection event scheduled, the receiver listens from compare 0
+    Listen,
+    // The central's packet coming in
+    Rx,
+    // The answer going out
+    Tx,
+}
+
+// Parameters of a connection update, applied at the instant
+#[derive(Clone, Copy)]
+struct Update {
+    win_size: u8,
+    win_offset: u16,
+    interval: u16,
+    timeout: u16,
+    instant: u16,
+}
+
+struct Connection {
+    access_address: u32,
+    crc_init: u32,
+    interval: u32,
+    timeout: u32,
+    sca_ppm: u32,
+    map: [u8; 5],
+    hop: u8,
+    unmapped: u8,
+    channel: u8,
+    counter: u16,
+    // Expected anchor point of the current event
+    anchor: u32,
+    // Since the last anchor point heard, for the window widening
+    since_anchor: u32,
+    // Since the last packet with a good CRC, for the supervision timeout
+    since_heard: u32,
+    // Transmit window past the anchor point after a connect or an update
+    window: u32,
+    established: bool,
+    sn: bool,
+    nesn: bool,
+    // tx[pending] goes out until it is acknowledged, the other one is next
+    tx: [[u8; PDU_LEN]; 2],
+    pending: usize,
+    // The other buffer holds the acknowledged PDU until it is refilled
+    acknowledged: bool,
+    update: Option<Update>,
+    new_map: Option<([u8; 5], u16)>,
+    version_sent: bool,
+    terminated: bool,
+}
+
+impl Connection {
+    fn used(&self, channel: u8) -> bool {
+        self.map[channel as usize / 8] & 1 << (channel % 8) != 0
+    }
+
+    // Channel selection algorithm #1
+    fn select_channel(&mut self) {
+        self.unmapped = (self.unmapped + self.hop) % 37;
+        self.channel = if self.used(self.unmapped) {
+            self.unmapped
+        } else {
+            let count = (0..37).filter(|&channel| self.used(channel)).count() as u8;
+            let index = self.unmapped % count.max(1);
+            (0..37)
+                .filter(|&channel| self.used(channel))
+                .nth(index as usize)
+                .unwrap_or(0)
+        };
+    }
+
+    fn widening(&self) -> u32 {
+        let drift = self.since_anchor as u64 * (self.sca_ppm + OWN_PPM) as u64 / 1_000_000;
+        // 16 us for the central's jitter and two ticks for our timestamps
+        drift as u32 + fine(16) + 2 * TICK
+    }
+}
+
+// PDUs waiting for a connection event
+struct Queue {
+    pdus: [[u8; PDU_LEN]; QUEUE_LEN],
+    head: usize,
+    len: usize,
+}
+
+impl Queue {
+    const fn new() -> Self {
+        Queue {
+            pdus: [[0; PDU_LEN]; QUEUE_LEN],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    // Dropped when full
+    fn push(&mut self, llid: u8, parts: &[&[u8]]) {
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+        if self.len == QUEUE_LEN || len > PAYLOAD_LEN {
+            return;
+        }
+        let pdu = &mut self.pdus[(self.head + self.len) % QUEUE_LEN];
+        pdu[0] = llid;
+        pdu[1] = len as u8;
+        let mut at = 2;
+        for part in parts {
+            pdu[at..at + part.len()].copy_from_slice(part);
+            at += part.len();
+        }
+        self.len += 1;
+    }
+
+    fn free(&self) -> usize {
+        QUEUE_LEN - self.len
+    }
+
+    fn pop(&mut self) -> Option<[u8; PDU_LEN]> {
+        if self.len == 0 {
+            return None;
+        }
+        let pdu = self.pdus[self.head];
+        self.head = (self.head + 1) % QUEUE_LEN;
+        self.len -= 1;
+        Some(pdu)
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+struct Link {
+    radio: RADIO,
+    rtc: RTC0,
+    address: [u8; 6],
+    phase: Phase,
+    adv: [u8; RX_LEN],
+    rx: [u8; RX_LEN],
+    // ADDRESS event of the packet received while advertising
+    heard_at: Option<u32>,
+    seed: u32,
+    connection: Option<Connection>,
+    queue: Queue,
+    server: Server,
+}
+
+impl Link {
+    fn now(&self) -> u32 {
+        self.rtc.counter.read().bits() * TICK
+    }
+
+    fn set_compare(&self, index: usize, at: u32) {
+        self.rtc.events_compare[index].write(|w| unsafe { w.bits(0) });
+        self.rtc.cc[index].write(|w| unsafe { w.bits(at / TICK) });
+    }
+
+    fn configure(&self, access_address: u32, crc_init: u32) {
+        // Ble_1Mbit, 0 dBm
+        self.radio.mode.write(|w| unsafe { w.bits(3) });
+        self.radio.txpower.write(|w| unsafe { w.bits(0) });
+        // 1 byte S0 (PDU header), 8 bit length
+        self.radio.pcnf0.write(|w| unsafe { w.bits(1 << 8 | 8) });
+        // 3 byte base address, little endian, whitening on
+        self.radio
+            .pcnf1
+            .write(|w| unsafe { w.bits(1 << 25 | 3 << 16 | (RX_LEN - 2) as u32) });
+        self.radio
+            .base0
+            .write(|w| unsafe { w.bits(access_address << 8) });
+        self.radio
+            .prefix0
+            .write(|w| unsafe { w.bits(access_address >> 24) });
+        self.radio.txaddress.write(|w| unsafe { w.bits(0) });
+        self.radio.rxaddresses.write(|w| unsafe { w.bits(1) });
+        // 24 bit CRC, not including the address
+        self.radio.crccnf.write(|w| unsafe { w.bits(1 << 8 | 3) });
+        self.radio.crcpoly.write(|w| unsafe { w.bits(0x0000_065b) });
+        self.radio.crcinit.write(|w| unsafe { w.bits(crc_init) });
+        self.radio.tifs.write(|w| unsafe { w.bits(150) });
+    }
+
+    fn tune(&self, channel: u8) {
+        let frequency = match channel {
+            37 => 2,
+            38 => 26,
+            39 => 80,
+            0..=10 => 4 + 2 * channel as u32,
+            _ => 6 + 2 * channel as u32,
+        };
+        self.radio.frequency.write(|w| unsafe { w.bits(frequency) });
+        self.radio
+            .datawhiteiv
+            .write(|w| unsafe { w.bits(channel as u32) });
+    }
+
+    fn point_at(&self, buf: &[u8]) {
+        self.radio
+            .packetptr
+            .write(|w| unsafe { w.bits(buf.as_ptr() as u32) });
+    }
+
+    fn set_shorts(&self, shorts: u32) {
+        self.radio.shorts.write(|w| unsafe { w.bits(shorts) });
+    }
+
+    // Stops the radio wherever it is
+    fn abort(&self) {
+        self.set_shorts(0);
+        if self.radio.state.read().bits() != STATE_DISABLED {
+            self.radio.events_disabled.write(|w| unsafe { w.bits(0) });
+            self.radio.tasks_disable.write(|w| unsafe { w.bits(1) });
+            while self.radio.events_disabled.read().bits() == 0 {}
+        }
+        self.radio.events_disabled.write(|w| unsafe { w.bits(0) });
+        self.radio.events_address.write(|w| unsafe { w.bits(0) });
+    }
+
+    fn start_advertising(&mut self) {
+        self.rtc.evtenclr.write(|w| unsafe { w.bits(COMPARE0) });
+        self.connection = None;
+        self.queue.clear();
+        self.server.reset();
+        self.configure(ADVERTISING_ADDRESS, ADVERTISING_CRC);
+        self.pause();
+    }
+
+    fn pause(&mut self) {
+        self.phase = Phase::Idle;
+        self.seed = self
+            .seed
+            .wrapping_mul(1_664_525)
+            .wrapping_add(1_013_904_223);
+        let delay_us = (self.seed >> 16) % ADV_DELAY_US;
+        self.set_compare(2, self.now().wrapping_add(fine(ADV_INTERVAL_US + delay_us)));
+    }
+
+    fn advertise(&mut self, index: u8) {
+        self.tune(37 + index);
+        self.point_at(&self.adv);
+        self.set_shorts(READY_START | END_DISABLE | DISABLED_RXEN);
+        self.radio.events_disabled.write(|w| unsafe { w.bits(0) });
+        self.radio.tasks_txen.write(|w| unsafe { w.bits(1) });
+        self.phase = Phase::AdvTx(index);
+    }
+
+    fn next_channel(&mut self, index: u8) {
+        if index < 3 {
+            self.advertise(index);
+        } else {
+            self.pause();
+        }
+    }
+
+    fn radio_event(&mut self) {
+        if self.radio.events_address.read().bits() != 0 {
+            self.radio.events_address.write(|w| unsafe { w.bits(0) });
+            let at = self.now().wrapping_sub(fine(ADDRESS_US));
+            match self.phase {
+                Phase::AdvRx(_) => self.heard_at = Some(at),
+                Phase::Listen => {
+                    // The default answer is the pending PDU as it is
+                    if let Some(connection) = self.connection.as_mut() {
+                        self.radio.packetptr.write(|w| unsafe {
+                            w.bits(connection.tx[connection.pending].as_ptr() as u32)
+                        });
+                        // Only a timely interrupt gives an anchor point
+                        if self.radio.events_disabled.read().bits() == 0 {
+                            connection.anchor = at;
+                            connection.since_anchor = 0;
+                        }
+                    }
+                    self.phase = Phase::Rx;
+                    // So late that the answer already went out with the
+                    // received packet in it: cut it off, the central
+                    // repeats its packet in the next event
+                    if self.radio.state.read().bits() >= STATE_TX_IDLE {
+                        self.abort();
+                        self.end_event();
+                        return;
+                    }
+                }
+                _ => (),
+            }
+        }
+        if self.radio.events_disabled.read().bits() != 0 {
+            self.radio.events_disabled.write(|w| unsafe { w.bits(0) });
+            match self.phase {
+                // The receiver already started into the advertisement,
+                // give up on this channel before anything comes in
+                Phase::AdvTx(index) if self.radio.state.read().bits() == STATE_RX => {
+                    self.abort();
+                    self.next_channel(index + 1);
+                }
+                Phase::AdvTx(index) => {
+                    self.point_at(&self.rx);
+                    self.set_shorts(READY_START | END_DISABLE);
+                    self.heard_at = None;
+                    self.phase = Phase::AdvRx(index);
+                    let until = self.now().wrapping_add(fine(ADV_LISTEN_US));
+                    self.set_compare(1, until);
+                }
+                Phase::AdvRx(index) => {
+                    if !self.connect() {
+                        self.next_channel(index + 1);
+                    }
+                }
+                Phase::Rx => {
+                    // The answer went out already if the interrupt is late
+                    let sent = self.radio.state.read().bits() == STATE_DISABLED;
+                    // Only the answer follows, not another receive
+                    self.set_shorts(READY_START | END_DISABLE);
+                    self.phase = Phase::Tx;
+                    self.receive();
+                    if sent {
+                        self.end_event();
+                    }
+                }
+                Phase::Tx => self.end_event(),
+                Phase::Idle | Phase::Listen => (),
+            }
+        }
+    }
+
+    fn rtc_event(&mut self) {
+        if self.rtc.events_compare[1].read().bits() != 0 {
+            self.rtc.events_compare[1].write(|w| unsafe { w.bits(0) });
+            match self.phase {
+                Phase::AdvRx(index) if self.heard_at.is_none() => {
+                    self.abort();
+                    self.next_channel(index + 1);
+                }
+                Phase::Listen => {
+                    self.abort();
+                    self.end_event();
+                }
+                _ => (),
+            }
+        }
+        if self.rtc.events_compare[2].read().bits() != 0 {
+            self.rtc.events_compare[2].write(|w| unsafe { w.bits(0) });
+            if self.phase == Phase::Idle {
+                self.advertise(0);
+            }
+        }
+    }
+
+    // A CONNECT_IND to us starts the connection
+    fn connect(&mut self) -> bool {
+        let Some(heard_at) = self.heard_at else {
+            return false;
+        };
+        let rx = self.rx;
+        let header = rx[0];
+        let pdu = &rx[2..2 + CONNECT_LEN];
+        let to_us = self.radio.crcstatus.read().bits() & 1 == 1
+            && header & 0x0f == CONNECT_IND
+            && header & RX_ADD != 0
+            && rx[1] as usize == CONNECT_LEN
+            && pdu[6..12] == self.address;
+        if !to_us {
+            return false;
+        }
+        let le16 = |at: usize| u16::from_le_bytes([pdu[at], pdu[at + 1]]);
+        let interval = le16(22);
+        let timeout = le16(26);
+        let map = [pdu[28], pdu[29], pdu[30], pdu[31], pdu[32]];
+        let hop = pdu[33] & 0x1f;
+        let channels: u32 = map.iter().map(|byte| byte.count_ones()).sum();
+        if !(6..=3200).contains(&interval) || !(5..=16).contains(&hop) || channels < 2 {
+            return false;
+        }
+        let interval_fine = fine(interval as u32 * UNIT_US);
+        // The transmit window opens 1.25 ms plus the offset after the
+        // CONNECT_IND, and is handled like an update at event 0
+        let end = heard_at.wrapping_add(fine(CONNECT_IND_US));
+        let before_window = fine(UNIT_US + le16(20) as u32 * UNIT_US);
+        self.connection = Some(Connection {
+            access_address: u32::from_le_bytes([pdu[12], pdu[13], pdu[14], pdu[15]]),
+            crc_init: u32::from_le_bytes([pdu[16], pdu[17], pdu[18], 0]),
+            interval: interval_fine,
+            timeout: fine(timeout as u32 * 10_000),
+            sca_ppm: SCA_PPM[pdu[33] as usize >> 5],
+            map,
+            hop,
+            unmapped: 0,
+            channel: 0,
+            counter: u16::MAX,
+            anchor: end.wrapping_add(before_window).wrapping_sub(interval_fine),
+            since_anchor: before_window.wrapping_sub(interval_fine),
+            since_heard: 0,
+            window: 0,
+            established: false,
+            sn: false,
+            nesn: false,
+            tx: [EMPTY; 2],
+            pending: 0,
+            acknowledged: false,
+            update: Some(Update {
+                win_size: pdu[19],
+                win_offset: 0,
+                interval,
+                timeout,
+                instant: 0,
+            }),
+            new_map: None,
+            version_sent: false,
+            terminated: false,
+        });
+        self.queue.clear();
+        self.server.reset();
+        let connection = self.connection.as_ref().unwrap();
+        self.configure(connection.access_address, connection.crc_init);
+        self.rtc.evtenset.write(|w| unsafe { w.bits(COMPARE0) });
+        self.next_event();
+        true
+    }
+
+    // The central's packet is in, picks the answer while the radio ramps up
+    fn receive(&mut self) {
+        let Some(connection) = self.connection.as_mut() else {
+            return;
+        };
+        // The answer goes out even after a CRC error, only without
+        // acknowledging anything
+        let crc_ok = self.radio.crcstatus.read().bits() & 1 == 1;
+        let header = self.rx[0];
+        let new_data = crc_ok && (header & SN != 0) == connection.nesn;
+        if crc_ok {
+            if (header & NESN != 0) != connection.sn {
+                connection.sn = !connection.sn;
+                connection.pending ^= 1;
+                connection.acknowledged = true;
+            }
+            connection.since_heard = 0;
+            connection.established = true;
+        }
+        if new_data {
+            connection.nesn = !connection.nesn;
+        }
+        let pdu = &mut connection.tx[connection.pending];
+        pdu[0] = (pdu[0] & 0x03) | (connection.nesn as u8) << 2 | (connection.sn as u8) << 3;
+        self.radio
+            .packetptr
+            .write(|w| unsafe { w.bits(pdu.as_ptr() as u32) });
+        if new_data {
+            let packet = self.rx;
+            let len = (packet[1] as usize).min(PAYLOAD_LEN);
+            let payload = &packet[2..2 + len];
+            match header & 0x03 {
+                LLID_CONTROL => self.control(payload),
+                LLID_START => self.l2cap(payload),
+                _ => (),
+            }
+        }
+    }
+
+    fn control(&mut self, payload: &[u8]) {
+        let Some(connection) = self.connection.as_mut() else {
+            return;
+        };
+        let Some((&opcode, data)) = payload.split_first() else {
+            return;
+        };
+        let le16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+        match opcode {
+            CONNECTION_UPDATE_IND if data.len() == 11 => {
+                connection.update = Some(Update {
+                    win_size: data[0],
+                    win_offset: le16(1),
+                    interval: le16(3),
+                    timeout: le16(7),
+                    instant: le16(9),
+                })
+            }
+            CHANNEL_MAP_IND if data.len() == 7 => {
+                let map = [data[0], data[1], data[2], data[3], data[4]];
+                connection.new_map = Some((map, le16(5)));
+            }
+            TERMINATE_IND => connection.terminated = true,
+            ENC_REQ => self
+                .queue
+                .push(LLID_CONTROL, &[&[REJECT_IND, UNSUPPORTED_REMOTE_FEATURE]]),
+            FEATURE_REQ => self.queue.push(LLID_CONTROL, &[&[FEATURE_RSP], &[0; 8]]),
+            VERSION_IND if !connection.version_sent => {
+                connection.version_sent = true;
+                self.queue.push(LLID_CONTROL, &[&VERSION]);
+            }
+            PING_REQ => self.queue.push(LLID_CONTROL, &[&[PING_RSP]]),
+            // No data length extension, the 4.0 sizes and times
+            LENGTH_REQ => self.queue.push(
+                LLID_CONTROL,
+                &[&[LENGTH_RSP, 27, 0, 0x48, 0x01, 27, 0, 0x48, 0x01]],
+            ),
+            VERSION_IND | UNKNOWN_RSP | REJECT_IND | REJECT_EXT_IND => (),
+            _ => self.queue.push(LLID_CONTROL, &[&[UNKNOWN_RSP, opcode]]),
+        }
+    }
+
+    fn l2cap(&mut self, payload: &[u8]) {
+        if payload.len() < 4 {
+            return;
+        }
+        let len = u16::from_le_bytes([payload[0], payload[1]]) as usize;
+        let channel = u16::from_le_bytes([payload[2], payload[3]]);
+        let data = &payload[4..];
+        if len != data.len() {
+            return;
+        }
+        match (channel, data) {
+            (ATT, _) => {
+                let mut response = [0; MTU];
+                if let Some(len) = self.server.handle(data, &mut response) {
+                    self.push_l2cap(ATT, &response[..len]);
+                }
+            }
+            (SIGNALLING, [code, id, ..])
+                if *code != COMMAND_REJECT && *code != PARAMETER_UPDATE_RSP =>
+            {
+                // Command not understood
+                self.push_l2cap(SIGNALLING, &[COMMAND_REJECT, *id, 2, 0, 0, 0]);
+            }
+            (SMP, [PAIRING_REQUEST, ..]) => {
+                self.push_l2cap(SMP, &[PAIRING_FAILED, PAIRING_NOT_SUPPORTED])
+            }
+            _ => (),
+        }
+    }
+
+    fn push_l2cap(&mut self, channel: u16, data: &[u8]) {
+        let len = (data.len() as u16).to_le_bytes();
+        self.queue
+            .push(LLID_START, &[&len, &channel.to_le_bytes(), data]);
+    }
+
+    // After the answer went out or nothing was heard
+    fn end_event(&mut self) {
+        // Notifications leave room for a response in the queue
+        while self.queue.free() >= 2 {
+            let mut notification = [0; MTU];
+            let Some(len) = self.server.notification(&mut notification) else {
+                break;
+            };
+            self.push_l2cap(ATT, &notification[..len]);
+        }
+        let Some(connection) = self.connection.as_mut() else {
+            return;
+        };
+        let next = connection.pending ^ 1;
+        if connection.acknowledged || connection.tx[next][1] == 0 {
+            connection.acknowledged = false;
+            connection.tx[next] = self.queue.pop().unwrap_or(EMPTY);
+        }
+        if connection.terminated {
+            self.start_advertising();
+            return;
+        }
+        self.next_event();
+    }
+
+    // Schedules the next event the receiver can still make, or gives up on
+    // the connection
+    fn next_event(&mut self) {
+        let now = self.now();
+        let Some(connection) = self.connection.as_mut() else {
+            return;
+        };
+        let (start, end) = loop {
+            connection.counter = connection.counter.wrapping_add(1);
+            connection.anchor = connection.anchor.wrapping_add(connection.interval);
+            connection.since_anchor = connection.since_anchor.wrapping_add(connection.interval);
+            connection.since_heard = connection.since_heard.wrapping_add(connection.interval);
+            // An unheard transmit window repeats until the first packet
+            if connection.established {
+                connection.window = 0;
+            }
+            if let Some(update) = connection
+                .update
+                .filter(|update| update.instant == connection.counter)
+            {
+                let offset = fine(update.win_offset as u32 * UNIT_US);
+                connection.anchor = connection.anchor.wrapping_add(offset);
+                connection.since_anchor = connection.since_anchor.wrapping_add(offset);
+                connection.window = fine(update.win_size as u32 * UNIT_US);
+                connection.interval = fine(update.interval as u32 * UNIT_US);
+                connection.timeout = fine(update.timeout as u32 * 10_000);
+                connection.update = None;
+            }
+            if let Some((map, _)) = connection
+                .new_map
+                .filter(|(_, instant)| *instant == connection.counter)
+            {
+                connection.map = map;
+                connection.new_map = None;
+            }
+            connection.select_channel();
+            let widening = connection.widening();
+            let lost = if connection.established {
+                connection.since_heard > connection.timeout
+            } else {
+                connection.counter >= FIRST_EVENTS
+            };
+            if lost || widening > connection.interval / 2 {
+                self.start_advertising();
+                return;
+            }
+            let start = connection
+                .anchor
+                .wrapping_sub(widening + fine(RAMP_US) + TICK);
+            if (start.wrapping_sub(now) as i32) >= 2 * TICK as i32 {
+                let end = connection
+                    .anchor
+                    .wrapping_add(connection.window + widening + fine(ADDRESS_US) + TICK);
+                break (start, end);
+            }
+        };
+        let channel = connection.channel;
+        self.tune(channel);
+        self.point_at(&self.rx);
+        self.set_shorts(READY_START | END_DISABLE | DISABLED_TXEN);
+        self.radio.events_disabled.write(|w| unsafe { w.bits(0) });
+        self.radio.events_address.write(|w| unsafe { w.bits(0) });
+        self.set_compare(0, start);
+        self.set_compare(1, end);
+        self.phase = Phase::Listen;
+    }
+}
+
+// Main's side of the link. Writes from the central arrive as console
+// requests, only the answers to parameter writes go back, into the
+// parameter characteristic.
+pub struct Ble;
+
+impl Ble {
+    // Takes over the radio, it can't also run the micro:bit radio link
+    pub fn start(radio: RADIO, rtc: RTC0, mut ppi: Ppi6, ficr: &FICR) -> Self {
+        #[cfg(feature = "v1")]
+        apply_trim(&radio, ficr);
+        ppi.set_event_endpoint(&rtc.events_compare[0]);
+        ppi.set_task_endpoint(&radio.tasks_rxen);
+        ppi.enable();
+        rtc.prescaler.write(|w| unsafe { w.bits(0) });
+        rtc.intenset
+            .write(|w| unsafe { w.bits(COMPARE1 | COMPARE2) });
+        rtc.tasks_start.write(|w| unsafe { w.bits(1) });
+        radio
+            .intenset
+            .write(|w| unsafe { w.bits(ADDRESS | DISABLED) });
+        let address = Beacon::new(ficr).address();
+        let mut adv = [0; RX_LEN];
+        let data = advertising_data();
+        adv[0] = ADV_IND | TX_ADD;
+        adv[1] = (6 + data.len()) as u8;
+        adv[2..8].copy_from_slice(&address);
+        adv[8..8 + data.len()].copy_from_slice(&data);
+        let link = Link {
+            radio,
+            rtc,
+            address,
+            phase: Phase::Idle,
+            adv,
+            rx: [0; RX_LEN],
+            heard_at: None,
+            seed: u32::from_le_bytes([address[0], address[1], address[2], address[3]]),
+            connection: None,
+            queue: Queue::new(),
+            server: Server::new(),
+        };
+        // The packet pointers refer to the link in its static
+        cortex_m::interrupt::free(|cs| {
+            let mut slot = LINK.borrow(cs).borrow_mut();
+            slot.insert(link).start_advertising();
+        });
+        Ble
+    }
+
+    pub fn poll(&mut self) -> Option<Request> {
+        cortex_m::interrupt::free(|cs| {
+            LINK.borrow(cs)
+                .borrow_mut()
+                .as_mut()
+                .and_then(|link| link.server.take_request())
+        })
+    }
+
+    // The values the central reads, notified at the next connection event
+    pub fn update(&mut self, values: &Values) {
+        cortex_m::interrupt::free(|cs| {
+            if let Some(link) = LINK.borrow(cs).borrow_mut().as_mut() {
+                link.server.update(values);
+            }
+        });
+    }
+
+    fn param_result(&mut self, ok: bool, value: Option<i32>) {
+        cortex_m::interrupt::free(|cs| {
+            if let Some(link) = LINK.borrow(cs).borrow_mut().as_mut() {
+                link.server.param_result(ok, value);
+            }
+        });
+    }
+}
+
+impl fmt::Write for Ble {
+    fn write_str(&mut self, _: &str) -> fmt::Result {
+        Ok(())
+    }
+}
+
+impl Reply for Ble {
+    fn reply(&mut self, text: &str) {
+        self.param_result(text == "ok", None);
+    }
+
+    fn value(&mut self, _: Param, value: i32) {
+        self.param_result(true, Some(value));
+    }
+}
+
+// Flags (general discoverable, no BR/EDR), the name and the service UUID
+fn advertising_data() -> [u8; 3 + 2 + NAME.len() + 2 + 16] {
+    let mut data = [0; 3 + 2 + NAME.len() + 2 + 16];
+    data[..3].copy_from_slice(&[2, 0x01, 0x06]);
+    data[3] = 1 + NAME.len() as u8;
+    data[4] = 0x09;
+    data[5..5 + NAME.len()].copy_from_slice(NAME);
+    let uuid = 5 + NAME.len();
+    data[uuid] = 17;
+    data[uuid + 1] = 0x07;
+    data[uuid + 2..].copy_from_slice(&gatt::SERVICE);
+    data
+}
+
+// The nRF51 needs the factory trim values for the BLE mode
+#[cfg(feature = "v1")]
+fn apply_trim(radio: &RADIO, ficr: &FICR) {
+    if ficr.overrideen.read().bits() & 1 << 3 == 0 {
+        let trim = |index: usize| ficr.ble_1mbit[index].read().bits();
+        radio.override0.write(|w| unsafe { w.bits(trim(0)) });
+        radio.override1.write(|w| unsafe { w.bits(trim(1)) });
+        radio.override2.write(|w| unsafe { w.bits(trim(2)) });
+        radio.override3.write(|w| unsafe { w.bits(trim(3)) });
+        radio
+            .override4
+            .write(|w| unsafe { w.bits(trim(4) | 1 << 31) });
+    }
+}
+
+// No global critical section in either interrupt: RADIO and RTC0 share a
+// priority so they don't preempt each other, and main only touches the link
+// in critical sections, which mask both
+pub fn radio_interrupt() {
+    let cs = unsafe { CriticalSection::new() };
+    let mut link = LINK.borrow(&cs).borrow_mut();
+    if let Some(link) = link.as_mut() {
+        link.radio_event();
+    }
+}
+
+pub fn rtc_interrupt() {
+    let cs = unsafe { CriticalSection::new() };
+    let mut link = LINK.borrow(&cs).borrow_mut();
+    if let Some(link) = link.as_mut() {
+        link.rtc_event();
+    }
+}
@@ -0,0 +1,38 @@
+// Event timebase shared by several cars. Every car counts local time in ms
+// on the monotonic clock (monotonic.rs), the sync broadcast of an event
+// master sets the offset to the master clock. Radio latency is well below a
+// millisecond, so the last sync is taken as is, and the crystal drift
+// between syncs is a few ms per minute at most.
+
+pub struct Clock {
+    offset: u32,
+    synced: bool,
+}
+
+impl Clock {
+    pub const fn new() -> Self {
+        Clock {
+            offset: 0,
+            synced: false,
+        }
+    }
+
+    pub fn sync(&mut self, master_ms: u32, local_ms: u32) {
+        self.offset = master_ms.wrapping_sub(local_ms);
+        self.synced = true;
+    }
+
+    pub fn synced(&self) -> bool {
+        self.synced
+    }
+
+    // Event time, local time until the first sync
+    pub fn now(&self, local_ms: u32) -> u32 {
+        local_ms.wrapping_add(self.offset)
+    }
+
+    // Wraps after 49 days like the clock itself
+    pub fn reached(&self, local_ms: u32, time_ms: u32) -> bool {
+        (self.now(local_ms).wrapping_sub(time_ms) as i32) >= 0
+    }
+}
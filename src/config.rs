@@ -0,0 +1,467 @@
+// Runtime configuration persisted in flash.
+// Unprogrammed or foreign flash contents fall back to the defaults.
+//
+// Saves alternate between two pages. Each record ends with a sequence number
+// and a CRC that are written last, so a save cut short by a power loss
+// leaves a record that fails its CRC, and the other, older slot loads.
+// The newest valid slot wins.
+//
+// The magic carries a layout version. Settings are only ever appended, a
+// record of an older version is migrated on load by filling the words it
+// doesn't have with the defaults, so a firmware update keeps the calibration.
+
+use crate::drive;
+use crate::errlog::PanicPolicy;
+use crate::expansion::{Spare, ADDONS};
+use crate::flash::{crc32, Flash, Page};
+use crate::headlight::HeadlightMode;
+use crate::lights::Effect;
+use crate::maneuver::EndOfLine;
+use crate::pins;
+
+// "RBC" and the version as an ASCII character in the low byte
+const MAGIC: u32 = 0x5242_4300;
+const VERSION: u8 = b'9';
+// "RBCF", the single page config before the slots. It grew setting by
+// setting and ends where the firmware that saved it stopped writing.
+const LEGACY: u8 = b'F';
+const WORDS: usize = 29;
+// Config words, sequence number and CRC
+const RECORD: usize = WORDS + 2;
+const SLOTS: [Page; 2] = [Page::Config, Page::ConfigB];
+const ERASED: u32 = 0xffff_ffff;
+pub const ZONES: usize = 8;
+// Four hours
+pub const POWER_OFF_MAX_MIN: u8 = 240;
+pub const TRIM_MAX: i8 = 20;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    LineFollow,
+    Remote,
+}
+
+#[derive(Clone, Copy)]
+pub struct RadioConfig {
+    // 2400 + frequency MHz, 0..=100
+    pub frequency: u8,
+    pub group: u8,
+    pub address: u32,
+    // AES-128 key for the command MAC, exchanged while pairing
+    pub key: [u8; 16],
+}
+
+#[derive(Clone, Copy)]
+pub struct Config {
+    pub radio: RadioConfig,
+    // Remote control stops the car when no valid command arrived for this long
+    pub failsafe_ms: u16,
+    // Spare pin of each add-on, indexed by Addon
+    pub addons: [Option<Spare>; ADDONS],
+    // Steering jerk limiter in percent of full steering per frame and per
+    // frame², 0 disables it
+    pub steer_rate: u8,
+    pub steer_accel: u8,
+    // Photocell classifier thresholds from the calibration
+    pub thresholds: [i16; 3],
+    // Last selected mode and speed profile (throttle in percent), restored at
+    // boot
+    pub mode: Mode,
+    pub speed: u8,
+    // LED effect while driving
+    pub effect: Effect,
+    // Stop or turn around at the end of the line
+    pub end_of_line: EndOfLine,
+    // Headlight mode and the ambient photocell reading it turns on below
+    pub headlight: HeadlightMode,
+    pub headlight_threshold: i16,
+    // Device expected on the external I2C bus, checked by the self test
+    pub i2c_device: Option<u8>,
+    // The car stops after driving this long or far in one run, 0 disables
+    pub run_limit_s: u16,
+    pub run_limit_m: u16,
+    // Speed in percent for the first track segments, None drives them with
+    // the speed profile
+    pub zones: [Option<u8>; ZONES],
+    // Photocell error around the line center that is driven straight (pid)
+    pub deadband: u16,
+    // PID gains kp, ki and kd in thousandths (pid)
+    pub gains: [u16; 3],
+    pub panic: PanicPolicy,
+    // Steering in percent beyond which the inner wheel reverses, 0 disables
+    pub tight_turn: u8,
+    // Servo periods per frame, 1 for 50 Hz, see drive::frame_periods
+    pub servo_periods: u8,
+    // Minutes stopped before System OFF, 0 disables
+    pub power_off_min: u8,
+    // Photocells, 2 follows the line on their difference, see differential.rs
+    pub sensors: u8,
+    // Steering trim in percent at full throttle, positive left
+    pub trim: i8,
+    // Follows the line driving backwards
+    pub reverse: bool,
+}
+
+impl Config {
+    // Same defaults as the micro:bit runtime
+    pub const DEFAULT: Config = Config {
+        radio: RadioConfig {
+            frequency: 7,
+            group: 0,
+            address: 0x7562_6974, // "ubit"
+            key: [0; 16],
+        },
+        failsafe_ms: 500,
+        // The defaults of pins.rs, the serial port TX and RX only
+        addons: [
+            pins::SERIAL_TX,
+            pins::SERIAL_RX,
+            None,
+            pins::HEADLIGHT,
+            None,
+            None,
+            None,
+            pins::SONAR,
+            None,
+            None,
+            None,
+            None,
+            None,
+            pins::ENCODER[0],
+            pins::ENCODER[1],
+        ],
+        steer_rate: 25,
+        steer_accel: 5,
+        thresholds: [64, 220, 320],
+        mode: Mode::LineFollow,
+        speed: 100,
+        effect: Effect::Status,
+        end_of_line: EndOfLine::Stop,
+        headlight: HeadlightMode::Auto,
+        headlight_threshold: 20,
+        i2c_device: None,
+        run_limit_s: 600,
+        run_limit_m: 0,
+        zones: [None; ZONES],
+        deadband: 0,
+        gains: [1200, 10, 2000],
+        panic: PanicPolicy::Blink,
+        tight_turn: 0,
+        servo_periods: 1,
+        power_off_min: 15,
+        sensors: 1,
+        trim: 0,
+        reverse: false,
+    };
+
+    // Speed in percent for a track segment
+    pub fn segment_speed(&self, segment: u8) -> u8 {
+        match self.zones.get(segment as usize) {
+            Some(Some(speed)) => *speed,
+            _ => self.speed,
+        }
+    }
+
+    pub fn load(flash: &Flash) -> Config {
+        newest(flash)
+            .and_then(|(_, slot)| Config::decode(&slot.words))
+            .unwrap_or(Config::DEFAULT)
+    }
+
+    // A config of any version in either slot
+    pub fn saved(flash: &Flash) -> bool {
+        newest(flash).is_some()
+    }
+
+    // Programmed but no valid config in either slot. Erased pages are a
+    // fresh board, not a fault.
+    pub fn corrupt(flash: &Flash) -> bool {
+        let valid = newest(flash).is_some_and(|(_, slot)| Config::decode(&slot.words).is_some());
+        !valid
+            && SLOTS.iter().any(|page| {
+                let mut record = [0u32; RECORD];
+                flash.read(*page, &mut record);
+                record.iter().any(|word| *word != ERASED)
+            })
+    }
+
+    fn decode(words: &[u32; WORDS]) -> Option<Config> {
+        let frequency = (words[1] & 0xff) as u8;
+        if frequency > 100 {
+            return None;
+        }
+        let mut key = [0; 16];
+        for (chunk, word) in key.chunks_exact_mut(4).zip(&words[3..7]) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        Some(Config {
+            radio: RadioConfig {
+                frequency,
+                group: (words[1] >> 8) as u8,
+                address: words[2],
+                key,
+            },
+            failsafe_ms: words[7] as u16,
+            addons: decode_addons([words[8], words[14], words[24], words[26]]),
+            steer_rate: percent(words[9], Config::DEFAULT.steer_rate),
+            steer_accel: percent(words[9] >> 8, Config::DEFAULT.steer_accel),
+            thresholds: decode_thresholds(words[10], words[11]),
+            mode: match words[12] as u8 {
+                1 => Mode::Remote,
+                _ => Mode::LineFollow,
+            },
+            speed: percent(words[12] >> 8, Config::DEFAULT.speed),
+            effect: match (words[12] >> 16) as u8 {
+                1 => Effect::Rainbow,
+                _ => Effect::Status,
+            },
+            end_of_line: match (words[12] >> 24) as u8 {
+                1 => EndOfLine::TurnAround,
+                2 => EndOfLine::Search,
+                3 => EndOfLine::Shuttle,
+                _ => EndOfLine::Stop,
+            },
+            headlight: match words[13] as u8 {
+                1 => HeadlightMode::On,
+                2 => HeadlightMode::Off,
+                _ => HeadlightMode::Auto,
+            },
+            headlight_threshold: match words[13] >> 16 {
+                0xffff => Config::DEFAULT.headlight_threshold,
+                threshold => threshold as i16,
+            },
+            i2c_device: match words[15] as u8 {
+                address @ 0..=0x7f => Some(address),
+                _ => None,
+            },
+            run_limit_s: match words[16] as u16 {
+                0xffff => Config::DEFAULT.run_limit_s,
+                limit => limit,
+            },
+            run_limit_m: match (words[16] >> 16) as u16 {
+                0xffff => Config::DEFAULT.run_limit_m,
+                limit => limit,
+            },
+            zones: decode_zones([words[17], words[18]]),
+            deadband: match words[19] as u16 {
+                0xffff => Config::DEFAULT.deadband,
+                deadband => deadband,
+            },
+            gains: [
+                gain(words[20], 0),
+                gain(words[20] >> 16, 1),
+                gain(words[21], 2),
+            ],
+            panic: match words[22] as u8 {
+                0 => PanicPolicy::Halt,
+                2 => PanicPolicy::Reset((words[22] >> 16) as u16),
+                _ => PanicPolicy::Blink,
+            },
+            tight_turn: percent(words[23], Config::DEFAULT.tight_turn),
+            servo_periods: match words[25] as u8 {
+                periods if drive::period_us(periods).is_some() => periods,
+                _ => Config::DEFAULT.servo_periods,
+            },
+            power_off_min: match words[27] as u8 {
+                minutes @ 0..=POWER_OFF_MAX_MIN => minutes,
+                _ => Config::DEFAULT.power_off_min,
+            },
+            sensors: match words[28] as u8 {
+                sensors @ 1..=2 => sensors,
+                _ => Config::DEFAULT.sensors,
+            },
+            trim: match (words[28] >> 8) as u8 as i8 {
+                trim if (-TRIM_MAX..=TRIM_MAX).contains(&trim) => trim,
+                _ => Config::DEFAULT.trim,
+            },
+            reverse: (words[28] >> 16) as u8 == 1,
+        })
+    }
+
+    pub fn save(&self, flash: &mut Flash) {
+        let words = self.encode();
+        // Over the older slot, the newest one stays intact until this is done
+        let (slot, sequence) = match newest(flash) {
+            Some((slot, newest)) => (1 - slot, newest.sequence.wrapping_add(1)),
+            None => (0, 1),
+        };
+        let mut record = [0u32; RECORD];
+        record[..WORDS].copy_from_slice(&words);
+        record[WORDS] = sequence;
+        record[WORDS + 1] = crc32(&record[..WORDS + 1]);
+        flash.write(SLOTS[slot], &record);
+    }
+
+    fn encode(&self) -> [u32; WORDS] {
+        let key = |i: usize| {
+            let k = &self.radio.key[i * 4..i * 4 + 4];
+            u32::from_le_bytes([k[0], k[1], k[2], k[3]])
+        };
+        let addons = encode_addons(&self.addons);
+        let zones = encode_zones(&self.zones);
+        [
+            MAGIC | VERSION as u32,
+            self.radio.frequency as u32 | (self.radio.group as u32) << 8,
+            self.radio.address,
+            key(0),
+            key(1),
+            key(2),
+            key(3),
+            self.failsafe_ms as u32,
+            addons[0],
+            self.steer_rate as u32 | (self.steer_accel as u32) << 8,
+            self.thresholds[0] as u16 as u32 | (self.thresholds[1] as u16 as u32) << 16,
+            self.thresholds[2] as u16 as u32,
+            self.mode as u32
+                | (self.speed as u32) << 8
+                | (self.effect as u32) << 16
+                | (self.end_of_line as u32) << 24,
+            self.headlight as u32 | (self.headlight_threshold as u16 as u32) << 16,
+            addons[1],
+            self.i2c_device.map_or(0xff, |address| address as u32),
+            self.run_limit_s as u32 | (self.run_limit_m as u32) << 16,
+            zones[0],
+            zones[1],
+            self.deadband as u32,
+            self.gains[0] as u32 | (self.gains[1] as u32) << 16,
+            self.gains[2] as u32,
+            match self.panic {
+                PanicPolicy::Halt => 0,
+                PanicPolicy::Blink => 1,
+                PanicPolicy::Reset(seconds) => 2 | (seconds as u32) << 16,
+            },
+            self.tight_turn as u32,
+            addons[2],
+            self.servo_periods as u32,
+            addons[3],
+            self.power_off_min as u32,
+            self.sensors as u32 | (self.trim as u8 as u32) << 8 | (self.reverse as u32) << 16,
+        ]
+    }
+}
+
+struct Slot {
+    sequence: u32,
+    words: [u32; WORDS],
+}
+
+fn read_slot(flash: &Flash, page: Page) -> Option<Slot> {
+    let mut record = [0u32; RECORD];
+    flash.read(page, &mut record);
+    if record[0] & !0xff != MAGIC {
+        return None;
+    }
+    let version = record[0] as u8;
+    let len = layout_len(version, &record)?;
+    // An older record is shorter, its sequence number and CRC follow its
+    // last word. The legacy config has no CRC and loads as sequence 0.
+    let sequence = match version {
+        LEGACY => 0,
+        _ if record[len + 1] == crc32(&record[..len + 1]) => record[len],
+        _ => return None,
+    };
+    // The words an older layout doesn't have start at the defaults
+    let mut words = Config::DEFAULT.encode();
+    words[..len].copy_from_slice(&record[..len]);
+    Some(Slot { sequence, words })
+}
+
+// Config words in a record of the version, None for an unknown version,
+// e.g. from newer firmware. A new version adds an arm with the length of
+// the layout before it.
+fn layout_len(version: u8, record: &[u32; RECORD]) -> Option<usize> {
+    let len = match version {
+        VERSION => WORDS,
+        // Before the panic policy and the tight turn
+        b'2' => 22,
+        b'3' => 23,
+        // Before the third add-on word, the servo rate and the fourth
+        // add-on word
+        b'4' => 24,
+        b'5' => 25,
+        b'6' => 26,
+        // Before the power off time
+        b'7' => 27,
+        // Before the second photocell
+        b'8' => 28,
+        LEGACY => record[..WORDS].iter().rposition(|word| *word != ERASED)? + 1,
+        _ => return None,
+    };
+    Some(len)
+}
+
+// Index and contents of the valid slot saved last, sequence numbers wrap
+fn newest(flash: &Flash) -> Option<(usize, Slot)> {
+    match (read_slot(flash, SLOTS[0]), read_slot(flash, SLOTS[1])) {
+        (Some(a), Some(b)) if (b.sequence.wrapping_sub(a.sequence) as i32) > 0 => Some((1, b)),
+        (Some(a), _) => Some((0, a)),
+        (None, b) => b.map(|b| (1, b)),
+    }
+}
+
+// One byte per add-on, four per word: 0 unassigned, otherwise the spare pin
+// index + 1. Anything else, like erased flash from a config without that
+// add-on, selects the default.
+fn decode_addons(words: [u32; 4]) -> [Option<Spare>; ADDONS] {
+    let mut addons = Config::DEFAULT.addons;
+    for (i, addon) in addons.iter_mut().enumerate() {
+        match (words[i / 4] >> (i % 4 * 8)) as u8 {
+            0 => *addon = None,
+            byte => {
+                if let Some(spare) = Spare::from_index(byte - 1) {
+                    *addon = Some(spare);
+                }
+            }
+        }
+    }
+    addons
+}
+
+fn encode_addons(addons: &[Option<Spare>; ADDONS]) -> [u32; 4] {
+    let mut words = [0; 4];
+    for (i, addon) in addons.iter().enumerate() {
+        words[i / 4] |= addon.map_or(0, |spare| spare as u32 + 1) << (i % 4 * 8);
+    }
+    words
+}
+
+// One byte per segment, a speed of 0..=100 or anything else for none
+fn decode_zones(words: [u32; 2]) -> [Option<u8>; ZONES] {
+    core::array::from_fn(|i| match (words[i / 4] >> (i % 4 * 8)) as u8 {
+        speed @ 0..=100 => Some(speed),
+        _ => None,
+    })
+}
+
+fn encode_zones(zones: &[Option<u8>; ZONES]) -> [u32; 2] {
+    let mut words = [0; 2];
+    for (i, zone) in zones.iter().enumerate() {
+        words[i / 4] |= zone.map_or(0xff, |speed| speed as u32) << (i % 4 * 8);
+    }
+    words
+}
+
+// Erased flash from an older config reads as 0xff
+fn percent(word: u32, default: u8) -> u8 {
+    match word as u8 {
+        value @ 0..=100 => value,
+        _ => default,
+    }
+}
+
+fn gain(word: u32, i: usize) -> u16 {
+    match word as u16 {
+        0xffff => Config::DEFAULT.gains[i],
+        gain => gain,
+    }
+}
+
+// Falls back to the defaults unless the thresholds are increasing
+fn decode_thresholds(word0: u32, word1: u32) -> [i16; 3] {
+    let thresholds = [word0 as i16, (word0 >> 16) as i16, word1 as i16];
+    if thresholds[0] < thresholds[1] && thresholds[1] < thresholds[2] {
+        thresholds
+    } else {
+        Config::DEFAULT.thresholds
+    }
+}
@@ -0,0 +1,669 @@
+// Line based text command protocol, independent of the transport. The
+// serial port (edge connector or USB) and the RTT shell are Ports, each
+// wrapped in a Console that collects lines and answers parse errors.
+// Commands are ASCII words separated by spaces, one command per line:
+//
+//   start                       start the car
+//   stop                        stop the car
+//   drive <throttle> <steering> remote control, -100..100 percent each
+//   reset                       stop, save the statistics and reboot
+//   status                      reply with the telemetry
+//   scan                        reply with the addresses found on the
+//                               external I2C bus
+//   addon <name> <pin|none>     assign a spare edge pin to an add-on (tx,
+//                               rx, leds, headlight, buzzer, marker,
+//                               linefinder, ultrasonic, pan, pwm_left,
+//                               pwm_right, dir_left, dir_right,
+//                               encoder_a or encoder_b),
+//                               saved, active after reset
+//   stats                       reply with runs, driving time and distance
+//   log                         reply with the errors recorded since power on
+//   effect <status|rainbow>     LED effect while driving, saved
+//   headlight <auto|on|off>     headlight override, saved
+//   headlight threshold <n>     averaged photocell reading the headlight
+//                               turns on below, saved
+//   speed <percent>             speed profile, scales the throttle, saved
+//   steer <rate> <accel>        steering jerk limits in percent per frame
+//                               and per frame², 0 0 disables, saved
+//   zone <segment[-last]> <percent|none>
+//                               speed for track segments 0..7, none
+//                               drives them with the speed profile, saved
+//   forward <cm>                drive straight, negative is backwards
+//   spin <degrees>              spin in place, positive turns left
+//   arc <radius> <degrees> [percent]
+//                               drive along a circle, radius in cm up to
+//                               2000, at 50 percent speed by default
+//   script <hex|clear|save|run>
+//                               load a maneuver script: clear, append up
+//                               to 24 bytes per line in hex, save to
+//                               flash, run the loaded script (script.rs)
+//   end <stop|turn|search|shuttle>
+//                               stop, turn around, search for the line or
+//                               follow it back at the end of the line, saved
+//   reverse <on|off>            follow the line driving backwards, saved
+//   deadband <reading>          photocell error around the line center
+//                               that is driven straight (pid), saved
+//   limit <seconds> <meters>    stop a run after this long or far, 0
+//                               disables a limit, saved
+//   i2c <address|none>          hex address of a device the self test
+//                               expects on the external I2C bus, saved
+//   servo <hz>                  servo frame rate for digital servos, 50
+//                               (default) to 300 in steps of 50, saved,
+//                               active after reset
+//   panic <halt|blink|reset <seconds>>
+//                               what a panic does: halt as is, stop the
+//                               servos and blink the fault code, or stop,
+//                               blink and reset after the seconds, saved
+//   set <param> <value>         change a setting until reset, gains with
+//                               up to three decimals (params.rs)
+//   get <param>                 reply with a setting or live value
+//   save                        write the settings to flash
+//   help                        reply with the commands and params
+//   menu                        browse and edit the settings page by page
+//                               (menu.rs)
+//   fsm                         reply with the run state machine as
+//                               Graphviz DOT (fsm.rs)
+//   noise [samples]             photocell noise statistics while stopped,
+//                               2048 samples by default (noise.rs)
+//   horn                        sound the horn on the speaker or buzzer
+//   wizard                      run the setup wizard on the display and
+//                               the buttons, stopped (wizard.rs)
+//   curve <left|right|clear>    measure a servo's pulse to speed curve
+//                               with the wheel encoder on its wheel, the
+//                               car lifted, or clear both, saved (curve.rs)
+//   manual                      steer with a potentiometer on P3 while A
+//                               is held, B or stop ends it (manual.rs)
+//
+// Every command is answered with one line: "ok", "error" or for status
+// "state=<0..4> on=<0|1> remote=<0|1> photo=<reading> mv=<supply>" with the
+// state codes of the beacon record, for scan "i2c <address>.." with hex
+// addresses or "i2c none", for stats "runs=<n> runtime=<s> distance=<cm>",
+// for log "log <kind>:<file>:<line>|<kind>:<value>.." oldest first or
+// "log none", for get "<param>=<value>", for help "commands <name>..
+// params <name>..", for noise "noise n=<samples> min=<reading>
+// max=<reading> mean=<reading> stddev=<reading> bins <low>:<count>.." with
+// the lower end of every bin, and a menu page and the DOT graph take
+// several lines.
+// Drive commands are subject to the same
+// failsafe timeout as radio drive commands. A failed self test is reported
+// once at boot as "fault <check>", a start refused by the arming interlock
+// as "interlock <check>", the power off of an idle car as "power off".
+
+use core::fmt::Write;
+
+use crate::beacon::Telemetry;
+use crate::config::{Config, ZONES};
+use crate::curve::Servo;
+use crate::diagnostics::Fault;
+use crate::drive;
+use crate::errlog::{self, Kind, PanicPolicy, Record};
+use crate::expansion::{Addon, Spare};
+use crate::fixed::Fix;
+use crate::fsm;
+use crate::headlight::HeadlightMode;
+use crate::lights::Effect;
+use crate::maneuver::{EndOfLine, Motion, MAX_RADIUS_CM};
+use crate::menu;
+use crate::noise::{self, Noise};
+use crate::params::{Param, PARAMS};
+use crate::radio::Command;
+use crate::script::{Chunk, ScriptCommand, CHUNK_LEN};
+use crate::stats::Stats;
+
+pub const LINE_LEN: usize = 64;
+
+const PERCENT: Fix = Fix::from_f32(0.01);
+
+const COMMANDS: &[&str] = &[
+    "start",
+    "stop",
+    "drive",
+    "reset",
+    "status",
+    "scan",
+    "addon",
+    "stats",
+    "log",
+    "effect",
+    "headlight",
+    "speed",
+    "steer",
+    "zone",
+    "forward",
+    "spin",
+    "arc",
+    "script",
+    "end",
+    "reverse",
+    "deadband",
+    "limit",
+    "i2c",
+    "servo",
+    "panic",
+    "set",
+    "get",
+    "save",
+    "help",
+    "menu",
+    "fsm",
+    "noise",
+    "manual",
+    "curve",
+    "wizard",
+    "horn",
+];
+
+pub enum Request {
+    Command(Command),
+    Status,
+    Scan,
+    Stats,
+    Log,
+    Addon {
+        addon: Addon,
+        spare: Option<Spare>,
+    },
+    Effect(Effect),
+    Headlight(HeadlightMode),
+    HeadlightThreshold(i16),
+    Speed(u8),
+    SteerLimits {
+        rate: u8,
+        accel: u8,
+    },
+    I2cDevice(Option<u8>),
+    RunLimit {
+        seconds: u16,
+        meters: u16,
+    },
+    Zone {
+        first: u8,
+        last: u8,
+        speed: Option<u8>,
+    },
+    Deadband(u16),
+    EndOfLine(EndOfLine),
+    Reverse(bool),
+    Panic(PanicPolicy),
+    // Servo periods per frame
+    ServoRate(u8),
+    Move(Motion),
+    Set(Param, i32),
+    Get(Param),
+    Save,
+    Help,
+    // Show a menu page, None closes the menu
+    Menu(Option<u8>),
+    Fsm,
+    Noise(u16),
+    Manual,
+    Wizard,
+    Horn,
+    // None clears both curves
+    Curve(Option<Servo>),
+}
+
+pub fn parse(line: &str) -> Option<Request> {
+    let mut words = line.split_ascii_whitespace();
+    let request = match words.next()? {
+        "start" => Request::Command(Command::Start),
+        "stop" => Request::Command(Command::Stop),
+        "reset" => Request::Command(Command::Reset),
+        "drive" => {
+            let throttle = percent(words.next()?)?;
+            let steering = percent(words.next()?)?;
+            Request::Command(Command::Drive { throttle, steering })
+        }
+        "status" => Request::Status,
+        "scan" => Request::Scan,
+        "stats" => Request::Stats,
+        "log" => Request::Log,
+        "addon" => {
+            let addon = Addon::from_name(words.next()?)?;
+            let spare = match words.next()? {
+                "none" => None,
+                pin => Some(Spare::from_number(pin.parse().ok()?)?),
+            };
+            Request::Addon { addon, spare }
+        }
+        "effect" => Request::Effect(match words.next()? {
+            "status" => Effect::Status,
+            "rainbow" => Effect::Rainbow,
+            _ => return None,
+        }),
+        "headlight" => match words.next()? {
+            "auto" => Request::Headlight(HeadlightMode::Auto),
+            "on" => Request::Headlight(HeadlightMode::On),
+            "off" => Request::Headlight(HeadlightMode::Off),
+            "threshold" => Request::HeadlightThreshold(words.next()?.parse().ok()?),
+            _ => return None,
+        },
+        "speed" => Request::Speed(words.next()?.parse().ok().filter(|speed| *speed <= 100)?),
+        "steer" => {
+            let rate = words.next()?.parse().ok().filter(|rate| *rate <= 100)?;
+            let accel = words.next()?.parse().ok().filter(|accel| *accel <= 100)?;
+            Request::SteerLimits { rate, accel }
+        }
+        "zone" => {
+            let segments = words.next()?;
+            let (first, last) = segments.split_once('-').unwrap_or((segments, segments));
+            let first: u8 = first.parse().ok()?;
+            let last: u8 = last.parse().ok().filter(|last| *last >= first)?;
+            if last as usize >= ZONES {
+                return None;
+            }
+            let speed = match words.next()? {
+                "none" => None,
+                speed => Some(speed.parse().ok().filter(|speed| *speed <= 100)?),
+            };
+            Request::Zone { first, last, speed }
+        }
+        // Symmetric around 0, -32768 has no positive counterpart
+        "forward" => Request::Move(Motion::Forward {
+            cm: words.next()?.parse().ok().filter(|cm| *cm != i16::MIN)?,
+        }),
+        "spin" => Request::Move(Motion::Turn {
+            degrees: words.next()?.parse().ok().filter(|d| *d != i16::MIN)?,
+        }),
+        "arc" => {
+            let radius_cm = words
+                .next()?
+                .parse()
+                .ok()
+                .filter(|radius| *radius <= MAX_RADIUS_CM)?;
+            let degrees = words.next()?.parse().ok()?;
+            let speed = match words.next() {
+                Some(speed) => speed
+                    .parse()
+                    .ok()
+                    .filter(|speed| (1..=100).contains(speed))?,
+                None => 50,
+            };
+            Request::Move(Motion::Arc {
+                radius_cm,
+                degrees,
+                speed,
+            })
+        }
+        "script" => Request::Command(Command::Script(match words.next()? {
+            "clear" => ScriptCommand::Clear,
+            "save" => ScriptCommand::Save,
+            "run" => ScriptCommand::Run,
+            hex => ScriptCommand::Append(chunk(hex)?),
+        })),
+        "end" => Request::EndOfLine(match words.next()? {
+            "stop" => EndOfLine::Stop,
+            "turn" => EndOfLine::TurnAround,
+            "search" => EndOfLine::Search,
+            "shuttle" => EndOfLine::Shuttle,
+            _ => return None,
+        }),
+        "reverse" => Request::Reverse(match words.next()? {
+            "on" => true,
+            "off" => false,
+            _ => return None,
+        }),
+        "deadband" => {
+            let deadband = words.next()?.parse().ok();
+            Request::Deadband(deadband.filter(|d| Param::Deadband.accepts(*d))? as u16)
+        }
+        "limit" => {
+            // 0xffff is erased flash in the config
+            let seconds = words.next()?.parse().ok().filter(|s| *s < u16::MAX)?;
+            let meters = words.next()?.parse().ok().filter(|m| *m < u16::MAX)?;
+            Request::RunLimit { seconds, meters }
+        }
+        "i2c" => Request::I2cDevice(match words.next()? {
+            "none" => None,
+            address => Some(
+                u8::from_str_radix(address.trim_start_matches("0x"), 16)
+                    .ok()
+                    .filter(|address| *address <= 0x7f)?,
+            ),
+        }),
+        "servo" => Request::ServoRate(drive::frame_periods(words.next()?.parse().ok()?)?),
+        "panic" => Request::Panic(match words.next()? {
+            "halt" => PanicPolicy::Halt,
+            "blink" => PanicPolicy::Blink,
+            "reset" => PanicPolicy::Reset(words.next()?.parse().ok()?),
+            _ => return None,
+        }),
+        "set" => {
+            let param = Param::from_name(words.next()?)?;
+            Request::Set(param, value(param, words.next()?)?)
+        }
+        "get" => Request::Get(Param::from_name(words.next()?)?),
+        "save" => Request::Save,
+        "help" => Request::Help,
+        "menu" => Request::Menu(Some(0)),
+        "fsm" => Request::Fsm,
+        "manual" => Request::Manual,
+        "wizard" => Request::Wizard,
+        "horn" => Request::Horn,
+        "curve" => Request::Curve(match words.next()? {
+            "left" => Some(Servo::Left),
+            "right" => Some(Servo::Right),
+            "clear" => None,
+            _ => return None,
+        }),
+        "noise" => Request::Noise(match words.next() {
+            Some(samples) => samples
+                .parse()
+                .ok()
+                .filter(|samples| (1..=noise::MAX_SAMPLES).contains(samples))?,
+            None => noise::DEFAULT_SAMPLES,
+        }),
+        _ => return None,
+    };
+    // Trailing words are an error, not silently ignored
+    match words.next() {
+        Some(_) => None,
+        None => Some(request),
+    }
+}
+
+fn percent(word: &str) -> Option<Fix> {
+    let value = word.parse::<i8>().ok()?.clamp(-100, 100);
+    Some(Fix::from_int(value as i32) * PERCENT)
+}
+
+// Integer, or with up to three decimals for a value in thousandths
+pub fn value(param: Param, word: &str) -> Option<i32> {
+    if !param.milli() {
+        return word.parse().ok();
+    }
+    let (int, frac) = word.split_once('.').unwrap_or((word, ""));
+    if frac.len() > 3 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let frac = frac.bytes().chain(core::iter::repeat(b'0')).take(3);
+    let frac = frac.fold(0, |value, digit| value * 10 + (digit - b'0') as i32);
+    // The fraction has the sign of the whole number, also for "-0.5"
+    let (sign, int) = match int.strip_prefix('-') {
+        Some(int) => (-1, int),
+        None => (1, int),
+    };
+    if !int.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let value = int
+        .parse::<i32>()
+        .ok()?
+        .checked_mul(1000)?
+        .checked_add(frac)?;
+    Some(sign * value)
+}
+
+fn chunk(hex: &str) -> Option<Chunk> {
+    if !hex.len().is_multiple_of(2) || hex.len() > 2 * CHUNK_LEN {
+        return None;
+    }
+    let mut bytes = [0; CHUNK_LEN];
+    for (byte, digits) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(core::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+    Chunk::new(&bytes[..hex.len() / 2])
+}
+
+// A transport the console runs on
+pub trait Port: Write {
+    // Next received byte, None when nothing is pending
+    fn read_byte(&mut self) -> Option<u8>;
+}
+
+pub struct Console<P> {
+    port: P,
+    line: LineBuffer,
+    // Page shown while the menu is open
+    menu: Option<u8>,
+}
+
+impl<P: Port> Console<P> {
+    pub fn new(port: P) -> Self {
+        Console {
+            port,
+            line: LineBuffer::new(),
+            menu: None,
+        }
+    }
+
+    // Reads all pending bytes, returns a request once a line is complete.
+    // Lines that don't parse are answered here.
+    pub fn poll(&mut self) -> Option<Request> {
+        while let Some(byte) = self.port.read_byte() {
+            let menu = self.menu;
+            let request = self.line.push(byte).map(|line| match menu {
+                Some(page) => menu::parse(line, page),
+                None => parse(line),
+            });
+            match request {
+                Some(Some(request)) => {
+                    if let Request::Menu(page) = request {
+                        self.menu = page;
+                    }
+                    return Some(request);
+                }
+                Some(None) => self.reply("error"),
+                None => (),
+            }
+        }
+        None
+    }
+}
+
+impl<P: Port> Write for Console<P> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.port.write_str(s)
+    }
+}
+
+// Replies of any console. Write errors are dropped, there is nobody to
+// report them to.
+pub trait Reply: Write {
+    fn reply(&mut self, text: &str) {
+        let _ = writeln!(self, "{}", text);
+    }
+
+    fn status(&mut self, telemetry: &Telemetry) {
+        let _ = write_status(self, telemetry);
+    }
+
+    fn stats(&mut self, stats: &Stats) {
+        let _ = write_stats(self, stats);
+    }
+
+    fn scan_result(&mut self, found: u128) {
+        let _ = write_scan(self, found);
+    }
+
+    fn log(&mut self) {
+        let _ = write_log(self, errlog::records());
+    }
+
+    fn fault(&mut self, fault: Fault) {
+        let _ = write_fault(self, fault);
+    }
+
+    fn interlock(&mut self, fault: Fault) {
+        let _ = writeln!(self, "interlock {}", fault.name());
+    }
+
+    fn power_off(&mut self) {
+        let _ = writeln!(self, "power off");
+    }
+
+    fn value(&mut self, param: Param, value: i32) {
+        let _ = write_value(self, param, value);
+    }
+
+    fn help(&mut self) {
+        let _ = write_help(self);
+    }
+
+    fn menu_page(&mut self, page: u8, config: &Config) {
+        let _ = menu::write_page(self, page, config);
+    }
+
+    fn fsm(&mut self) {
+        let _ = fsm::write_dot(self);
+    }
+
+    fn noise(&mut self, noise: &Noise) {
+        let _ = write_noise(self, noise);
+    }
+}
+
+impl<P: Port> Reply for Console<P> {}
+
+// Collects received bytes into lines
+pub struct LineBuffer {
+    buf: [u8; LINE_LEN],
+    len: usize,
+    overflow: bool,
+}
+
+impl LineBuffer {
+    pub const fn new() -> Self {
+        LineBuffer {
+            buf: [0; LINE_LEN],
+            len: 0,
+            overflow: false,
+        }
+    }
+
+    // Returns the line once its terminator arrived, over long lines come back
+    // as empty lines so they are answered with an error.
+    pub fn push(&mut self, byte: u8) -> Option<&str> {
+        match byte {
+            b'\n' | b'\r' if self.len == 0 && !self.overflow => None,
+            b'\n' | b'\r' => {
+                let len = if self.overflow { 0 } else { self.len };
+                self.len = 0;
+                self.overflow = false;
+                Some(core::str::from_utf8(&self.buf[..len]).unwrap_or(""))
+            }
+            _ if self.len == LINE_LEN => {
+                self.overflow = true;
+                None
+            }
+            _ => {
+                self.buf[self.len] = byte;
+                self.len += 1;
+                None
+            }
+        }
+    }
+}
+
+pub fn write_status<W: Write + ?Sized>(out: &mut W, telemetry: &Telemetry) -> core::fmt::Result {
+    writeln!(
+        out,
+        "state={} on={} remote={} photo={} mv={}",
+        telemetry.state,
+        telemetry.is_on as u8,
+        telemetry.remote as u8,
+        telemetry.photo_cell,
+        telemetry.battery_mv
+    )
+}
+
+pub fn write_scan<W: Write + ?Sized>(out: &mut W, found: u128) -> core::fmt::Result {
+    write!(out, "i2c")?;
+    if found == 0 {
+        write!(out, " none")?;
+    }
+    for address in (0..128).filter(|address| found & 1 << address != 0) {
+        write!(out, " 0x{:02x}", address)?;
+    }
+    writeln!(out)
+}
+
+pub fn write_stats<W: Write + ?Sized>(out: &mut W, stats: &Stats) -> core::fmt::Result {
+    writeln!(
+        out,
+        "runs={} runtime={} distance={}",
+        stats.runs, stats.runtime_s, stats.distance_cm
+    )
+}
+
+pub fn write_fault<W: Write + ?Sized>(out: &mut W, fault: Fault) -> core::fmt::Result {
+    writeln!(out, "fault {}", fault.name())
+}
+
+pub fn write_log<W: Write + ?Sized>(
+    out: &mut W,
+    records: impl Iterator<Item = Record>,
+) -> core::fmt::Result {
+    write!(out, "log")?;
+    let mut empty = true;
+    for record in records {
+        let Some(kind) = record.kind() else {
+            continue;
+        };
+        match kind {
+            Kind::Panic => write!(out, " panic:{}:{}", record.file(), record.value)?,
+            Kind::HardFault => write!(out, " hardfault:0x{:08x}", record.value)?,
+            Kind::SelfTest => write!(out, " fault:{}", record.value)?,
+        }
+        empty = false;
+    }
+    if empty {
+        write!(out, " none")?;
+    }
+    writeln!(out)
+}
+
+pub fn write_value<W: Write + ?Sized>(out: &mut W, param: Param, value: i32) -> core::fmt::Result {
+    write!(out, "{}=", param.name())?;
+    write_number(out, param, value)?;
+    writeln!(out)
+}
+
+pub fn write_number<W: Write + ?Sized>(out: &mut W, param: Param, value: i32) -> core::fmt::Result {
+    if param.milli() {
+        let sign = if value < 0 { "-" } else { "" };
+        let value = value.unsigned_abs();
+        write!(out, "{}{}.{:03}", sign, value / 1000, value % 1000)
+    } else {
+        write!(out, "{}", value)
+    }
+}
+
+// Tenths with one decimal
+fn write_tenths<W: Write + ?Sized>(out: &mut W, tenths: i32) -> core::fmt::Result {
+    let sign = if tenths < 0 { "-" } else { "" };
+    write!(out, "{}{}.{}", sign, tenths.abs() / 10, tenths.abs() % 10)
+}
+
+pub fn write_noise<W: Write + ?Sized>(out: &mut W, noise: &Noise) -> core::fmt::Result {
+    write!(
+        out,
+        "noise n={} min={} max={} mean=",
+        noise.samples, noise.min, noise.max
+    )?;
+    write_tenths(out, noise.mean)?;
+    write!(out, " stddev=")?;
+    write_tenths(out, noise.stddev)?;
+    write!(out, " bins")?;
+    for (bin, count) in noise.histogram.iter().enumerate() {
+        write!(
+            out,
+            " {}:{}",
+            noise.low as i32 + bin as i32 * noise.width as i32,
+            count
+        )?;
+    }
+    writeln!(out)
+}
+
+pub fn write_help<W: Write + ?Sized>(out: &mut W) -> core::fmt::Result {
+    write!(out, "commands")?;
+    for command in COMMANDS {
+        write!(out, " {}", command)?;
+    }
+    write!(out, " params")?;
+    for param in PARAMS {
+        write!(out, " {}", param.name())?;
+    }
+    writeln!(out)
+}
@@ -0,0 +1,192 @@
+// Proportional line following on top of the raw photocell reading, as an
+// alternative to the threshold classifier (cargo feature `pid`).
+// The math is fixed-point by default. With the `fpu` feature the micro:bit V2
+// uses hardware floating point instead, which makes gains easier to tune.
+
+use core::ops::{Add, Div, Mul, Neg, Sub};
+
+use crate::fixed::Fix;
+
+#[cfg(not(feature = "fpu"))]
+pub type Scalar = Fix;
+#[cfg(feature = "fpu")]
+pub type Scalar = f32;
+
+// For gains and other constants of the active control path
+pub const fn scalar(v: f32) -> Scalar {
+    #[cfg(not(feature = "fpu"))]
+    return Fix::from_f32(v);
+    #[cfg(feature = "fpu")]
+    return v;
+}
+
+pub trait Num:
+    Copy
+    + PartialOrd
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+{
+    const ZERO: Self;
+    const ONE: Self;
+    fn from_i32(v: i32) -> Self;
+    fn to_fix(self) -> Fix;
+    fn clamp(self, min: Self, max: Self) -> Self;
+}
+
+impl Num for Fix {
+    const ZERO: Self = Fix::ZERO;
+    const ONE: Self = Fix::ONE;
+    fn from_i32(v: i32) -> Self {
+        Fix::from_int(v)
+    }
+    fn to_fix(self) -> Fix {
+        self
+    }
+    fn clamp(self, min: Self, max: Self) -> Self {
+        Fix::clamp(self, min, max)
+    }
+}
+
+impl Num for f32 {
+    const ZERO: Self = 0.0;
+    const ONE: Self = 1.0;
+    fn from_i32(v: i32) -> Self {
+        v as f32
+    }
+    fn to_fix(self) -> Fix {
+        Fix::from_f32(self)
+    }
+    fn clamp(self, min: Self, max: Self) -> Self {
+        f32::clamp(self, min, max)
+    }
+}
+
+// First order low pass, alpha = 1 disables filtering
+pub struct LowPass<T> {
+    alpha: T,
+    value: T,
+}
+
+impl<T: Num> LowPass<T> {
+    pub const fn new(alpha: T) -> Self {
+        LowPass {
+            alpha,
+            value: T::ZERO,
+        }
+    }
+
+    // Starts the filter at a known value instead of zero
+    pub fn set(&mut self, value: T) {
+        self.value = value;
+    }
+
+    pub fn update(&mut self, input: T) -> T {
+        self.value = self.value + self.alpha * (input - self.value);
+        self.value
+    }
+}
+
+// Gains are per servo frame (20 ms), the output is a steering command in -1..1
+pub struct Pid<T> {
+    kp: T,
+    ki: T,
+    kd: T,
+    integral: T,
+    last_error: T,
+}
+
+impl<T: Num> Pid<T> {
+    pub const fn new(kp: T, ki: T, kd: T) -> Self {
+        Pid {
+            kp,
+            ki,
+            kd,
+            integral: T::ZERO,
+            last_error: T::ZERO,
+        }
+    }
+
+    // Keeps the integral, so gains can be tuned while driving
+    pub fn set_gains(&mut self, kp: T, ki: T, kd: T) {
+        self.kp = kp;
+        self.ki = ki;
+        self.kd = kd;
+    }
+
+    pub fn reset(&mut self) {
+        self.integral = T::ZERO;
+        self.last_error = T::ZERO;
+    }
+
+    pub fn update(&mut self, error: T) -> T {
+        // Clamping the integral term keeps it from winding up while the car is lost
+        self.integral = (self.integral + self.ki * error).clamp(-T::ONE, T::ONE);
+        let derivative = error - self.last_error;
+        self.last_error = error;
+        (self.kp * error + self.integral + self.kd * derivative).clamp(-T::ONE, T::ONE)
+    }
+}
+
+// Second order (jerk) limiter for the steering command: the output moves
+// towards the input by at most max_rate per frame, and its rate changes by at
+// most max_accel per frame, so a bang-bang flip becomes an S-shaped ramp
+// instead of a jump. It brakes early enough to stop on the input. Zero limits
+// pass the input through.
+pub struct JerkLimiter<T> {
+    max_rate: T,
+    max_accel: T,
+    value: T,
+    rate: T,
+}
+
+impl<T: Num> JerkLimiter<T> {
+    pub const fn new(max_rate: T, max_accel: T) -> Self {
+        JerkLimiter {
+            max_rate,
+            max_accel,
+            value: T::ZERO,
+            rate: T::ZERO,
+        }
+    }
+
+    pub fn set_limits(&mut self, max_rate: T, max_accel: T) {
+        self.max_rate = max_rate;
+        self.max_accel = max_accel;
+    }
+
+    pub fn update(&mut self, input: T) -> T {
+        let error = input - self.value;
+        let distance = if error < T::ZERO { -error } else { error };
+        let speed = if self.rate < T::ZERO {
+            -self.rate
+        } else {
+            self.rate
+        };
+        let unlimited = self.max_rate <= T::ZERO || self.max_accel <= T::ZERO;
+        // Settle on the input instead of oscillating around it
+        if unlimited || (distance <= self.max_accel && speed <= self.max_accel) {
+            self.value = input;
+            self.rate = T::ZERO;
+            return input;
+        }
+        let approaching = (error > T::ZERO) == (self.rate > T::ZERO);
+        // Braking from speed s covers about s (s + max_accel) / (2 max_accel),
+        // checked one frame ahead so the output doesn't overshoot
+        let braking = approaching
+            && speed * (speed + self.max_accel)
+                >= (self.max_accel + self.max_accel) * (distance - speed);
+        let wanted = if braking {
+            T::ZERO
+        } else if error > T::ZERO {
+            self.max_rate
+        } else {
+            -self.max_rate
+        };
+        self.rate = self.rate + (wanted - self.rate).clamp(-self.max_accel, self.max_accel);
+        self.value = self.value + self.rate;
+        self.value
+    }
+}
@@ -0,0 +1,47 @@
+// Several cars on one track, each with the `marker` add-on and the radio in
+// the same group. While line following every car broadcasts its track
+// segment, counted from the lap mark (markers.rs), and the time it has been
+// in it. A car that hears another one in its own segment that entered it at
+// most GAP_MS before itself has that car just ahead and drives at SLOW of
+// its speed. It speeds up again once it heard nothing of the kind for
+// HOLD_MS: the car ahead left the segment or stopped.
+
+use crate::fixed::Fix;
+
+// A position broadcast every 100 ms
+pub const SEND_FRAMES: u32 = 5;
+const GAP_MS: u32 = 1500;
+const HOLD_MS: u32 = 500;
+const SLOW: Fix = Fix::from_f32(0.4);
+
+pub struct Convoy {
+    // When a car was last heard just ahead
+    ahead_ms: Option<u32>,
+}
+
+impl Convoy {
+    pub const fn new() -> Self {
+        Convoy { ahead_ms: None }
+    }
+
+    // A broadcast of another car, compared with this car's segment and time
+    // in it
+    pub fn heard(&mut self, other: (u8, u16), own: (u8, u32), now_ms: u32) {
+        let (segment, other_ms) = (other.0, other.1 as u32);
+        let (own_segment, own_ms) = own;
+        if segment == own_segment && other_ms >= own_ms && other_ms - own_ms <= GAP_MS {
+            self.ahead_ms = Some(now_ms);
+        }
+    }
+
+    // Speed factor for the line following
+    pub fn speed(&mut self, now_ms: u32) -> Fix {
+        match self.ahead_ms {
+            Some(ms) if now_ms.wrapping_sub(ms) <= HOLD_MS => SLOW,
+            _ => {
+                self.ahead_ms = None;
+                Fix::ONE
+            }
+        }
+    }
+}
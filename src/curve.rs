@@ -0,0 +1,152 @@
+// Pulse to speed curves of the two servos. Cheap continuous rotation servos
+// are far from linear, with a wide dead zone around the center pulse, and
+// no two are alike, so the same pulse turns the wheels at different speeds
+// and the car drifts off a straight line. `curve <left|right>` on the
+// console measures one servo with the wheel encoder (encoder.rs, so V2
+// only) on its wheel, the car lifted: the wheel runs at POINTS pulse widths
+// from PULSE_MIN to PULSE_MAX and the speed at each is saved to flash.
+// Once both servos are measured, drive.rs inverts the curves: a wheel speed
+// of -1..1 is a fraction of the top speed both servos reach in both
+// directions, and the pulse is interpolated between the measured points
+// around that speed. `curve clear` returns to the linear mapping.
+
+use crate::drive::{PULSE_CENTER, PULSE_MIN, PULSE_SPAN};
+use crate::fixed::Fix;
+use crate::flash::{Flash, Page};
+use crate::mixer::Wheels;
+
+pub const POINTS: usize = 9;
+const STEP_US: u32 = 2 * PULSE_SPAN / (POINTS as u32 - 1);
+const CENTER: usize = POINTS / 2;
+
+const MAGIC: u32 = 0x5242_4356; // "RBCV"
+
+// The magic, the measured servos and two curves at two speeds per word
+const WORDS: usize = 2 + 2 * POINTS / 2;
+
+#[derive(Clone, Copy)]
+pub enum Servo {
+    Left,
+    Right,
+}
+
+pub const fn point_pulse(point: usize) -> u32 {
+    PULSE_MIN + point as u32 * STEP_US
+}
+
+// Wheel speeds in mm/s at the pulses of the points, positive above the
+// center pulse
+#[derive(Clone, Copy)]
+pub struct Curve {
+    speeds: [i16; POINTS],
+}
+
+impl Curve {
+    // Measured with the encoder's own sign, which depends on how it is
+    // mounted
+    #[cfg(feature = "v2")]
+    pub fn new(mut speeds: [i16; POINTS]) -> Self {
+        if speeds[POINTS - 1] < speeds[0] {
+            speeds = speeds.map(|speed| speed.saturating_neg());
+        }
+        Curve { speeds }
+    }
+
+    // The slower direction's top speed
+    fn top(&self) -> i16 {
+        self.speeds[POINTS - 1].min(self.speeds[0].saturating_neg())
+    }
+
+    // Pulse for the speed, searched outwards from the center so the dead
+    // zone is crossed in one step
+    fn pulse(&self, mm_s: i32) -> u32 {
+        if mm_s == 0 {
+            return PULSE_CENTER;
+        }
+        let upwards = mm_s > 0;
+        let mut point = CENTER;
+        loop {
+            let next = match upwards {
+                true if point < POINTS - 1 => point + 1,
+                false if point > 0 => point - 1,
+                _ => return point_pulse(point),
+            };
+            let (from, to) = (self.speeds[point] as i32, self.speeds[next] as i32);
+            let reached = if upwards { to >= mm_s } else { to <= mm_s };
+            if reached {
+                // Clamped for a servo that already turns at the center pulse
+                let offset = match to - from {
+                    0 => 0,
+                    span => ((mm_s - from) * STEP_US as i32 / span).clamp(0, STEP_US as i32),
+                } as u32;
+                return match upwards {
+                    true => point_pulse(point) + offset,
+                    false => point_pulse(point) - offset,
+                };
+            }
+            point = next;
+        }
+    }
+}
+
+// Both servos measured
+#[derive(Clone, Copy)]
+pub struct Curves {
+    left: Curve,
+    right: Curve,
+    top: i16,
+}
+
+impl Curves {
+    pub fn new(left: Curve, right: Curve) -> Self {
+        let top = left.top().min(right.top()).max(1);
+        Curves { left, right, top }
+    }
+
+    // Left and right pulse, the right servo is mounted mirrored
+    pub fn pulses(&self, wheels: Wheels) -> (u32, u32) {
+        let mm_s = |speed: Fix| (speed * Fix::from_int(self.top as i32)).to_int();
+        (
+            self.left.pulse(mm_s(wheels.left)),
+            self.right.pulse(-mm_s(wheels.right)),
+        )
+    }
+}
+
+// None until both servos are measured
+pub fn both(curves: &[Option<Curve>; 2]) -> Option<Curves> {
+    match curves {
+        [Some(left), Some(right)] => Some(Curves::new(*left, *right)),
+        _ => None,
+    }
+}
+
+// A bit per measured servo in the second word
+pub fn load(flash: &Flash) -> [Option<Curve>; 2] {
+    let mut words = [0u32; WORDS];
+    flash.read(Page::Curves, &mut words);
+    if words[0] != MAGIC {
+        return [None, None];
+    }
+    core::array::from_fn(|servo| {
+        let speeds = core::array::from_fn(|point| {
+            let index = servo * POINTS + point;
+            (words[2 + index / 2] >> (index % 2 * 16)) as i16
+        });
+        (words[1] & 1 << servo != 0).then_some(Curve { speeds })
+    })
+}
+
+pub fn save(flash: &mut Flash, curves: &[Option<Curve>; 2]) {
+    let mut words = [0u32; WORDS];
+    words[0] = MAGIC;
+    for (servo, curve) in curves.iter().enumerate() {
+        let Some(curve) = curve else { continue };
+        words[1] |= 1 << servo;
+        for (point, speed) in curve.speeds.iter().enumerate() {
+            let index = servo * POINTS + point;
+            words[2 + index / 2] |= (*speed as u16 as u32) << (index % 2 * 16);
+        }
+    }
+    flash.write(Page::Curves, &words);
+}
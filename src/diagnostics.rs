@@ -0,0 +1,104 @@
+// Self test at boot. A failed check keeps the car from arming: the display
+// and the LEDs blink its fault code instead of showing the state, and the
+// check is logged over defmt and the serial port.
+//
+// The arming interlock repeats the photocell and battery checks on every
+// start, stricter, and a failed one only refuses that start: the display
+// shows the cross and the fault code once, and the check is logged.
+//
+// Fault codes, counted in blinks of the cross:
+//   1 photocell reading out of range
+//   2 flash config corrupt, the defaults are used until it is saved again
+//   3 battery low
+//   4 configured I2C device missing
+//   5 servo timer not running
+//   6 panic, blinked by the panic handler until the reset (errlog.rs)
+//   7 no sensor, the photocell input floats (interlock only, floating.rs)
+
+// Readings stuck at either end of the 10 bit range mean an open or shorted
+// photocell
+const PHOTO_CELL_MIN: i16 = 2;
+const PHOTO_CELL_MAX: i16 = 1021;
+// Below this the servos stall and the radio gets unreliable
+const BATTERY_MIN_MV: u16 = 2700;
+// 0.2 s on and off per blink, 1 s pause before the code repeats
+const BLINK_FRAMES: u32 = 10;
+const PAUSE_FRAMES: u32 = 50;
+pub const PANIC_CODE: u32 = 6;
+
+#[derive(Clone, Copy)]
+pub enum Fault {
+    Adc,
+    Config,
+    Battery,
+    I2c,
+    ServoTimer,
+    NoSensor,
+}
+
+impl Fault {
+    pub fn name(self) -> &'static str {
+        match self {
+            Fault::Adc => "adc",
+            Fault::Config => "config",
+            Fault::Battery => "battery",
+            Fault::I2c => "i2c",
+            Fault::ServoTimer => "servo",
+            Fault::NoSensor => "sensor",
+        }
+    }
+
+    pub fn code(self) -> u32 {
+        match self {
+            // After the panic code
+            Fault::NoSensor => PANIC_CODE + 1,
+            fault => fault as u32 + 1,
+        }
+    }
+
+    pub fn blink_on(self, frame: u32) -> bool {
+        blink_on(self.code(), frame)
+    }
+}
+
+// True while the blink code is lit at this frame
+pub fn blink_on(code: u32, frame: u32) -> bool {
+    let blinks = code * 2 * BLINK_FRAMES;
+    let phase = frame % (blinks + PAUSE_FRAMES);
+    phase < blinks && phase % (2 * BLINK_FRAMES) < BLINK_FRAMES
+}
+
+pub fn check_photo_cell(reading: i16) -> Result<(), Fault> {
+    match reading {
+        PHOTO_CELL_MIN..=PHOTO_CELL_MAX => Ok(()),
+        _ => Err(Fault::Adc),
+    }
+}
+
+pub fn check_battery(mv: u16) -> Result<(), Fault> {
+    match mv {
+        BATTERY_MIN_MV.. => Ok(()),
+        _ => Err(Fault::Battery),
+    }
+}
+
+// A reading within half the calibrated span around the bands, the sensor
+// sees one of the surfaces or the line edge. Outside it's misaligned, lifted
+// or not calibrated for this track. The battery check is the same as at
+// boot, the supply sags under load during a run.
+pub fn check_arming(reading: i16, thresholds: &[i16; 3], mv: u16) -> Result<(), Fault> {
+    let (low, high) = (thresholds[0] as i32, thresholds[2] as i32);
+    let margin = (high - low) / 2;
+    if !(low - margin..=high + margin).contains(&(reading as i32)) {
+        return Err(Fault::Adc);
+    }
+    check_battery(mv)
+}
+
+// found is the bitmask from i2c::Bus::scan
+pub fn check_i2c(address: u8, found: u128) -> Result<(), Fault> {
+    match found & 1 << address {
+        0 => Err(Fault::I2c),
+        _ => Ok(()),
+    }
+}
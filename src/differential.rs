@@ -0,0 +1,75 @@
+// Differential line following with a second photocell (`set sensors 2`).
+// The two sensors sit side by side across the line edge, the first on P0
+// on the left and the second on edge pin 4 on the right. Instead of
+// classifying one absolute reading against the calibrated bands, the car
+// steers on the difference of the two, divided by their sum: a change of
+// the ambient light or of the surface moves both readings alike and
+// cancels out, and only the line edge moving under the sensors steers.
+//
+// The big pads are taken by the first photocell and the servos, edge pin 4
+// is a display column with an analog input. While the second sensor is on
+// the pin is switched to an input and that column stays dark, as with
+// manual mode on P3. The conversion runs on the spot right after the
+// frame's photocell sample, see sampler.rs.
+
+use crate::pins::port;
+use crate::sampler;
+
+// Edge pin 4 is P0.05, AIN6 on the V1 and P0.28, AIN4 on the V2
+#[cfg(feature = "v1")]
+pub const GPIO: usize = 5;
+#[cfg(feature = "v1")]
+const AIN: u8 = 6;
+#[cfg(feature = "v2")]
+pub const GPIO: usize = 28;
+#[cfg(feature = "v2")]
+const AIN: u8 = 4;
+
+// Input with the input buffer disconnected, for an analog pin
+const INPUT_ANALOG: u32 = 1 << 1;
+
+// Only the pin config changes, the display writes the output levels only
+pub struct RightSensor {
+    // The display's pin config, restored on leave
+    pin_cnf: u32,
+}
+
+impl RightSensor {
+    pub fn enter() -> Self {
+        let pin_cnf = port().pin_cnf[GPIO].read().bits();
+        port().pin_cnf[GPIO].write(|w| unsafe { w.bits(INPUT_ANALOG) });
+        RightSensor { pin_cnf }
+    }
+
+    pub fn leave(self) {
+        port().pin_cnf[GPIO].write(|w| unsafe { w.bits(self.pin_cnf) });
+    }
+
+    pub fn convert(&self) -> Result<i16, ()> {
+        sampler::convert(AIN)
+    }
+}
+
+// 0 left, 1 forward, 2 back and 3 right, the bands of the classifier
+fn band(reading: i16, thresholds: &[i16; 3]) -> usize {
+    thresholds
+        .iter()
+        .take_while(|&&threshold| reading > threshold)
+        .count()
+}
+
+// Both readings all dark or both all bright, past the end of the tape. One
+// dark and one bright is the line right between the sensors.
+pub fn lost(left: i16, right: i16, thresholds: &[i16; 3]) -> bool {
+    let (left, right) = (band(left, thresholds), band(right, thresholds));
+    left == right && (left == 0 || left == 3)
+}
+
+// Positive when the left sensor reads darker, the line is to the left. The
+// normalized difference is scaled to the span of the bands, so the PID
+// gains and the deadband tuned for one sensor stay in the same range.
+pub fn error(left: i16, right: i16, thresholds: &[i16; 3]) -> i32 {
+    let span = (thresholds[2] - thresholds[0]) as i32;
+    let sum = (left as i32 + right as i32).max(1);
+    (right as i32 - left as i32) * span / sum
+}
@@ -0,0 +1,223 @@
+// Normalized drive interface for the two continuous rotation servos.
+// Throttle and steering are given in -1.0..1.0 and converted here to servo
+// pulse widths in microseconds, so nothing above this module handles raw CC
+// register values. ticks and micros convert at the Timer0 registers.
+
+use crate::curve::Curves;
+#[cfg(feature = "v2")]
+use crate::curve::Servo;
+use crate::fixed::Fix;
+use crate::mixer::{Mixer, Wheels};
+
+// Servo duty cycle is from 0.5 ms to 2.5 ms with 1.5 ms for center position
+pub const PULSE_CENTER: u32 = 1500;
+pub const PULSE_SPAN: u32 = 1000;
+pub const PULSE_MIN: u32 = PULSE_CENTER - PULSE_SPAN;
+pub const PULSE_MAX: u32 = PULSE_CENTER + PULSE_SPAN;
+
+// Timer0 counts 16 MHz / 2^PRESCALER, the default 4 is 1 MHz, 1 us pulse
+// steps. Lower prescalers give finer steps and shorten the longest period
+// the counter holds, higher ones run the timer slower for less power, with
+// steps of several us. BITMODE selects the counter width: 0 16, 1 8, 2 24
+// and 3 32 bit. The checks below fail the build for a setting the CC math
+// doesn't fit.
+pub const PRESCALER: u32 = 4;
+pub const BITMODE: u32 = 0;
+// Pulse steps across the span, coarser steps turn into steering noise
+const MIN_STEPS: u32 = 125;
+
+// Timer0 ticks of a duration in us, rounded down
+pub const fn ticks(us: u32) -> u32 {
+    (us << 4) >> PRESCALER
+}
+
+pub const fn micros(ticks: u32) -> u32 {
+    (ticks << PRESCALER) >> 4
+}
+
+const fn whole_ticks(us: u32) -> bool {
+    micros(ticks(us)) == us
+}
+
+const COUNTER_MAX: u32 = match BITMODE {
+    0 => u16::MAX as u32,
+    1 => u8::MAX as u32,
+    2 => (1 << 24) - 1,
+    _ => u32::MAX,
+};
+
+const _: () = assert!(
+    PRESCALER <= 9 && matches!(BITMODE, 0..=3),
+    "no such Timer0 setting"
+);
+const _: () = assert!(
+    ticks(FRAME_US) <= COUNTER_MAX,
+    "the servo frame overflows the Timer0 counter"
+);
+const _: () = assert!(
+    whole_ticks(PULSE_MIN) && whole_ticks(PULSE_CENTER) && whole_ticks(PULSE_MAX),
+    "the servo pulses aren't whole Timer0 ticks"
+);
+const _: () = assert!(
+    ticks(PULSE_SPAN) >= MIN_STEPS,
+    "Timer0 pulse steps are too coarse"
+);
+
+// Digital servos take frames faster than 50 Hz. The control frame is split
+// into servo periods then, each long enough for the longest pulse and
+// a quiet gap after it for the reload and the photocell conversion.
+const MIN_GAP_US: u32 = 500;
+pub const FRAME_US: u32 = 20_000;
+
+// A speed drop of more than this within one frame, or to standstill, counts as
+// braking and lights the brake light for BRAKE_FRAMES. Smaller drops, like
+// slowing down for a turn, don't.
+const BRAKE_DROP: Fix = Fix::from_f32(0.5);
+const BRAKE_FRAMES: u8 = 25;
+
+// Rough continuous servo wheel speed at full throttle, 20 cm/s, per frame,
+// and the distance between the wheels
+pub const CM_PER_FRAME: Fix = Fix::from_f32(0.4);
+pub const TRACK_CM: i32 = 10;
+
+// Time the two CC writes of a reload take, with room to spare
+const COMMIT_MARGIN_US: u32 = 20;
+
+// Drive is the shadow of the CC1 and CC2 registers: the main loop stages
+// pulse widths in it any time, the servo interrupt commits them at the frame
+// start. A commit is only safe while the edges of both the running and the
+// staged pulses are still ahead of the timer. A late one would drop or double
+// a toggle of the GPIOTE output and invert the servo signal for good, so the
+// staged pulses wait for the next frame instead.
+pub fn can_commit(timer_us: u32, current: [u32; 2], staged: [u32; 2]) -> bool {
+    let first_edge = current.into_iter().chain(staged).min().unwrap_or(0);
+    timer_us + COMMIT_MARGIN_US < first_edge
+}
+
+// Servo periods per frame for a servo rate, None for a rate that isn't a
+// multiple of 50 Hz or leaves no room after the longest pulse. 300 Hz is
+// the fastest.
+pub fn frame_periods(hz: u16) -> Option<u8> {
+    const FRAME_HZ: u32 = 1_000_000 / FRAME_US;
+    if hz == 0 || !(hz as u32).is_multiple_of(FRAME_HZ) {
+        return None;
+    }
+    let periods = u8::try_from(hz as u32 / FRAME_HZ).ok()?;
+    period_us(periods).map(|_| periods)
+}
+
+pub fn period_us(periods: u8) -> Option<u32> {
+    let period_us = FRAME_US.checked_div(periods as u32)?;
+    (period_us >= PULSE_MAX + MIN_GAP_US).then_some(period_us)
+}
+
+// A chassis backend, takes the mixed wheel speeds once per frame. Drive
+// itself is the one of the servos, hbridge.rs the one of DC motors.
+pub trait MotorDriver {
+    fn set_wheels(&mut self, wheels: Wheels);
+}
+
+pub struct Drive {
+    lpulse: u32,
+    rpulse: u32,
+    wheels: Wheels,
+    forward: Fix,
+    brake_frames: u8,
+    mixer: Mixer,
+    curves: Option<Curves>,
+}
+
+impl Drive {
+    pub const fn new() -> Self {
+        Drive {
+            lpulse: PULSE_CENTER,
+            rpulse: PULSE_CENTER,
+            wheels: Wheels {
+                left: Fix::ZERO,
+                right: Fix::ZERO,
+            },
+            forward: Fix::ZERO,
+            brake_frames: 0,
+            mixer: Mixer::new(),
+            curves: None,
+        }
+    }
+
+    // Measured servo curves, None maps the wheel speeds linearly
+    pub fn set_curves(&mut self, curves: Option<Curves>) {
+        self.curves = curves;
+    }
+
+    // A raw pulse width for one servo, until the next set
+    #[cfg(feature = "v2")]
+    pub fn set_pulse(&mut self, servo: Servo, pulse: u32) {
+        match servo {
+            Servo::Left => self.lpulse = pulse,
+            Servo::Right => self.rpulse = pulse,
+        }
+    }
+
+    // See Mixer::set_tight_turn
+    pub fn set_tight_turn(&mut self, threshold: Option<Fix>) {
+        self.mixer.set_tight_turn(threshold);
+    }
+
+    // See Mixer::set_trim
+    pub fn set_trim(&mut self, trim: Fix) {
+        self.mixer.set_trim(trim);
+    }
+
+    // Positive throttle drives forward, positive steering turns towards
+    // CarState::Left. Called once per frame.
+    pub fn set(&mut self, throttle: Fix, steering: Fix) {
+        let wheels = self.mixer.mix(throttle, steering);
+        self.set_wheels(wheels);
+        let forward = wheels.forward();
+        let (speed, last_speed) = (forward.abs(), self.forward.abs());
+        if speed < last_speed && (speed == Fix::ZERO || last_speed - speed > BRAKE_DROP) {
+            self.brake_frames = BRAKE_FRAMES;
+        } else {
+            self.brake_frames = self.brake_frames.saturating_sub(1);
+        }
+        self.forward = forward;
+    }
+
+    // For the other chassis backends, only the V2 has one
+    #[cfg(feature = "v2")]
+    pub fn wheels(&self) -> Wheels {
+        self.wheels
+    }
+
+    pub fn lpulse(&self) -> u32 {
+        self.lpulse
+    }
+
+    pub fn rpulse(&self) -> u32 {
+        self.rpulse
+    }
+
+    // Forward speed of the car after mixing, -1..1
+    pub fn forward(&self) -> Fix {
+        self.forward
+    }
+
+    pub fn braking(&self) -> bool {
+        self.brake_frames > 0
+    }
+}
+
+impl MotorDriver for Drive {
+    fn set_wheels(&mut self, wheels: Wheels) {
+        // The servos are mounted mirrored, so forward is a long pulse on the
+        // left wheel and a short pulse on the right wheel.
+        (self.lpulse, self.rpulse) = match &self.curves {
+            Some(curves) => curves.pulses(wheels),
+            None => (to_pulse(wheels.left), to_pulse(-wheels.right)),
+        };
+        self.wheels = wheels;
+    }
+}
+
+fn to_pulse(speed: Fix) -> u32 {
+    (Fix::from_int(PULSE_CENTER as i32) + speed * Fix::from_int(PULSE_SPAN as i32)).to_int() as u32
+}
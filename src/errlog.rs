@@ -0,0 +1,143 @@
+// Ring of the most recent errors in RAM that the startup code doesn't
+// initialize, so it survives soft resets but not a power cycle. Panics
+// follow the panic policy after recording, hard faults record and reset, and
+// main prints the log at the next boot.
+
+use core::cell::RefCell;
+use core::mem::MaybeUninit;
+use core::panic::PanicInfo;
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{self, Ordering};
+
+use cortex_m::interrupt::Mutex;
+use cortex_m_rt::{exception, ExceptionFrame};
+
+const MAGIC: u32 = 0x5242_4c47; // "RBLG"
+const RECORDS: usize = 8;
+const FILE_LEN: usize = 12;
+
+// What a panic does after it is recorded. Halting leaves the servos
+// running on their last pulses, useful with a debugger on the bench only.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PanicPolicy {
+    Halt,
+    // Stop the servos and blink fault code 6 until a reset
+    Blink,
+    // Stop and blink, then reset after the seconds
+    Reset(u16),
+}
+
+// Set from the config by main, panics before that blink
+static POLICY: Mutex<RefCell<PanicPolicy>> = Mutex::new(RefCell::new(PanicPolicy::Blink));
+
+pub fn set_panic_policy(policy: PanicPolicy) {
+    cortex_m::interrupt::free(|cs| *POLICY.borrow(cs).borrow_mut() = policy);
+}
+
+#[derive(Clone, Copy)]
+pub enum Kind {
+    Panic = 1,
+    HardFault = 2,
+    SelfTest = 3,
+}
+
+impl Kind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Panic => "panic",
+            Kind::HardFault => "hardfault",
+            Kind::SelfTest => "fault",
+        }
+    }
+}
+
+// Plain integers, any leftover RAM contents are a valid Record
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct Record {
+    kind: u32,
+    // Panic line, hard fault PC or self test fault code
+    pub value: u32,
+    // End of the source file name of a panic
+    file: [u8; FILE_LEN],
+}
+
+impl Record {
+    pub fn kind(&self) -> Option<Kind> {
+        match self.kind {
+            1 => Some(Kind::Panic),
+            2 => Some(Kind::HardFault),
+            3 => Some(Kind::SelfTest),
+            _ => None,
+        }
+    }
+
+    pub fn file(&self) -> &str {
+        let len = self.file.iter().position(|b| *b == 0).unwrap_or(FILE_LEN);
+        core::str::from_utf8(&self.file[..len]).unwrap_or("")
+    }
+}
+
+#[repr(C)]
+struct Log {
+    magic: u32,
+    // Records written in total, the next one goes to count % RECORDS
+    count: u32,
+    records: [Record; RECORDS],
+}
+
+#[link_section = ".uninit.errlog"]
+static mut LOG: MaybeUninit<Log> = MaybeUninit::uninit();
+
+fn log() -> &'static mut Log {
+    // Only used from the main loop and the fault handlers, which don't return
+    // to it
+    let log = unsafe { &mut *addr_of_mut!(LOG).cast::<Log>() };
+    if log.magic != MAGIC {
+        log.magic = MAGIC;
+        log.count = 0;
+    }
+    log
+}
+
+pub fn push(kind: Kind, value: u32, file: &str) {
+    let log = log();
+    let mut record = Record {
+        kind: kind as u32,
+        value,
+        file: [0; FILE_LEN],
+    };
+    let tail = &file.as_bytes()[file.len().saturating_sub(FILE_LEN)..];
+    record.file[..tail.len()].copy_from_slice(tail);
+    log.records[log.count as usize % RECORDS] = record;
+    log.count = log.count.wrapping_add(1);
+}
+
+// Oldest first
+pub fn records() -> impl Iterator<Item = Record> {
+    let log = log();
+    let len = (log.count as usize).min(RECORDS);
+    let first = log.count as usize - len;
+    (first..first + len).map(move |i| log.records[i % RECORDS])
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let (file, line) = info
+        .location()
+        .map_or(("", 0), |location| (location.file(), location.line()));
+    push(Kind::Panic, line, file);
+    let policy = cortex_m::interrupt::free(|cs| *POLICY.borrow(cs).borrow());
+    if policy != PanicPolicy::Halt {
+        crate::panic_stop(policy);
+    }
+    loop {
+        atomic::compiler_fence(Ordering::SeqCst);
+    }
+}
+
+#[exception]
+unsafe fn HardFault(frame: &ExceptionFrame) -> ! {
+    push(Kind::HardFault, frame.pc(), "");
+    cortex_m::peripheral::SCB::sys_reset()
+}
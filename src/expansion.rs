@@ -0,0 +1,128 @@
+// Registry of the spare edge connector pins. Add-ons get their pin from the
+// flash config and claim it here at boot, so two add-ons configured on the
+// same pin can't both drive it: the second claim fails and that add-on stays
+// disabled.
+
+use microbit::hal::gpio::{Disconnected, Pin};
+
+pub const SPARES: usize = 6;
+pub const ADDONS: usize = 15;
+
+// Edge connector pins not used by the car itself
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Spare {
+    P8,
+    P12,
+    P13,
+    P14,
+    P15,
+    P16,
+}
+
+impl Spare {
+    const ALL: [Spare; SPARES] = [
+        Spare::P8,
+        Spare::P12,
+        Spare::P13,
+        Spare::P14,
+        Spare::P15,
+        Spare::P16,
+    ];
+
+    pub fn from_index(index: u8) -> Option<Spare> {
+        Spare::ALL.get(index as usize).copied()
+    }
+
+    // Edge connector pin number
+    pub fn from_number(number: u8) -> Option<Spare> {
+        Spare::ALL
+            .into_iter()
+            .find(|spare| spare.number() == number)
+    }
+
+    pub fn number(self) -> u8 {
+        match self {
+            Spare::P8 => 8,
+            Spare::P12 => 12,
+            Spare::P13 => 13,
+            Spare::P14 => 14,
+            Spare::P15 => 15,
+            Spare::P16 => 16,
+        }
+    }
+}
+
+// Optional functions that need a spare pin, the index into Config::addons
+#[derive(Clone, Copy)]
+pub enum Addon {
+    SerialTx,
+    SerialRx,
+    Leds,
+    Headlight,
+    Buzzer,
+    Marker,
+    // Grove modules, see grove.rs
+    LineFinder,
+    Ultrasonic,
+    // Servo of the scanning sensor head, see scanhead.rs
+    Pan,
+    // DC motor H-bridge, see hbridge.rs
+    PwmLeft,
+    PwmRight,
+    DirLeft,
+    DirRight,
+    // Wheel encoder, see encoder.rs
+    EncoderA,
+    EncoderB,
+}
+
+impl Addon {
+    pub fn from_name(name: &str) -> Option<Addon> {
+        match name {
+            "tx" => Some(Addon::SerialTx),
+            "rx" => Some(Addon::SerialRx),
+            "leds" => Some(Addon::Leds),
+            "headlight" => Some(Addon::Headlight),
+            "buzzer" => Some(Addon::Buzzer),
+            "marker" => Some(Addon::Marker),
+            "linefinder" => Some(Addon::LineFinder),
+            "ultrasonic" => Some(Addon::Ultrasonic),
+            "pan" => Some(Addon::Pan),
+            "pwm_left" => Some(Addon::PwmLeft),
+            "pwm_right" => Some(Addon::PwmRight),
+            "dir_left" => Some(Addon::DirLeft),
+            "dir_right" => Some(Addon::DirRight),
+            "encoder_a" => Some(Addon::EncoderA),
+            "encoder_b" => Some(Addon::EncoderB),
+            _ => None,
+        }
+    }
+}
+
+pub struct Expansion {
+    pins: [Option<Pin<Disconnected>>; SPARES],
+}
+
+impl Expansion {
+    // Pins in the order of Spare
+    pub fn new(pins: [Pin<Disconnected>; SPARES]) -> Self {
+        Expansion {
+            pins: pins.map(Some),
+        }
+    }
+
+    // The pin assigned to the add-on, None when it is not assigned or the pin
+    // has already been claimed
+    pub fn claim(
+        &mut self,
+        addons: &[Option<Spare>; ADDONS],
+        addon: Addon,
+    ) -> Option<Pin<Disconnected>> {
+        let spare = addons[addon as usize]?;
+        let pin = self.pins[spare as usize].take();
+        if pin.is_none() {
+            defmt::warn!("P{} is already in use", spare.number());
+        }
+        pin
+    }
+}
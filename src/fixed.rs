@@ -0,0 +1,81 @@
+// Q16.16 fixed-point numbers for the control path.
+// The micro:bit V1 has no FPU, and soft-float routines make the interrupt
+// time depend on the operands. Fixed-point keeps every step a few integer
+// instructions on both boards.
+
+use core::ops::{Add, Div, Mul, Neg, Sub};
+
+const FRAC_BITS: u32 = 16;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Fix(i32);
+
+impl Fix {
+    pub const ZERO: Fix = Fix(0);
+    pub const ONE: Fix = Fix(1 << FRAC_BITS);
+
+    pub const fn from_int(v: i32) -> Self {
+        Fix(v << FRAC_BITS)
+    }
+
+    // Mostly for constants, so gains can be written naturally
+    pub const fn from_f32(v: f32) -> Self {
+        Fix((v * (1 << FRAC_BITS) as f32) as i32)
+    }
+
+    pub const fn to_int(self) -> i32 {
+        self.0 >> FRAC_BITS
+    }
+
+    pub fn abs(self) -> Self {
+        Fix(self.0.saturating_abs())
+    }
+
+    pub fn clamp(self, min: Fix, max: Fix) -> Self {
+        Fix(self.0.clamp(min.0, max.0))
+    }
+}
+
+fn saturate(v: i64) -> i32 {
+    v.clamp(i32::MIN as i64, i32::MAX as i64) as i32
+}
+
+impl Add for Fix {
+    type Output = Fix;
+    fn add(self, rhs: Fix) -> Fix {
+        Fix(self.0.saturating_add(rhs.0))
+    }
+}
+
+impl Sub for Fix {
+    type Output = Fix;
+    fn sub(self, rhs: Fix) -> Fix {
+        Fix(self.0.saturating_sub(rhs.0))
+    }
+}
+
+impl Neg for Fix {
+    type Output = Fix;
+    fn neg(self) -> Fix {
+        Fix(self.0.saturating_neg())
+    }
+}
+
+impl Mul for Fix {
+    type Output = Fix;
+    fn mul(self, rhs: Fix) -> Fix {
+        Fix(saturate((self.0 as i64 * rhs.0 as i64) >> FRAC_BITS))
+    }
+}
+
+// Division by zero saturates
+impl Div for Fix {
+    type Output = Fix;
+    fn div(self, rhs: Fix) -> Fix {
+        match rhs.0 {
+            0 if self.0 < 0 => Fix(i32::MIN),
+            0 => Fix(i32::MAX),
+            _ => Fix(saturate(((self.0 as i64) << FRAC_BITS) / rhs.0 as i64)),
+        }
+    }
+}
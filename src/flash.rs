@@ -0,0 +1,115 @@
+// Persistent storage in the last pages of the code flash.
+// The NVMC PAC is used directly as the nRF51 HAL has no flash driver.
+
+use core::ptr;
+
+use microbit::hal::pac::{FICR, NVMC};
+
+const NVMC_READ: u32 = 0;
+const NVMC_WRITE: u32 = 1;
+const NVMC_ERASE: u32 = 2;
+
+// Pages counted back from the end of the code flash, each user gets its own
+// so erasing one can't lose the others
+#[derive(Clone, Copy)]
+pub enum Page {
+    Config = 1,
+    Stats = 2,
+    Script = 3,
+    // Second slot of the config, see config.rs
+    ConfigB = 4,
+    // Boot count of the joystick transmitter
+    Transmitter = 5,
+    // Log of the odometer, see odometer.rs
+    Odometer = 6,
+    // Servo speed curves, see curve.rs
+    Curves = 7,
+}
+
+pub struct Flash {
+    nvmc: NVMC,
+    page_size: u32,
+    end: u32,
+}
+
+impl Flash {
+    pub fn new(nvmc: NVMC, ficr: &FICR) -> Self {
+        // 1 kB pages on the nRF51, 4 kB pages on the nRF52
+        let page_size = ficr.codepagesize.read().bits();
+        let pages = ficr.codesize.read().bits();
+        Flash {
+            nvmc,
+            page_size,
+            end: page_size * pages,
+        }
+    }
+
+    fn address(&self, page: Page) -> u32 {
+        self.end - self.page_size * page as u32
+    }
+
+    pub fn read(&self, page: Page, words: &mut [u32]) {
+        let address = self.address(page) as *const u32;
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = unsafe { ptr::read_volatile(address.add(i)) };
+        }
+    }
+
+    // Erases the page and writes words from its start
+    pub fn write(&mut self, page: Page, words: &[u32]) {
+        self.erase(page);
+        self.config(NVMC_WRITE);
+        for (i, word) in words.iter().enumerate() {
+            self.program(page, i, *word);
+        }
+        self.config(NVMC_READ);
+    }
+
+    // All words read 0xffff_ffff afterwards
+    pub fn erase(&mut self, page: Page) {
+        let address = self.address(page);
+        self.config(NVMC_ERASE);
+        self.nvmc.erasepage().write(|w| unsafe { w.bits(address) });
+        self.wait();
+        self.config(NVMC_READ);
+    }
+
+    // Writes one word of an erased page without the erase, for logs that
+    // fill a page word by word. Programming only clears bits.
+    pub fn write_word(&mut self, page: Page, index: usize, word: u32) {
+        self.config(NVMC_WRITE);
+        self.program(page, index, word);
+        self.config(NVMC_READ);
+    }
+
+    fn program(&mut self, page: Page, index: usize, word: u32) {
+        let address = self.address(page) as *mut u32;
+        unsafe { ptr::write_volatile(address.add(index), word) };
+        self.wait();
+    }
+
+    fn config(&mut self, mode: u32) {
+        self.nvmc.config.write(|w| unsafe { w.bits(mode) });
+        self.wait();
+    }
+
+    fn wait(&self) {
+        while self.nvmc.ready.read().bits() == 0 {}
+    }
+}
+
+// CRC-32 (IEEE) of the little endian bytes of words
+pub fn crc32(words: &[u32]) -> u32 {
+    let mut crc = !0u32;
+    for byte in words.iter().flat_map(|word| word.to_le_bytes()) {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                crc >> 1 ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
@@ -0,0 +1,33 @@
+// Unplugged sensor check for the arming interlock. A floating sensor
+// input picks up whatever is near, readings that look plausible, so the range
+// check passes and the car drives off on noise. Here the input is read once
+// with the internal pullup and once with the pulldown: about 13 kΩ move a
+// floating pin to the rails, the voltage divider of a connected photocell
+// (or a Grove module's output) holds it well inside.
+
+use crate::pins::port;
+
+const PULL_MASK: u32 = 3 << 2;
+const PULLDOWN: u32 = 1 << 2;
+const PULLUP: u32 = 3 << 2;
+// Pulled to within 2% of either rail
+const RAIL_LOW: i16 = 20;
+const RAIL_HIGH: i16 = 1003;
+// Time for the pin and a cable to charge, about 1 ms on the V1
+const SETTLE_CYCLES: u32 = 16_000;
+
+// True if the input on the GPIO pin follows both pulls, a failed conversion
+// doesn't count. The pin config is restored.
+pub fn floating(gpio: usize, mut convert: impl FnMut() -> Result<i16, ()>) -> bool {
+    let pin_cnf = &port().pin_cnf[gpio];
+    let saved = pin_cnf.read().bits();
+    let mut read_pulled = |pull: u32| {
+        pin_cnf.write(|w| unsafe { w.bits(saved & !PULL_MASK | pull) });
+        cortex_m::asm::delay(SETTLE_CYCLES);
+        convert()
+    };
+    let high = read_pulled(PULLUP);
+    let low = read_pulled(PULLDOWN);
+    pin_cnf.write(|w| unsafe { w.bits(saved) });
+    matches!((high, low), (Ok(high), Ok(low)) if high >= RAIL_HIGH && low <= RAIL_LOW)
+}
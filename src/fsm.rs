@@ -0,0 +1,131 @@
+// The run states of the car and the transitions between them, as driven by
+// the main loop. Main derives the state once per frame and checks every
+// change against the table, so the table can't drift from the code
+// unnoticed. `fsm` on the console dumps it as Graphviz DOT, render it with
+// `dot -Tsvg`.
+
+use core::fmt::Write;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RunState {
+    Boot,
+    Fault,
+    Idle,
+    // Waiting for a start at time
+    Armed,
+    Finished,
+    LineFollow,
+    Remote,
+    Maneuver,
+    Script,
+    // Pot steering, A throttle
+    Manual,
+}
+
+impl RunState {
+    pub fn name(self) -> &'static str {
+        match self {
+            RunState::Boot => "boot",
+            RunState::Fault => "fault",
+            RunState::Idle => "idle",
+            RunState::Armed => "armed",
+            RunState::Finished => "finished",
+            RunState::LineFollow => "line_follow",
+            RunState::Remote => "remote",
+            RunState::Maneuver => "maneuver",
+            RunState::Script => "script",
+            RunState::Manual => "manual",
+        }
+    }
+}
+
+struct Transition {
+    from: RunState,
+    to: RunState,
+    guard: &'static str,
+}
+
+const fn t(from: RunState, to: RunState, guard: &'static str) -> Transition {
+    Transition { from, to, guard }
+}
+
+use RunState::*;
+
+// Stop stands for B, the A+B kill switch, a stop command and the run limit
+const TRANSITIONS: &[Transition] = &[
+    t(Boot, Idle, "self test ok"),
+    t(Boot, Fault, "self test failed"),
+    // A held or a drive command already in the boot frame
+    t(Boot, LineFollow, "A | start"),
+    t(Boot, Remote, "drive"),
+    t(Idle, LineFollow, "A | start"),
+    t(Idle, Remote, "drive"),
+    t(Idle, Armed, "start at, synced"),
+    t(Idle, Maneuver, "move"),
+    t(Idle, Script, "script run"),
+    t(Idle, Manual, "manual"),
+    t(Armed, Idle, "B | stop"),
+    t(Armed, LineFollow, "time reached, line mode"),
+    t(Armed, Remote, "time reached, remote mode"),
+    t(Armed, Maneuver, "move"),
+    t(Armed, Script, "script run"),
+    t(Armed, Manual, "manual"),
+    t(Finished, LineFollow, "A | start"),
+    t(Finished, Remote, "drive"),
+    t(Finished, Maneuver, "move"),
+    t(Finished, Script, "script run"),
+    t(Finished, Manual, "manual"),
+    t(LineFollow, Idle, "stop"),
+    t(LineFollow, Finished, "end of line, end stop"),
+    t(
+        LineFollow,
+        Maneuver,
+        "end of line, end turn, end search | move",
+    ),
+    t(LineFollow, Remote, "drive"),
+    t(LineFollow, Script, "script run"),
+    t(Remote, Idle, "stop"),
+    t(Remote, LineFollow, "A"),
+    t(Remote, Maneuver, "move"),
+    t(Remote, Script, "script run"),
+    t(Maneuver, Idle, "done, was stopped | stop"),
+    t(Maneuver, LineFollow, "done, line mode | line found"),
+    t(Maneuver, Finished, "search failed"),
+    t(Maneuver, Remote, "done, remote mode"),
+    t(Maneuver, Script, "done, in a script"),
+    t(Maneuver, Manual, "done, manual mode"),
+    t(Script, Idle, "end of script | stop"),
+    t(Script, Maneuver, "move"),
+    t(Manual, Idle, "B | stop"),
+    t(Manual, Maneuver, "move"),
+    t(Manual, Script, "script run"),
+    // The photocell failed for good
+    t(Idle, Fault, "adc errors"),
+    t(Armed, Fault, "adc errors"),
+    t(Finished, Fault, "adc errors"),
+    t(LineFollow, Fault, "adc errors"),
+    t(Remote, Fault, "adc errors"),
+    t(Maneuver, Fault, "adc errors"),
+    t(Script, Fault, "adc errors"),
+    t(Manual, Fault, "adc errors"),
+];
+
+pub fn allowed(from: RunState, to: RunState) -> bool {
+    TRANSITIONS
+        .iter()
+        .any(|transition| transition.from == from && transition.to == to)
+}
+
+pub fn write_dot<W: Write + ?Sized>(out: &mut W) -> core::fmt::Result {
+    writeln!(out, "digraph run {{")?;
+    for transition in TRANSITIONS {
+        writeln!(
+            out,
+            "  {} -> {} [label=\"{}\"];",
+            transition.from.name(),
+            transition.to.name(),
+            transition.guard
+        )?;
+    }
+    writeln!(out, "}}")
+}
@@ -0,0 +1,556 @@
+// GATT server of the BLE link (feature `gatt`, ble.rs), so a phone, a tablet
+// or tools/dashboard.html can connect, watch the car, start and stop it and
+// tune the `set` parameters. The ATT requests are answered from the radio
+// interrupt with the values main last handed over, writes become console
+// requests for main, see ble::Ble.
+//
+// The ringbit service 4f9a0001-8c3c-4b8e-9c1b-2f6d0c5e7a31 next to the GAP
+// service. Values are little endian with naturally aligned fields:
+//
+// 4f9a0002  drive state, read and notify
+//           u8 state: 0 stopped, 1 forward, 2 left, 3 right, 4 back
+//           u8 flags: bit 0 on, bit 1 remote control
+//           i8 throttle and i8 steering in percent
+// 4f9a0003  speed profile, read and notify
+//           u8 speed in percent, then u8 per zone 0..7, 255 for none
+// 4f9a0004  sensor reading, read and notify
+//           i16 photocell reading, u16 supply voltage in mV
+// 4f9a0005  control, write
+//           u8 0 stop, 1 start, 2 save the settings to flash
+// 4f9a0006  parameter, read, write and notify
+//           write u8 index into params::PARAMS to get a parameter, or
+//           u8 index, 3 padding bytes and i32 value to set it, the value
+//           as `set` takes it, thousandths for the gains
+//           reads u8 index, u8 result (0 ok, 1 error, 2 pending), 2
+//           padding bytes and i32 value of the last get or set
+//
+// Drive state and sensor reading are notified at 10 Hz, the speed profile
+// when it changes and the parameter when main has answered. The ATT MTU
+// stays at the default 23 bytes, every value fits. There is no pairing, any
+// central in range can connect and start the car.
+
+use crate::config::ZONES;
+use crate::console::Request;
+use crate::params::{Param, PARAMS};
+use crate::radio::Command;
+
+pub const MTU: usize = 23;
+
+// ATT opcodes
+const ERROR_RSP: u8 = 0x01;
+const MTU_REQ: u8 = 0x02;
+const MTU_RSP: u8 = 0x03;
+const FIND_INFO_REQ: u8 = 0x04;
+const FIND_INFO_RSP: u8 = 0x05;
+const FIND_BY_TYPE_REQ: u8 = 0x06;
+const FIND_BY_TYPE_RSP: u8 = 0x07;
+const READ_BY_TYPE_REQ: u8 = 0x08;
+const READ_BY_TYPE_RSP: u8 = 0x09;
+const READ_REQ: u8 = 0x0a;
+const READ_RSP: u8 = 0x0b;
+const READ_BLOB_REQ: u8 = 0x0c;
+const READ_BLOB_RSP: u8 = 0x0d;
+const READ_BY_GROUP_REQ: u8 = 0x10;
+const READ_BY_GROUP_RSP: u8 = 0x11;
+const WRITE_REQ: u8 = 0x12;
+const WRITE_RSP: u8 = 0x13;
+const HANDLE_VALUE_NTF: u8 = 0x1b;
+const WRITE_CMD: u8 = 0x52;
+// Set in the opcodes that get no response
+const COMMAND: u8 = 0x40;
+
+// ATT error codes
+const INVALID_HANDLE: u8 = 0x01;
+const READ_NOT_PERMITTED: u8 = 0x02;
+const WRITE_NOT_PERMITTED: u8 = 0x03;
+const INVALID_PDU: u8 = 0x04;
+const NOT_SUPPORTED: u8 = 0x06;
+const INVALID_OFFSET: u8 = 0x07;
+const NOT_FOUND: u8 = 0x0a;
+const INVALID_LENGTH: u8 = 0x0d;
+const UNSUPPORTED_GROUP: u8 = 0x10;
+// Application error, no such parameter
+const UNKNOWN_PARAM: u8 = 0x80;
+
+// Characteristic properties
+const READ: u8 = 0x02;
+const WRITE_NO_RSP: u8 = 0x04;
+const WRITE: u8 = 0x08;
+const NOTIFY: u8 = 0x10;
+
+// Attribute types, little endian as on the air
+const PRIMARY_SERVICE: &[u8] = &[0x00, 0x28];
+const CHARACTERISTIC: &[u8] = &[0x03, 0x28];
+const CLIENT_CONFIGURATION: &[u8] = &[0x02, 0x29];
+const DEVICE_NAME: &[u8] = &[0x00, 0x2a];
+const APPEARANCE: &[u8] = &[0x01, 0x2a];
+const GAP_SERVICE: &[u8] = &[0x00, 0x18];
+
+pub const NAME: &[u8] = b"ringbit";
+
+// 4f9aXXXX-8c3c-4b8e-9c1b-2f6d0c5e7a31 in the byte order of the air
+const fn uuid(short: u16) -> [u8; 16] {
+    let [low, high] = short.to_le_bytes();
+    [
+        0x31, 0x7a, 0x5e, 0x0c, 0x6d, 0x2f, 0x1b, 0x9c, 0x8e, 0x4b, 0x3c, 0x8c, low, high, 0x9a,
+        0x4f,
+    ]
+}
+
+pub const SERVICE: [u8; 16] = uuid(0x0001);
+const DRIVE: [u8; 16] = uuid(0x0002);
+const PROFILE: [u8; 16] = uuid(0x0003);
+const SENSOR: [u8; 16] = uuid(0x0004);
+const CONTROL: [u8; 16] = uuid(0x0005);
+const PARAM: [u8; 16] = uuid(0x0006);
+
+// Characteristic declaration value: properties, value handle and type
+const fn declaration(properties: u8, handle: u16, uuid: [u8; 16]) -> [u8; 19] {
+    let mut value = [0; 19];
+    value[0] = properties;
+    value[1] = handle as u8;
+    value[2] = (handle >> 8) as u8;
+    let mut i = 0;
+    while i < 16 {
+        value[3 + i] = uuid[i];
+        i += 1;
+    }
+    value
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Value {
+    Fixed(&'static [u8]),
+    Drive,
+    Profile,
+    Sensor,
+    // Write only
+    Control,
+    Param,
+    // Notifications of NOTIFIED[index] on or off
+    Configuration(usize),
+}
+
+struct Attribute {
+    kind: &'static [u8],
+    value: Value,
+}
+
+const DRIVE_DECLARATION: [u8; 19] = declaration(READ | NOTIFY, 8, DRIVE);
+const PROFILE_DECLARATION: [u8; 19] = declaration(READ | NOTIFY, 11, PROFILE);
+const SENSOR_DECLARATION: [u8; 19] = declaration(READ | NOTIFY, 14, SENSOR);
+const CONTROL_DECLARATION: [u8; 19] = declaration(WRITE | WRITE_NO_RSP, 17, CONTROL);
+const PARAM_DECLARATION: [u8; 19] = declaration(READ | WRITE | NOTIFY, 19, PARAM);
+
+// The notifying characteristics with their value handles, in the order of
+// the bits of Server::notify
+const NOTIFIED: [(Value, u16); 4] = [
+    (Value::Drive, 8),
+    (Value::Profile, 11),
+    (Value::Sensor, 14),
+    (Value::Param, 19),
+];
+const DRIVE_BIT: u8 = 1 << 0;
+const PROFILE_BIT: u8 = 1 << 1;
+const SENSOR_BIT: u8 = 1 << 2;
+const PARAM_BIT: u8 = 1 << 3;
+
+// Parameter results
+const PARAM_OK: u8 = 0;
+const PARAM_ERROR: u8 = 1;
+const PARAM_PENDING: u8 = 2;
+
+const fn attribute(kind: &'static [u8], value: Value) -> Attribute {
+    Attribute { kind, value }
+}
+
+// Handle 1 is the first entry, the declarations name the handle after them
+const ATTRIBUTES: [Attribute; 20] = [
+    attribute(PRIMARY_SERVICE, Value::Fixed(GAP_SERVICE)),
+    attribute(CHARACTERISTIC, Value::Fixed(&[READ, 3, 0, 0x00, 0x2a])),
+    attribute(DEVICE_NAME, Value::Fixed(NAME)),
+    attribute(CHARACTERISTIC, Value::Fixed(&[READ, 5, 0, 0x01, 0x2a])),
+    // Generic, no appearance
+    attribute(APPEARANCE, Value::Fixed(&[0, 0])),
+    attribute(PRIMARY_SERVICE, Value::Fixed(&SERVICE)),
+    attribute(CHARACTERISTIC, Value::Fixed(&DRIVE_DECLARATION)),
+    attribute(&DRIVE, Value::Drive),
+    attribute(CLIENT_CONFIGURATION, Value::Configuration(0)),
+    attribute(CHARACTERISTIC, Value::Fixed(&PROFILE_DECLARATION)),
+    attribute(&PROFILE, Value::Profile),
+    attribute(CLIENT_CONFIGURATION, Value::Configuration(1)),
+    attribute(CHARACTERISTIC, Value::Fixed(&SENSOR_DECLARATION)),
+    attribute(&SENSOR, Value::Sensor),
+    attribute(CLIENT_CONFIGURATION, Value::Configuration(2)),
+    attribute(CHARACTERISTIC, Value::Fixed(&CONTROL_DECLARATION)),
+    attribute(&CONTROL, Value::Control),
+    attribute(CHARACTERISTIC, Value::Fixed(&PARAM_DECLARATION)),
+    attribute(&PARAM, Value::Param),
+    attribute(CLIENT_CONFIGURATION, Value::Configuration(3)),
+];
+
+const LAST_HANDLE: u16 = ATTRIBUTES.len() as u16;
+
+// The car state main hands over, see Ble::update
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct Values {
+    pub state: u8,
+    pub is_on: bool,
+    pub remote: bool,
+    pub throttle: i8,
+    pub steering: i8,
+    pub speed: u8,
+    pub zones: [Option<u8>; ZONES],
+    pub photo_cell: i16,
+    pub battery_mv: u16,
+}
+
+pub struct Server {
+    values: Values,
+    // Written by the central, taken by main
+    request: Option<Request>,
+    // Index into PARAMS, result and value
+    param: (u8, u8, i32),
+    // Bits of NOTIFIED, the ones the central enabled and the ones with a
+    // new value to send
+    notify: u8,
+    due: u8,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Server {
+            values: Values::default(),
+            request: None,
+            param: (0, PARAM_OK, 0),
+            notify: 0,
+            due: 0,
+        }
+    }
+
+    pub fn update(&mut self, values: &Values) {
+        let profile = (values.speed, values.zones) != (self.values.speed, self.values.zones);
+        self.values = *values;
+        self.due |= DRIVE_BIT | SENSOR_BIT | if profile { PROFILE_BIT } else { 0 };
+    }
+
+    pub fn take_request(&mut self) -> Option<Request> {
+        self.request.take()
+    }
+
+    // A new connection starts without a request left from the last one and
+    // with the notifications off
+    pub fn reset(&mut self) {
+        self.request = None;
+        self.notify = 0;
+        self.due = 0;
+    }
+
+    // Main's answer to a parameter write, `value` for a get
+    pub fn param_result(&mut self, ok: bool, value: Option<i32>) {
+        let (index, result, old) = self.param;
+        if result != PARAM_PENDING {
+            return;
+        }
+        let result = if ok { PARAM_OK } else { PARAM_ERROR };
+        self.param = (index, result, value.unwrap_or(old));
+        self.due |= PARAM_BIT;
+    }
+
+    // The next notification into `out`, of the values that changed since
+    // the last one
+    pub fn notification(&mut self, out: &mut [u8; MTU]) -> Option<usize> {
+        let ready = self.due & self.notify;
+        self.due &= self.notify;
+        let index = (0..NOTIFIED.len()).find(|index| ready & 1 << index != 0)?;
+        self.due &= !(1 << index);
+        let (value, handle) = NOTIFIED[index];
+        out[0] = HANDLE_VALUE_NTF;
+        out[1..3].copy_from_slice(&handle.to_le_bytes());
+        let mut bytes = [0; MTU];
+        let len = self.value(value, &mut bytes)?.min(MTU - 3);
+        out[3..3 + len].copy_from_slice(&bytes[..len]);
+        Some(3 + len)
+    }
+
+    // Answers an ATT request into `response`, None for commands and
+    // requests without an answer
+    pub fn handle(&mut self, request: &[u8], response: &mut [u8; MTU]) -> Option<usize> {
+        let (&opcode, params) = request.split_first()?;
+        let result = match opcode {
+            MTU_REQ if params.len() == 2 => {
+                response[..3].copy_from_slice(&[MTU_RSP, MTU as u8, 0]);
+                Ok(3)
+            }
+            FIND_INFO_REQ => range(params).and_then(|(start, end)| find_info(start, end, response)),
+            FIND_BY_TYPE_REQ if params.len() >= 6 => {
+                range(params).and_then(|(start, end)| find_by_type(start, end, params, response))
+            }
+            READ_BY_TYPE_REQ => range(params)
+                .and_then(|(start, end)| self.read_by_type(start, end, &params[4..], response)),
+            READ_REQ if params.len() == 2 => self.read(le16(params), 0, READ_RSP, response),
+            READ_BLOB_REQ if params.len() == 4 => {
+                let offset = u16::from_le_bytes([params[2], params[3]]) as usize;
+                self.read(le16(params), offset, READ_BLOB_RSP, response)
+            }
+            READ_BY_GROUP_REQ => range(params)
+                .and_then(|(start, end)| self.read_by_group(start, end, &params[4..], response)),
+            WRITE_REQ | WRITE_CMD if params.len() >= 2 => {
+                self.write(le16(params), &params[2..]).map(|()| {
+                    response[0] = WRITE_RSP;
+                    1
+                })
+            }
+            MTU_REQ | FIND_BY_TYPE_REQ | READ_REQ | READ_BLOB_REQ | WRITE_REQ => {
+                Err((0, INVALID_PDU))
+            }
+            _ => Err((0, NOT_SUPPORTED)),
+        };
+        if opcode & COMMAND != 0 {
+            return None;
+        }
+        Some(match result {
+            Ok(len) => len,
+            Err((handle, code)) => {
+                let [low, high] = handle.to_le_bytes();
+                response[..5].copy_from_slice(&[ERROR_RSP, opcode, low, high, code]);
+                5
+            }
+        })
+    }
+
+    // The current value, None if it can't be read
+    fn value(&self, value: Value, out: &mut [u8; MTU]) -> Option<usize> {
+        let values = &self.values;
+        let bytes: &[u8] = match value {
+            Value::Fixed(bytes) => bytes,
+            Value::Drive => &[
+                values.state,
+                values.is_on as u8 | (values.remote as u8) << 1,
+                values.throttle as u8,
+                values.steering as u8,
+            ],
+            Value::Profile => {
+                out[0] = values.speed;
+                for (byte, zone) in out[1..].iter_mut().zip(values.zones) {
+                    *byte = zone.unwrap_or(255);
+                }
+                return Some(1 + ZONES);
+            }
+            Value::Sensor => {
+                let [p0, p1] = values.photo_cell.to_le_bytes();
+                let [b0, b1] = values.battery_mv.to_le_bytes();
+                &[p0, p1, b0, b1]
+            }
+            Value::Control => return None,
+            Value::Param => {
+                let (index, result, value) = self.param;
+                out[..4].copy_from_slice(&[index, result, 0, 0]);
+                out[4..8].copy_from_slice(&value.to_le_bytes());
+                return Some(8);
+            }
+            Value::Configuration(index) => &[(self.notify >> index) & 1, 0],
+        };
+        out[..bytes.len()].copy_from_slice(bytes);
+        Some(bytes.len())
+    }
+
+    fn read(&self, handle: u16, offset: usize, opcode: u8, out: &mut [u8; MTU]) -> Outcome {
+        let attribute = lookup(handle)?;
+        let mut value = [0; MTU];
+        let len = self
+            .value(attribute.value, &mut value)
+            .ok_or((handle, READ_NOT_PERMITTED))?;
+        if offset > len {
+            return Err((handle, INVALID_OFFSET));
+        }
+        let part = &value[offset..len.min(offset + MTU - 1)];
+        out[0] = opcode;
+        out[1..1 + part.len()].copy_from_slice(part);
+        Ok(1 + part.len())
+    }
+
+    // Handle and value pairs of one type, all of the length of the first
+    fn read_by_type(&self, start: u16, end: u16, kind: &[u8], out: &mut [u8; MTU]) -> Outcome {
+        if kind.len() != 2 && kind.len() != 16 {
+            return Err((start, INVALID_PDU));
+        }
+        out[0] = READ_BY_TYPE_RSP;
+        let mut len = 2;
+        let mut entry_len = 0;
+        for (handle, attribute) in attributes(start, end).filter(|(_, a)| a.kind == kind) {
+            let mut value = [0; MTU];
+            let Some(value_len) = self.value(attribute.value, &mut value) else {
+                if len == 2 {
+                    return Err((handle, READ_NOT_PERMITTED));
+                }
+                break;
+            };
+            let value_len = value_len.min(MTU - 4);
+            if entry_len == 0 {
+                entry_len = 2 + value_len;
+            } else if 2 + value_len != entry_len || len + entry_len > MTU {
+                break;
+            }
+            out[len..len + 2].copy_from_slice(&handle.to_le_bytes());
+            out[len + 2..len + entry_len].copy_from_slice(&value[..value_len]);
+            len += entry_len;
+        }
+        if len == 2 {
+            return Err((start, NOT_FOUND));
+        }
+        out[1] = entry_len as u8;
+        Ok(len)
+    }
+
+    // Services with their last handle, only primary services are groups
+    fn read_by_group(&self, start: u16, end: u16, kind: &[u8], out: &mut [u8; MTU]) -> Outcome {
+        if kind != PRIMARY_SERVICE {
+            return Err((start, UNSUPPORTED_GROUP));
+        }
+        out[0] = READ_BY_GROUP_RSP;
+        let mut len = 2;
+        let mut entry_len = 0;
+        for (handle, attribute) in attributes(start, end).filter(|(_, a)| a.kind == kind) {
+            let Value::Fixed(uuid) = attribute.value else {
+                continue;
+            };
+            if entry_len == 0 {
+                entry_len = 4 + uuid.len();
+            } else if 4 + uuid.len() != entry_len || len + entry_len > MTU {
+                break;
+            }
+            out[len..len + 2].copy_from_slice(&handle.to_le_bytes());
+            out[len + 2..len + 4].copy_from_slice(&group_end(handle).to_le_bytes());
+            out[len + 4..len + entry_len].copy_from_slice(uuid);
+            len += entry_len;
+        }
+        if len == 2 {
+            return Err((start, NOT_FOUND));
+        }
+        out[1] = entry_len as u8;
+        Ok(len)
+    }
+
+    fn write(&mut self, handle: u16, data: &[u8]) -> Result<(), (u16, u8)> {
+        let attribute = lookup(handle)?;
+        match (attribute.value, data) {
+            (Value::Control, [0]) => self.request = Some(Request::Command(Command::Stop)),
+            (Value::Control, [1]) => self.request = Some(Request::Command(Command::Start)),
+            (Value::Control, [2]) => self.request = Some(Request::Save),
+            (Value::Control, _) => return Err((handle, INVALID_LENGTH)),
+            (Value::Param, [index]) => {
+                let param = find_param(handle, *index)?;
+                self.request = Some(Request::Get(param));
+                self.param = (*index, PARAM_PENDING, 0);
+            }
+            (Value::Param, [index, _, _, _, value @ ..]) if value.len() == 4 => {
+                let param = find_param(handle, *index)?;
+                let value = i32::from_le_bytes([value[0], value[1], value[2], value[3]]);
+                self.request = Some(Request::Set(param, value));
+                self.param = (*index, PARAM_PENDING, value);
+            }
+            (Value::Param, _) => return Err((handle, INVALID_LENGTH)),
+            // Bit 0 turns notifications on, indications aren't supported
+            (Value::Configuration(index), [bits, _]) => {
+                self.notify = self.notify & !(1 << index) | (bits & 1) << index;
+            }
+            (Value::Configuration(_), _) => return Err((handle, INVALID_LENGTH)),
+            _ => return Err((handle, WRITE_NOT_PERMITTED)),
+        }
+        Ok(())
+    }
+}
+
+fn find_param(handle: u16, index: u8) -> Result<Param, (u16, u8)> {
+    PARAMS
+        .get(index as usize)
+        .copied()
+        .ok_or((handle, UNKNOWN_PARAM))
+}
+
+// The response length, or the handle and the error code
+type Outcome = Result<usize, (u16, u8)>;
+
+fn le16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+// Start and end handle of a request, the start must be valid
+fn range(params: &[u8]) -> Result<(u16, u16), (u16, u8)> {
+    if params.len() < 4 {
+        return Err((0, INVALID_PDU));
+    }
+    let (start, end) = (le16(params), le16(&params[2..]));
+    if start == 0 || start > end {
+        return Err((start, INVALID_HANDLE));
+    }
+    Ok((start, end))
+}
+
+fn lookup(handle: u16) -> Result<&'static Attribute, (u16, u8)> {
+    ATTRIBUTES
+        .get((handle as usize).wrapping_sub(1))
+        .ok_or((handle, INVALID_HANDLE))
+}
+
+fn attributes(start: u16, end: u16) -> impl Iterator<Item = (u16, &'static Attribute)> {
+    (start..=end.min(LAST_HANDLE)).map(|handle| (handle, &ATTRIBUTES[handle as usize - 1]))
+}
+
+// Last handle of the service declared at `handle`
+fn group_end(handle: u16) -> u16 {
+    attributes(handle + 1, LAST_HANDLE)
+        .find(|(_, attribute)| attribute.kind == PRIMARY_SERVICE)
+        .map_or(LAST_HANDLE, |(next, _)| next - 1)
+}
+
+// Handles and types, 16 bit types first as all have to be of one size
+fn find_info(start: u16, end: u16, out: &mut [u8; MTU]) -> Outcome {
+    out[0] = FIND_INFO_RSP;
+    let mut len = 2;
+    let mut kind_len = 0;
+    for (handle, attribute) in attributes(start, end) {
+        let kind = attribute.kind;
+        if kind_len == 0 {
+            kind_len = kind.len();
+        } else if kind.len() != kind_len || len + 2 + kind_len > MTU {
+            break;
+        }
+        out[len..len + 2].copy_from_slice(&handle.to_le_bytes());
+        out[len + 2..len + 2 + kind_len].copy_from_slice(kind);
+        len += 2 + kind_len;
+    }
+    if len == 2 {
+        return Err((start, NOT_FOUND));
+    }
+    out[1] = if kind_len == 2 { 1 } else { 2 };
+    Ok(len)
+}
+
+// Services by their UUID, the way some centrals discover one service
+fn find_by_type(start: u16, end: u16, params: &[u8], out: &mut [u8; MTU]) -> Outcome {
+    let (kind, wanted) = (&params[4..6], &params[6..]);
+    out[0] = FIND_BY_TYPE_RSP;
+    let mut len = 1;
+    for (handle, attribute) in attributes(start, end).filter(|(_, a)| a.kind == kind) {
+        if !matches!(attribute.value, Value::Fixed(value) if value == wanted) {
+            continue;
+        }
+        if len + 4 > MTU {
+            break;
+        }
+        let last = if kind == PRIMARY_SERVICE {
+            group_end(handle)
+        } else {
+            handle
+        };
+        out[len..len + 2].copy_from_slice(&handle.to_le_bytes());
+        out[len + 2..len + 4].copy_from_slice(&last.to_le_bytes());
+        len += 4;
+    }
+    if len == 1 {
+        return Err((start, NOT_FOUND));
+    }
+    Ok(len)
+}
@@ -0,0 +1,126 @@
+// Racing against the best lap (add-on `marker`, markers.rs). Every lap that
+// starts at a lap mark records when it enters each track segment. The
+// fastest one since reset becomes the ghost, and on the laps after it each
+// marker tick compares the time with the ghost's time for the same segment.
+// The display shows the delta once per tick, a plus when behind and a minus
+// when ahead, then the tenths of a second digit by digit. The Ring:bit LEDs
+// show it for the whole segment, green ahead and purple behind.
+//
+// The run up to the first lap mark only starts a lap, it doesn't count as
+// one. Segments past SEGMENTS are timed for the lap but not compared.
+
+use microbit::display::nonblocking::BitImage;
+
+use crate::images::{Pacing, MINUS, PLUS};
+
+const SEGMENTS: usize = 32;
+// Display ticks at 10 Hz, the sign is the header
+const PACING: Pacing = Pacing {
+    header: 4,
+    digit: 4,
+    gap: 1,
+};
+
+#[derive(Clone, Copy)]
+struct Lap {
+    // From the start of the lap to the entry of each segment
+    entered_ms: [u32; SEGMENTS],
+    segments: usize,
+    ms: u32,
+}
+
+impl Lap {
+    const fn new() -> Self {
+        Lap {
+            entered_ms: [0; SEGMENTS],
+            segments: 1,
+            ms: 0,
+        }
+    }
+}
+
+pub struct Ghost {
+    best: Option<Lap>,
+    lap: Option<Lap>,
+    lap_start_ms: u32,
+    laps: u16,
+    segment: u8,
+    delta_ms: Option<i32>,
+    // Display ticks since the last delta
+    tick: u32,
+}
+
+impl Ghost {
+    pub const fn new() -> Self {
+        Ghost {
+            best: None,
+            lap: None,
+            lap_start_ms: 0,
+            laps: 0,
+            segment: 0,
+            delta_ms: None,
+            tick: u32::MAX,
+        }
+    }
+
+    // At the start of a run, the ghost stays
+    pub fn start(&mut self) {
+        *self = Ghost {
+            best: self.best,
+            ..Ghost::new()
+        };
+    }
+
+    // Every frame of a run with the lap marks passed and the track segment
+    pub fn update(&mut self, now_ms: u32, laps: u16, segment: u8) {
+        let lap_ms = now_ms.wrapping_sub(self.lap_start_ms);
+        if laps != self.laps {
+            if let Some(mut lap) = self.lap {
+                lap.ms = lap_ms;
+                if let Some(best) = self.best {
+                    self.show(lap_ms as i32 - best.ms as i32);
+                }
+                if self.best.is_none_or(|best| lap.ms < best.ms) {
+                    self.best = Some(lap);
+                }
+            }
+            self.lap = Some(Lap::new());
+            self.lap_start_ms = now_ms;
+            self.laps = laps;
+            self.segment = segment;
+            return;
+        }
+        if segment == self.segment {
+            return;
+        }
+        self.segment = segment;
+        let index = segment as usize;
+        let Some(lap) = self.lap.as_mut().filter(|_| index < SEGMENTS) else {
+            return;
+        };
+        lap.entered_ms[index] = lap_ms;
+        lap.segments = index + 1;
+        if let Some(best) = self.best.filter(|best| index < best.segments) {
+            self.show(lap_ms as i32 - best.entered_ms[index] as i32);
+        }
+    }
+
+    fn show(&mut self, delta_ms: i32) {
+        self.delta_ms = Some(delta_ms);
+        self.tick = 0;
+    }
+
+    // Positive behind the ghost, None without one
+    pub fn delta_ms(&self) -> Option<i32> {
+        self.delta_ms
+    }
+
+    // Every display tick, None once the last delta is shown
+    pub fn next(&mut self) -> Option<&'static BitImage> {
+        let delta_ms = self.delta_ms?;
+        let tick = self.tick;
+        self.tick = self.tick.saturating_add(1);
+        let sign = if delta_ms > 0 { &PLUS } else { &MINUS };
+        PACING.image(sign, delta_ms.unsigned_abs() / 100, tick)
+    }
+}
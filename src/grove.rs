@@ -0,0 +1,105 @@
+// Grove modules, add-ons on a spare pin read by the main loop every
+// GROVE_FRAMES. A Grove port carries two signal pins, the modules here only
+// need the first, the second stays free for another add-on. Analog modules
+// need an analog input and the spare pins have none, a Grove light sensor
+// takes the photocell's place on P0. I2C modules go on the edge connector
+// bus, `i2c <address>` has the self test check for one.
+//
+//   linefinder  Grove Line Finder, high over black: 1 over the line, else 0
+//   ultrasonic  Grove Ultrasonic Ranger, trigger and echo on one pin: the
+//               distance in cm up to MAX_CM, 0 without an echo
+//
+// One Grove module at a time, `get grove` reads it and script opcode 0x08
+// branches on it, e.g. to stop in front of an obstacle.
+
+use embedded_hal::digital::InputPin;
+use microbit::hal::gpio::{Disconnected, Floating, Input, Level, Pin};
+
+use crate::expansion::Addon;
+
+// The ranging blocks the main loop for the echo, about 58 us per cm
+const MAX_CM: u32 = 50;
+const ECHO_TIMEOUT_US: u32 = 2000;
+const US_PER_CM: u32 = 58;
+const STEP_US: u32 = 10;
+#[cfg(feature = "v1")]
+const CYCLES_PER_US: u32 = 16;
+#[cfg(feature = "v2")]
+const CYCLES_PER_US: u32 = 64;
+
+#[derive(Clone, Copy)]
+pub enum Kind {
+    LineFinder,
+    Ultrasonic,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 2] = [Kind::LineFinder, Kind::Ultrasonic];
+
+    pub fn addon(self) -> Addon {
+        match self {
+            Kind::LineFinder => Addon::LineFinder,
+            Kind::Ultrasonic => Addon::Ultrasonic,
+        }
+    }
+}
+
+pub struct Grove {
+    kind: Kind,
+    // Taken while an ultrasonic trigger drives it
+    pin: Option<Pin<Input<Floating>>>,
+    reading: u16,
+}
+
+impl Grove {
+    pub fn new(kind: Kind, pin: Pin<Disconnected>) -> Self {
+        Grove {
+            kind,
+            pin: Some(pin.into_floating_input()),
+            reading: 0,
+        }
+    }
+
+    // The last reading, see the table above
+    pub fn reading(&self) -> u16 {
+        self.reading
+    }
+
+    pub fn update(&mut self) {
+        let Some(mut pin) = self.pin.take() else {
+            return;
+        };
+        self.reading = match self.kind {
+            Kind::LineFinder => (pin.is_high() == Ok(true)) as u16,
+            Kind::Ultrasonic => {
+                let trigger = pin.into_push_pull_output(Level::High);
+                cortex_m::asm::delay(10 * CYCLES_PER_US);
+                pin = trigger.into_floating_input();
+                let distance = echo_us(&mut pin).map_or(0, |us| us / US_PER_CM);
+                distance.min(MAX_CM) as u16
+            }
+        };
+        self.pin = Some(pin);
+    }
+}
+
+// Length of the echo pulse, timed in STEP_US polls
+fn echo_us(pin: &mut Pin<Input<Floating>>) -> Option<u32> {
+    let mut waited = 0;
+    while pin.is_low() == Ok(true) {
+        waited += STEP_US;
+        if waited > ECHO_TIMEOUT_US {
+            return None;
+        }
+        cortex_m::asm::delay(STEP_US * CYCLES_PER_US);
+    }
+    let mut us = 0;
+    while pin.is_high() == Ok(true) {
+        us += STEP_US;
+        if us > MAX_CM * US_PER_CM {
+            return None;
+        }
+        cortex_m::asm::delay(STEP_US * CYCLES_PER_US);
+    }
+    Some(us)
+}
@@ -0,0 +1,73 @@
+// DC motor chassis (add-ons `pwm_left`, `pwm_right`, `dir_left` and
+// `dir_right`, V2 only, the V1 has no PWM): a dual H-bridge with a PWM and
+// a direction input per motor, like a DRV8833 or an L298N with its enable
+// input on PWM, for the common TT motor robot kits. The duty cycle is the
+// wheel speed, DIR high drives forward, swapped motor leads fix a wheel
+// that runs backwards. The line following and everything else drive it
+// through the mixer like the servos, which keep getting their pulses.
+
+use embedded_hal::digital::OutputPin;
+use microbit::hal::{
+    gpio::{Disconnected, Level, Output, Pin, PushPull},
+    pac::{self, PWM1},
+    pwm::{Channel, Pwm},
+    time::Hertz,
+};
+
+use crate::drive::MotorDriver;
+use crate::fixed::Fix;
+use crate::mixer::Wheels;
+
+// Fast enough for small motors, slow enough for the L298N
+const PWM_HZ: u32 = 1000;
+
+pub struct HBridge {
+    pwm: Pwm<PWM1>,
+    dir: [Pin<Output<PushPull>>; 2],
+}
+
+impl HBridge {
+    // PWM and direction pins of the left and the right motor
+    pub fn new(pwm: PWM1, pins: [Pin<Disconnected>; 4]) -> Self {
+        let [pwm_left, pwm_right, dir_left, dir_right] = pins;
+        let pwm = Pwm::new(pwm);
+        pwm.set_output_pin(Channel::C0, pwm_left.into_push_pull_output(Level::Low))
+            .set_output_pin(Channel::C1, pwm_right.into_push_pull_output(Level::Low))
+            .set_period(Hertz(PWM_HZ));
+        let mut bridge = HBridge {
+            pwm,
+            dir: [
+                dir_left.into_push_pull_output(Level::High),
+                dir_right.into_push_pull_output(Level::High),
+            ],
+        };
+        bridge.set_wheels(Wheels {
+            left: Fix::ZERO,
+            right: Fix::ZERO,
+        });
+        bridge
+    }
+}
+
+impl MotorDriver for HBridge {
+    fn set_wheels(&mut self, wheels: Wheels) {
+        let max_duty = Fix::from_int(self.pwm.max_duty() as i32);
+        let channels = [Channel::C0, Channel::C1];
+        for ((channel, dir), speed) in channels
+            .into_iter()
+            .zip(&mut self.dir)
+            .zip([wheels.left, wheels.right])
+        {
+            dir.set_state((speed >= Fix::ZERO).into()).ok();
+            let duty = (speed.abs().clamp(Fix::ZERO, Fix::ONE) * max_duty).to_int();
+            self.pwm.set_duty_on(channel, duty as u16);
+        }
+    }
+}
+
+// From the panic handler: the outputs fall back to their low pin level
+pub fn halt() {
+    // Safe as stopping is all that is done, whoever owns it
+    let pwm = unsafe { &*pac::PWM1::ptr() };
+    pwm.tasks_stop.write(|w| unsafe { w.bits(1) });
+}
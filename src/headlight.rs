@@ -0,0 +1,66 @@
+// Headlight LED on a spare edge pin (add-on `headlight`). In auto mode it
+// turns on in the dark. There is no separate light sensor, the ambient light
+// level is the photocell reading averaged over a few seconds, which hides the
+// line crossings.
+
+use embedded_hal::digital::OutputPin;
+use microbit::hal::gpio::{Disconnected, Level, Output, Pin, PushPull};
+
+use crate::control::LowPass;
+use crate::fixed::Fix;
+
+// Off again only this far above the threshold, so it doesn't flicker at dusk
+const HYSTERESIS: i32 = 10;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum HeadlightMode {
+    Auto,
+    On,
+    Off,
+}
+
+pub struct Headlight {
+    pin: Pin<Output<PushPull>>,
+    ambient: LowPass<Fix>,
+    started: bool,
+    lit: bool,
+}
+
+impl Headlight {
+    pub fn new(pin: Pin<Disconnected>) -> Self {
+        Headlight {
+            pin: pin.into_push_pull_output(Level::Low),
+            // About 2 s time constant at 50 Hz
+            ambient: LowPass::new(Fix::from_f32(0.01)),
+            started: false,
+            lit: false,
+        }
+    }
+
+    // Called every frame
+    pub fn update(&mut self, mode: HeadlightMode, threshold: i16, photo_cell: i16) {
+        let reading = Fix::from_int(photo_cell as i32);
+        if !self.started {
+            self.ambient.set(reading);
+            self.started = true;
+        }
+        let ambient = self.ambient.update(reading).to_int();
+        let dark = if self.lit {
+            ambient < threshold as i32 + HYSTERESIS
+        } else {
+            ambient < threshold as i32
+        };
+        self.lit = match mode {
+            HeadlightMode::Auto => dark,
+            HeadlightMode::On => true,
+            HeadlightMode::Off => false,
+        };
+        let _ = self.pin.set_state(self.lit.into());
+    }
+
+    // Before System OFF, the pin keeps its level
+    pub fn off(&mut self) {
+        self.lit = false;
+        let _ = self.pin.set_low();
+    }
+}
@@ -0,0 +1,67 @@
+// External I2C bus on the edge connector (P19 SCL, P20 SDA) for add-on
+// boards like the Sonar:bit, IMUs or motor drivers. On the V1 the on-board
+// accelerometer and magnetometer share this bus, the V2 has them on a separate
+// internal bus.
+
+#[cfg(feature = "v2")]
+use microbit::{
+    board::I2CExternalPins,
+    hal::{
+        pac::TWIM0,
+        twim::{Frequency, Twim},
+    },
+};
+#[cfg(feature = "v1")]
+use microbit::{
+    board::I2CPins,
+    hal::{
+        pac::TWI0,
+        twi::{Frequency, Twi},
+    },
+};
+
+// Reserved addresses at both ends of the 7 bit range are skipped
+const FIRST_ADDRESS: u8 = 0x08;
+const LAST_ADDRESS: u8 = 0x77;
+
+pub struct Bus {
+    #[cfg(feature = "v1")]
+    twi: Twi<TWI0>,
+    #[cfg(feature = "v2")]
+    twi: Twim<TWIM0>,
+}
+
+impl Bus {
+    #[cfg(feature = "v1")]
+    pub fn new(twi: TWI0, pins: I2CPins) -> Self {
+        Bus {
+            twi: Twi::new(twi, pins.into(), Frequency::K100),
+        }
+    }
+
+    #[cfg(feature = "v2")]
+    pub fn new(twim: TWIM0, pins: I2CExternalPins) -> Self {
+        Bus {
+            twi: Twim::new(twim, pins.into(), Frequency::K100),
+        }
+    }
+
+    // For drivers of the on-board devices on the same bus
+    #[cfg(feature = "v1")]
+    pub fn device(&mut self) -> &mut Twi<TWI0> {
+        &mut self.twi
+    }
+
+    // Bit n is set when a device acknowledged address n. Probes with a one
+    // byte read, the nRF TWI can't send an address without data. Blocks for
+    // about 25 ms, so a servo frame is skipped.
+    pub fn scan(&mut self) -> u128 {
+        let mut found = 0;
+        for address in FIRST_ADDRESS..=LAST_ADDRESS {
+            if self.twi.read(address, &mut [0]).is_ok() {
+                found |= 1 << address;
+            }
+        }
+        found
+    }
+}
@@ -0,0 +1,321 @@
+// Images for the 5x5 LED matrix. Statics, not consts, so screen.rs can tell
+// them apart by address.
+
+use microbit::display::nonblocking::BitImage;
+
+pub static SMILE: BitImage = BitImage::new(&[
+    [0, 1, 0, 1, 0],
+    [1, 0, 1, 0, 1],
+    [0, 0, 1, 0, 0],
+    [1, 0, 0, 0, 1],
+    [0, 1, 1, 1, 0],
+]);
+
+pub static ARROW_LEFT: BitImage = BitImage::new(&[
+    [0, 0, 1, 0, 0],
+    [0, 1, 0, 0, 0],
+    [1, 1, 1, 1, 1],
+    [0, 1, 0, 0, 0],
+    [0, 0, 1, 0, 0],
+]);
+
+pub static ARROW_RIGHT: BitImage = BitImage::new(&[
+    [0, 0, 1, 0, 0],
+    [0, 0, 0, 1, 0],
+    [1, 1, 1, 1, 1],
+    [0, 0, 0, 1, 0],
+    [0, 0, 1, 0, 0],
+]);
+
+pub static ARROW_DOWN: BitImage = BitImage::new(&[
+    [0, 0, 1, 0, 0],
+    [0, 0, 1, 0, 0],
+    [1, 0, 1, 0, 1],
+    [0, 1, 1, 1, 0],
+    [0, 0, 1, 0, 0],
+]);
+
+pub static ARROW_UP: BitImage = BitImage::new(&[
+    [0, 0, 1, 0, 0],
+    [0, 1, 1, 1, 0],
+    [1, 0, 1, 0, 1],
+    [0, 0, 1, 0, 0],
+    [0, 0, 1, 0, 0],
+]);
+
+pub static PAIRING_IMAGE: BitImage = BitImage::new(&[
+    [0, 1, 1, 1, 0],
+    [1, 0, 0, 0, 1],
+    [0, 0, 1, 0, 0],
+    [0, 1, 0, 1, 0],
+    [0, 0, 1, 0, 0],
+]);
+
+// Failed self test
+pub static FAULT: BitImage = BitImage::new(&[
+    [1, 0, 0, 0, 1],
+    [0, 1, 0, 1, 0],
+    [0, 0, 1, 0, 0],
+    [0, 1, 0, 1, 0],
+    [1, 0, 0, 0, 1],
+]);
+
+// Stopped at the end of the line
+pub static FINISHED: BitImage = BitImage::new(&[
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 1],
+    [0, 0, 0, 1, 0],
+    [1, 0, 1, 0, 0],
+    [0, 1, 0, 0, 0],
+]);
+
+// Ready to start after a wakeup from power off
+pub static ARMED_PROMPT: BitImage = BitImage::new(&[
+    [0, 1, 1, 1, 0],
+    [1, 0, 0, 0, 1],
+    [0, 0, 1, 1, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 1, 0, 0],
+]);
+
+// Photocell calibration saved
+pub static CALIBRATED: BitImage = BitImage::new(&[
+    [0, 1, 1, 1, 0],
+    [1, 0, 0, 0, 1],
+    [1, 0, 1, 0, 1],
+    [1, 0, 0, 0, 1],
+    [0, 1, 1, 1, 0],
+]);
+
+pub static BLANK: BitImage = BitImage::new(&[[0; 5]; 5]);
+
+// Stats page headers: runs, driving time in minutes, distance in meters
+pub static STATS_RUNS: BitImage = BitImage::new(&[
+    [0, 1, 0, 1, 0],
+    [1, 1, 1, 1, 1],
+    [0, 1, 0, 1, 0],
+    [1, 1, 1, 1, 1],
+    [0, 1, 0, 1, 0],
+]);
+
+pub static STATS_TIME: BitImage = BitImage::new(&[
+    [0, 1, 1, 1, 0],
+    [1, 0, 1, 0, 1],
+    [1, 0, 1, 1, 1],
+    [1, 0, 0, 0, 1],
+    [0, 1, 1, 1, 0],
+]);
+
+pub static STATS_DISTANCE: BitImage = BitImage::new(&[
+    [0, 0, 0, 0, 0],
+    [1, 0, 0, 0, 1],
+    [1, 1, 1, 1, 1],
+    [1, 0, 0, 0, 1],
+    [0, 0, 0, 0, 0],
+]);
+
+// Odometer page header, a wheel, distance in meters
+pub static ODOMETER: BitImage = BitImage::new(&[
+    [0, 1, 1, 1, 0],
+    [1, 0, 1, 0, 1],
+    [1, 1, 1, 1, 1],
+    [1, 0, 1, 0, 1],
+    [0, 1, 1, 1, 0],
+]);
+
+// Post-run summary page headers (summary.rs), the run time uses STATS_TIME:
+// laps, best lap, top speed and lowest supply voltage
+pub static SUMMARY_LAPS: BitImage = BitImage::new(&[
+    [1, 1, 1, 1, 0],
+    [1, 1, 1, 1, 0],
+    [1, 0, 0, 0, 0],
+    [1, 0, 0, 0, 0],
+    [1, 0, 0, 0, 0],
+]);
+
+pub static SUMMARY_BEST: BitImage = BitImage::new(&[
+    [1, 1, 1, 1, 1],
+    [1, 1, 1, 1, 1],
+    [0, 1, 1, 1, 0],
+    [0, 0, 1, 0, 0],
+    [0, 1, 1, 1, 0],
+]);
+
+pub static SUMMARY_SPEED: BitImage = BitImage::new(&[
+    [1, 0, 1, 0, 0],
+    [0, 1, 0, 1, 0],
+    [0, 0, 1, 0, 1],
+    [0, 1, 0, 1, 0],
+    [1, 0, 1, 0, 0],
+]);
+
+pub static SUMMARY_BATTERY: BitImage = BitImage::new(&[
+    [0, 1, 1, 1, 0],
+    [1, 0, 0, 0, 1],
+    [1, 0, 0, 0, 1],
+    [1, 1, 1, 1, 1],
+    [1, 1, 1, 1, 1],
+]);
+
+// Button tuning headers (adjust.rs): K for kp, S for speed, T for trim,
+// and the sign of a negative value
+pub static ADJUST_KP: BitImage = BitImage::new(&[
+    [1, 0, 0, 1, 0],
+    [1, 0, 1, 0, 0],
+    [1, 1, 0, 0, 0],
+    [1, 0, 1, 0, 0],
+    [1, 0, 0, 1, 0],
+]);
+
+pub static ADJUST_SPEED: BitImage = BitImage::new(&[
+    [0, 1, 1, 1, 0],
+    [1, 0, 0, 0, 0],
+    [0, 1, 1, 1, 0],
+    [0, 0, 0, 0, 1],
+    [0, 1, 1, 1, 0],
+]);
+
+pub static ADJUST_TRIM: BitImage = BitImage::new(&[
+    [1, 1, 1, 1, 1],
+    [0, 0, 1, 0, 0],
+    [0, 0, 1, 0, 0],
+    [0, 0, 1, 0, 0],
+    [0, 0, 1, 0, 0],
+]);
+
+pub static MINUS: BitImage = BitImage::new(&[
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 0],
+    [0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0],
+]);
+
+pub static PLUS: BitImage = BitImage::new(&[
+    [0, 0, 0, 0, 0],
+    [0, 0, 1, 0, 0],
+    [0, 1, 1, 1, 0],
+    [0, 0, 1, 0, 0],
+    [0, 0, 0, 0, 0],
+]);
+
+pub static DIGITS: [BitImage; 10] = [
+    BitImage::new(&[
+        [0, 1, 1, 1, 0],
+        [0, 1, 0, 1, 0],
+        [0, 1, 0, 1, 0],
+        [0, 1, 0, 1, 0],
+        [0, 1, 1, 1, 0],
+    ]),
+    BitImage::new(&[
+        [0, 0, 1, 0, 0],
+        [0, 1, 1, 0, 0],
+        [0, 0, 1, 0, 0],
+        [0, 0, 1, 0, 0],
+        [0, 1, 1, 1, 0],
+    ]),
+    BitImage::new(&[
+        [0, 1, 1, 1, 0],
+        [0, 0, 0, 1, 0],
+        [0, 1, 1, 1, 0],
+        [0, 1, 0, 0, 0],
+        [0, 1, 1, 1, 0],
+    ]),
+    BitImage::new(&[
+        [0, 1, 1, 1, 0],
+        [0, 0, 0, 1, 0],
+        [0, 0, 1, 1, 0],
+        [0, 0, 0, 1, 0],
+        [0, 1, 1, 1, 0],
+    ]),
+    BitImage::new(&[
+        [0, 1, 0, 1, 0],
+        [0, 1, 0, 1, 0],
+        [0, 1, 1, 1, 0],
+        [0, 0, 0, 1, 0],
+        [0, 0, 0, 1, 0],
+    ]),
+    BitImage::new(&[
+        [0, 1, 1, 1, 0],
+        [0, 1, 0, 0, 0],
+        [0, 1, 1, 1, 0],
+        [0, 0, 0, 1, 0],
+        [0, 1, 1, 1, 0],
+    ]),
+    BitImage::new(&[
+        [0, 1, 1, 1, 0],
+        [0, 1, 0, 0, 0],
+        [0, 1, 1, 1, 0],
+        [0, 1, 0, 1, 0],
+        [0, 1, 1, 1, 0],
+    ]),
+    BitImage::new(&[
+        [0, 1, 1, 1, 0],
+        [0, 0, 0, 1, 0],
+        [0, 0, 1, 0, 0],
+        [0, 0, 1, 0, 0],
+        [0, 0, 1, 0, 0],
+    ]),
+    BitImage::new(&[
+        [0, 1, 1, 1, 0],
+        [0, 1, 0, 1, 0],
+        [0, 1, 1, 1, 0],
+        [0, 1, 0, 1, 0],
+        [0, 1, 1, 1, 0],
+    ]),
+    BitImage::new(&[
+        [0, 1, 1, 1, 0],
+        [0, 1, 0, 1, 0],
+        [0, 1, 1, 1, 0],
+        [0, 0, 0, 1, 0],
+        [0, 1, 1, 1, 0],
+    ]),
+];
+
+// Timing of a page on the display, in display ticks: a header image, then a
+// number digit by digit, each digit followed by a blank gap
+#[derive(Clone, Copy)]
+pub struct Pacing {
+    pub header: u32,
+    pub digit: u32,
+    pub gap: u32,
+}
+
+impl Pacing {
+    // Ticks of the page for the value
+    pub fn ticks(&self, value: u32) -> u32 {
+        self.header + digits(value) * (self.digit + self.gap)
+    }
+
+    // The image at the tick of the page, None past its end
+    pub fn image(
+        &self,
+        header: &'static BitImage,
+        value: u32,
+        tick: u32,
+    ) -> Option<&'static BitImage> {
+        if tick < self.header {
+            return Some(header);
+        }
+        let tick = tick - self.header;
+        let digits = digits(value);
+        if tick >= digits * (self.digit + self.gap) {
+            return None;
+        }
+        let place = digits - 1 - tick / (self.digit + self.gap);
+        match tick % (self.digit + self.gap) < self.digit {
+            true => Some(&DIGITS[(value / 10u32.pow(place) % 10) as usize]),
+            false => Some(&BLANK),
+        }
+    }
+}
+
+// Decimal digits of the value, at least one
+pub fn digits(mut value: u32) -> u32 {
+    let mut digits = 1;
+    while value >= 10 {
+        value /= 10;
+        digits += 1;
+    }
+    digits
+}
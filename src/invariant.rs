@@ -0,0 +1,15 @@
+// Checks of internal invariants for development: with the `invariants`
+// feature a debug build panics with the defmt message when one is broken,
+// so logic bugs show on the bench instead of on the track. Otherwise the
+// condition is only type checked, it's never evaluated.
+
+macro_rules! invariant {
+    ($condition:expr, $($message:tt)+) => {
+        #[cfg(all(feature = "invariants", debug_assertions))]
+        defmt::assert!($condition, $($message)+);
+        #[cfg(not(all(feature = "invariants", debug_assertions)))]
+        if false {
+            let _ = $condition;
+        }
+    };
+}
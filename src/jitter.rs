@@ -0,0 +1,49 @@
+// Spacing of the servo timer interrupts against the servo period, 20 ms at
+// 50 Hz. The periods themselves are exact, CC0 restarts the timer in
+// hardware, but the interrupt that reloads the pulse widths runs late by
+// its latency, and a reload that misses the end of a pulse makes a
+// malformed one. The interrupt captures the timer on entry, the time since
+// the period started, and the spacing of two interrupts deviates from the
+// period by the difference of their latencies.
+
+// Deviations above this are reported, a small fraction of the 500 us
+// shortest pulse
+pub const LIMIT_US: u32 = 100;
+
+pub struct Jitter {
+    last_latency_us: Option<u32>,
+    max_us: u32,
+    // Reloads put off to the next frame, see drive::can_commit
+    deferred: u32,
+}
+
+impl Jitter {
+    pub const fn new() -> Self {
+        Jitter {
+            last_latency_us: None,
+            max_us: 0,
+            deferred: 0,
+        }
+    }
+
+    // From the interrupt, with the timer position on entry
+    pub fn record(&mut self, latency_us: u32) {
+        if let Some(last) = self.last_latency_us {
+            self.max_us = self.max_us.max(latency_us.abs_diff(last));
+        }
+        self.last_latency_us = Some(latency_us);
+    }
+
+    pub fn defer(&mut self) {
+        self.deferred += 1;
+    }
+
+    pub fn take_deferred(&mut self) -> u32 {
+        core::mem::take(&mut self.deferred)
+    }
+
+    // Largest deviation from the frame spacing since the last call
+    pub fn take_max(&mut self) -> u32 {
+        core::mem::take(&mut self.max_us)
+    }
+}
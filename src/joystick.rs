@@ -0,0 +1,211 @@
+// Transmitter firmware for the Elecfreaks Joystick:bit controller, built
+// with the `joystick` feature in place of the car firmware. The micro:bit in
+// the controller sends the car's radio commands:
+//
+//   stick          drive, Y is the throttle and X the steering
+//   A or C         start line following
+//   B or D         stop
+//   A+B at reset   pair with a car that offers pairing (A+B held on the car)
+//
+// Drive commands go out 20 times a second while the stick is off center and
+// for half a second after it returns, then the car's failsafe stops it and
+// it keeps what A started. The pairing is saved in the radio settings of the
+// flash config, like on the car.
+//
+// Pins: stick X on P1 and Y on P2, buttons C to F on P12 to P15, low while
+// pressed. E and F are unused.
+//
+// The car drops commands whose counter isn't above the last one, also after
+// a reboot of the controller. The upper half of the counter is a boot count
+// kept in flash, the lower half counts the packets of this boot.
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::InputPin;
+use microbit::{
+    adc::{Adc, AdcConfig, Default},
+    board::{Board, Buttons},
+    display::nonblocking::Display,
+    gpio::{EDGE01, EDGE02},
+    hal::{
+        ecb::Ecb,
+        gpio::{Floating, Input},
+        pac,
+        timer::Timer,
+    },
+};
+
+use crate::config::Config;
+use crate::flash::{Flash, Page};
+use crate::images::{PAIRING_IMAGE, SMILE};
+use crate::radio::{Radio, PAIRING};
+use crate::{critical, show, DISPLAY};
+
+const MAGIC: u32 = 0x5242_4a53; // "RBJS"
+const TICK_MS: u32 = 50;
+// Drive commands after the stick returned to center
+const RELEASE_TICKS: u8 = 10;
+// Readings are 10 bit, center about 512
+const CENTER: i32 = 512;
+const DEAD_ZONE: i32 = 40;
+// A car offers pairing every 200 ms for 30 s, polled every millisecond
+const PAIR_TIMEOUT_MS: u32 = 30_000;
+const ACCEPT_REPEATS: u8 = 3;
+
+struct Stick {
+    converter: Adc,
+    x: EDGE01<Input<Floating>>,
+    y: EDGE02<Input<Floating>>,
+}
+
+impl Stick {
+    #[cfg(feature = "v1")]
+    fn read(&mut self) -> (i32, i32) {
+        let x = self.converter.read_channel(&self.x);
+        let y = self.converter.read_channel(&self.y);
+        (x as i32, y as i32)
+    }
+
+    #[cfg(feature = "v2")]
+    fn read(&mut self) -> (i32, i32) {
+        let x = self.converter.read_channel(&mut self.x).unwrap_or(0);
+        let y = self.converter.read_channel(&mut self.y).unwrap_or(0);
+        (x as i32, y as i32)
+    }
+}
+
+// -127..127 from a reading, 0 inside the dead zone
+fn axis(reading: i32) -> i8 {
+    let offset = reading - CENTER;
+    if offset.abs() < DEAD_ZONE {
+        return 0;
+    }
+    (offset * 127 / (CENTER - DEAD_ZONE)).clamp(-127, 127) as i8
+}
+
+struct Counter {
+    boot: u32,
+    packets: u32,
+}
+
+impl Counter {
+    fn new(flash: &mut Flash) -> Self {
+        let mut counter = Counter {
+            boot: 0,
+            packets: 0,
+        };
+        let mut words = [0u32; 2];
+        flash.read(Page::Transmitter, &mut words);
+        if words[0] == MAGIC {
+            counter.boot = words[1];
+        }
+        counter.next_boot(flash);
+        counter
+    }
+
+    fn next_boot(&mut self, flash: &mut Flash) {
+        self.boot = (self.boot + 1) & 0xffff;
+        self.packets = 0;
+        flash.write(Page::Transmitter, &[MAGIC, self.boot]);
+    }
+
+    fn next(&mut self, flash: &mut Flash) -> u32 {
+        self.packets += 1;
+        if self.packets > 0xffff {
+            self.next_boot(flash);
+            self.packets = 1;
+        }
+        self.boot << 16 | self.packets
+    }
+}
+
+// Waits for an offer on the pairing settings and accepts it, the session is
+// saved. A fresh press of B cancels, A+B are still held from the reset.
+fn pair(
+    radio: &mut Radio,
+    timer: &mut Timer<pac::TIMER0>,
+    buttons: &mut Buttons,
+    config: &mut Config,
+    flash: &mut Flash,
+) {
+    show(&PAIRING_IMAGE);
+    radio.configure(&PAIRING);
+    radio.listen();
+    let mut b_was_low = true;
+    for _ in 0..PAIR_TIMEOUT_MS {
+        let b_low = buttons.button_b.is_low() == Ok(true);
+        if b_low && !b_was_low {
+            break;
+        }
+        b_was_low = b_low;
+        if let Some(session) = radio.receive_pair_offer() {
+            radio.configure(&session);
+            // A fresh session, the car starts counting again
+            for counter in 1..=ACCEPT_REPEATS {
+                radio.send_pair_accept(counter as u32);
+            }
+            config.radio = session;
+            config.save(flash);
+            break;
+        }
+        timer.delay_ms(1);
+    }
+    radio.configure(&config.radio);
+    radio.listen();
+}
+
+pub fn run(mut board: Board) -> ! {
+    let display = Display::new(board.TIMER1, board.display_pins);
+    critical(|cs| *DISPLAY.borrow(cs).borrow_mut() = Some(display));
+    unsafe { pac::NVIC::unmask(pac::Interrupt::TIMER1) };
+    let mut timer = Timer::new(board.TIMER0);
+    let mut flash = Flash::new(board.NVMC, &board.FICR);
+    let mut config = Config::load(&flash);
+    let mut counter = Counter::new(&mut flash);
+    let mut radio = Radio::new(board.RADIO, Ecb::init(board.ECB), &config.radio);
+    #[cfg(feature = "v1")]
+    radio.apply_trim(&board.FICR);
+    let mut stick = Stick {
+        converter: Adc::new(board.ADC, AdcConfig::default_10bit()),
+        x: board.edge.e01.into_floating_input(),
+        y: board.edge.e02.into_floating_input(),
+    };
+    let mut button_c = board.edge.e12.into_pullup_input();
+    #[cfg(feature = "v1")]
+    let mut button_d = board.pins.p0_23.into_pullup_input();
+    #[cfg(feature = "v2")]
+    let mut button_d = board.pins.p0_17.into_pullup_input();
+    let a_low = board.buttons.button_a.is_low() == Ok(true);
+    let b_low = board.buttons.button_b.is_low() == Ok(true);
+    if a_low && b_low {
+        pair(
+            &mut radio,
+            &mut timer,
+            &mut board.buttons,
+            &mut config,
+            &mut flash,
+        );
+    }
+    show(&SMILE);
+    let mut release_ticks = 0;
+    loop {
+        let start = board.buttons.button_a.is_low() == Ok(true) || button_c.is_low() == Ok(true);
+        let stop = board.buttons.button_b.is_low() == Ok(true) || button_d.is_low() == Ok(true);
+        let (x, y) = stick.read();
+        // Left is positive steering
+        let (throttle, steering) = (axis(y), -axis(x));
+        if stop {
+            radio.send_stop(counter.next(&mut flash));
+        } else if start {
+            radio.send_start(counter.next(&mut flash));
+        } else {
+            if throttle != 0 || steering != 0 {
+                release_ticks = RELEASE_TICKS;
+            }
+            if release_ticks > 0 {
+                release_ticks -= 1;
+                radio.send_drive(counter.next(&mut flash), throttle, steering);
+            }
+        }
+        timer.delay_ms(TICK_MS);
+    }
+}
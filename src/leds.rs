@@ -0,0 +1,178 @@
+// WS2812 RGB LEDs of the Ring:bit board on a spare edge pin (add-on `leds`).
+// The 800 kHz bit stream comes from a peripheral so its timing doesn't depend
+// on the CPU: the V2 PWM plays one duty cycle per bit through EasyDMA, the V1
+// has no PWM and sends every bit as 4 SPI bits at 4 MHz (1000 for a 0, 1110
+// for a 1) with SCK disconnected. Writes block for about 0.1 ms.
+
+use microbit::hal::gpio::{Disconnected, Level, Pin};
+
+pub const COUNT: usize = 2;
+
+#[derive(Clone, Copy)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Color { r, g, b }
+    }
+
+    // brightness 0..=255
+    pub fn scale(self, brightness: u8) -> Self {
+        let scale = |c: u8| (c as u16 * brightness as u16 / 255) as u8;
+        Color::new(scale(self.r), scale(self.g), scale(self.b))
+    }
+
+    // Color wheel: red, green, blue and back to red over 0..=255
+    pub fn hue(hue: u8) -> Self {
+        let step = (hue % 85) * 3;
+        match hue / 85 {
+            0 => Color::new(255 - step, step, 0),
+            1 => Color::new(0, 255 - step, step),
+            _ => Color::new(step, 0, 255 - step),
+        }
+    }
+
+    // Bits in wire order, green first
+    fn bits(self) -> u32 {
+        (self.g as u32) << 16 | (self.r as u32) << 8 | self.b as u32
+    }
+}
+
+#[cfg(feature = "v1")]
+pub use v1::Leds;
+#[cfg(feature = "v2")]
+pub use v2::Leds;
+
+#[cfg(feature = "v1")]
+mod v1 {
+    use super::*;
+    use microbit::hal::pac::SPI1;
+
+    // 4 MHz
+    const FREQUENCY: u32 = 0x4000_0000;
+    const DISCONNECTED: u32 = 0xffff_ffff;
+
+    pub struct Leds {
+        spi: SPI1,
+    }
+
+    impl Leds {
+        pub fn new(spi: SPI1, pin: Pin<Disconnected>) -> Self {
+            // Idles low between transfers
+            let pin = pin.into_push_pull_output(Level::Low);
+            spi.pselmosi.write(|w| unsafe { w.bits(pin.psel_bits()) });
+            spi.pselsck.write(|w| unsafe { w.bits(DISCONNECTED) });
+            spi.pselmiso.write(|w| unsafe { w.bits(DISCONNECTED) });
+            spi.frequency.write(|w| unsafe { w.bits(FREQUENCY) });
+            // MSB first, mode 0
+            spi.config.write(|w| unsafe { w.bits(0) });
+            spi.enable.write(|w| unsafe { w.bits(1) });
+            Leds { spi }
+        }
+
+        pub fn write(&mut self, colors: &[Color; COUNT]) {
+            // TXD is double buffered, the next byte is queued while one is sent.
+            // Interrupts would stretch the gaps past the 50 us reset time.
+            cortex_m::interrupt::free(|_| {
+                let mut pending = 0;
+                for color in colors {
+                    let bits = color.bits();
+                    for pair in (0..12).rev() {
+                        let byte = encode(bits >> (pair * 2 + 1) & 1) << 4
+                            | encode(bits >> (pair * 2) & 1);
+                        self.spi.txd.write(|w| unsafe { w.bits(byte) });
+                        pending += 1;
+                        if pending == 2 {
+                            self.wait();
+                            pending -= 1;
+                        }
+                    }
+                }
+                while pending > 0 {
+                    self.wait();
+                    pending -= 1;
+                }
+            });
+        }
+
+        fn wait(&mut self) {
+            while self.spi.events_ready.read().bits() == 0 {}
+            self.spi.events_ready.write(|w| unsafe { w.bits(0) });
+            let _ = self.spi.rxd.read();
+        }
+    }
+
+    fn encode(bit: u32) -> u32 {
+        if bit == 1 {
+            0b1110
+        } else {
+            0b1000
+        }
+    }
+}
+
+#[cfg(feature = "v2")]
+mod v2 {
+    use super::*;
+    use microbit::hal::pac::PWM0;
+
+    // 16 MHz / 20 = 800 kHz, the high time is 0.375 us for a 0, 0.8 us for a 1
+    const COUNTERTOP: u16 = 20;
+    const ZERO: u16 = 0x8000 | 6;
+    const ONE: u16 = 0x8000 | 13;
+    // Low for the 50 us reset time after the data
+    const RESET: usize = 40;
+    const LEN: usize = COUNT * 24 + RESET;
+
+    pub struct Leds {
+        pwm: PWM0,
+        buf: [u16; LEN],
+    }
+
+    impl Leds {
+        pub fn new(pwm: PWM0, pin: Pin<Disconnected>) -> Self {
+            let pin = pin.into_push_pull_output(Level::Low);
+            pwm.psel.out[0].write(|w| unsafe { w.bits(pin.psel_bits()) });
+            pwm.enable.write(|w| unsafe { w.bits(1) });
+            // Up counter, 16 MHz, common duty cycle for all channels
+            pwm.mode.write(|w| unsafe { w.bits(0) });
+            pwm.prescaler.write(|w| unsafe { w.bits(0) });
+            pwm.countertop
+                .write(|w| unsafe { w.bits(COUNTERTOP as u32) });
+            pwm.decoder.write(|w| unsafe { w.bits(0) });
+            pwm.loop_.write(|w| unsafe { w.bits(0) });
+            pwm.seq0.refresh.write(|w| unsafe { w.bits(0) });
+            pwm.seq0.enddelay.write(|w| unsafe { w.bits(0) });
+            Leds {
+                pwm,
+                buf: [0x8000; LEN],
+            }
+        }
+
+        pub fn write(&mut self, colors: &[Color; COUNT]) {
+            for (color, bits) in colors.iter().zip(self.buf.chunks_exact_mut(24)) {
+                let value = color.bits();
+                for (i, duty) in bits.iter_mut().enumerate() {
+                    *duty = if value >> (23 - i) & 1 == 1 {
+                        ONE
+                    } else {
+                        ZERO
+                    };
+                }
+            }
+            // The buffer is inside self, so the pointer is set on every write
+            self.pwm
+                .seq0
+                .ptr
+                .write(|w| unsafe { w.bits(self.buf.as_ptr() as u32) });
+            self.pwm.seq0.cnt.write(|w| unsafe { w.bits(LEN as u32) });
+            self.pwm.events_seqend[0].write(|w| unsafe { w.bits(0) });
+            self.pwm.tasks_seqstart[0].write(|w| unsafe { w.bits(1) });
+            while self.pwm.events_seqend[0].read().bits() == 0 {}
+        }
+    }
+}
@@ -0,0 +1,113 @@
+// Effects on the Ring:bit LEDs: a slow breathing while the car is stopped,
+// so it reads as powered but idle, and while driving either a solid color per
+// state or a rainbow chase following the forward speed. Both LEDs double as
+// brake lights when the drive brakes, and as blinkers while turning.
+
+use crate::fixed::Fix;
+use crate::leds::{Color, COUNT};
+use crate::CarState;
+
+const LEFT: usize = 0;
+const RIGHT: usize = 1;
+const BLINKER: Color = Color::new(255, 100, 0);
+
+const IDLE: Color = Color::new(0, 40, 255);
+// 3 s per breath
+const BREATH_FRAMES: u32 = 150;
+const MIN_BRIGHTNESS: u32 = 8;
+// Hue steps per frame at full speed, about 2 turns of the wheel per second
+const CHASE_STEP: Fix = Fix::from_int(10);
+// Hue at full speed, the chase shifts from red towards blue as it gets faster
+const FAST_HUE: Fix = Fix::from_int(170);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    Status,
+    Rainbow,
+}
+
+pub fn idle(frame: u32) -> [Color; COUNT] {
+    let half = BREATH_FRAMES / 2;
+    let phase = frame % BREATH_FRAMES;
+    let level = if phase < half {
+        phase
+    } else {
+        BREATH_FRAMES - phase
+    };
+    // Squared, the eye is more sensitive to changes at low brightness
+    let brightness = MIN_BRIGHTNESS + level * level * (255 - MIN_BRIGHTNESS) / (half * half);
+    [IDLE.scale(brightness as u8); COUNT]
+}
+
+pub fn off() -> [Color; COUNT] {
+    [Color::new(0, 0, 0); COUNT]
+}
+
+pub fn brake() -> [Color; COUNT] {
+    [Color::new(255, 0, 0); COUNT]
+}
+
+// Fault blink code in red
+pub fn fault(blink_on: bool) -> [Color; COUNT] {
+    match blink_on {
+        true => brake(),
+        false => off(),
+    }
+}
+
+// Overrides the LED on the turning side, off in the dark blinker phase
+pub fn blinker(colors: &mut [Color; COUNT], state: CarState, blink_on: bool) {
+    let side = match state {
+        CarState::Left => LEFT,
+        CarState::Right => RIGHT,
+        _ => return,
+    };
+    colors[side] = if blink_on {
+        BLINKER
+    } else {
+        Color::new(0, 0, 0)
+    };
+}
+
+// Against the ghost lap, see ghost.rs
+pub fn ghost(delta_ms: i32) -> [Color; COUNT] {
+    match delta_ms > 0 {
+        true => [Color::new(160, 0, 255); COUNT],
+        false => [Color::new(0, 255, 0); COUNT],
+    }
+}
+
+pub fn status(state: CarState) -> [Color; COUNT] {
+    let color = match state {
+        CarState::Stopped => Color::new(40, 40, 40),
+        CarState::Forward => Color::new(0, 255, 0),
+        CarState::Left | CarState::Right => Color::new(255, 140, 0),
+        CarState::Back => Color::new(255, 0, 0),
+    };
+    [color; COUNT]
+}
+
+pub struct Rainbow {
+    phase: Fix,
+}
+
+impl Rainbow {
+    pub const fn new() -> Self {
+        Rainbow { phase: Fix::ZERO }
+    }
+
+    // Called every frame with the forward speed from the drive
+    pub fn update(&mut self, speed: Fix) -> [Color; COUNT] {
+        let speed = speed.abs();
+        self.phase = self.phase + speed * CHASE_STEP;
+        if self.phase >= Fix::from_int(256) {
+            self.phase = self.phase - Fix::from_int(256);
+        }
+        let base = (self.phase + speed * FAST_HUE).to_int() as u32;
+        let mut colors = [Color::new(0, 0, 0); COUNT];
+        for (i, color) in colors.iter_mut().enumerate() {
+            *color = Color::hue((base + i as u32 * 256 / COUNT as u32) as u8);
+        }
+        colors
+    }
+}
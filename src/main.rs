@@ -1,73 +1,154 @@
 #![no_std]
 #![no_main]
 
-use defmt_rtt as _;
-use panic_halt as _;
-
 use core::cell::RefCell;
-use cortex_m::interrupt::Mutex;
+use cortex_m::interrupt::{CriticalSection, Mutex};
 use cortex_m_rt::entry;
 
 use embedded_hal::digital::InputPin;
 
+// First, the macro is only visible below it
+#[macro_use]
+mod invariant;
+
+mod adjust;
+mod attract;
+mod audio;
+mod battery;
+mod beacon;
+mod bench;
+mod ble;
+mod clock;
+mod config;
+mod console;
+mod control;
+mod convoy;
+mod curve;
+mod diagnostics;
+mod differential;
+mod drive;
+#[cfg(feature = "v2")]
+mod encoder;
+mod errlog;
+mod expansion;
+mod fixed;
+mod flash;
+mod floating;
+mod fsm;
+mod gatt;
+mod ghost;
+mod grove;
+#[cfg(feature = "v2")]
+mod hbridge;
+mod headlight;
+mod i2c;
+mod images;
+mod jitter;
+mod joystick;
+mod leds;
+mod lights;
+mod maneuver;
+mod manual;
+mod markers;
+mod menu;
+mod mixer;
+mod monotonic;
+mod motion;
+mod noise;
+mod odometer;
+mod params;
+mod photocell;
+mod pins;
+mod radio;
+mod sampler;
+mod scanhead;
+mod screen;
+mod script;
+mod search;
+mod serial;
+mod shell;
+mod stats;
+mod summary;
+mod telemetry;
+mod temperature;
+mod tuning;
+mod wake;
+mod wizard;
+
+use adjust::Adjust;
+use audio::{Audio, Voice};
+use beacon::Beacon;
+use ble::Ble;
+use clock::Clock;
+use config::{Config, Mode};
+use console::{Console, Reply, Request};
+use control::{scalar, JerkLimiter, LowPass, Num, Pid, Scalar};
+use convoy::Convoy;
+#[cfg(feature = "v2")]
+use curve::{Curve, Servo};
+use diagnostics::Fault;
+use differential::RightSensor;
+#[cfg(feature = "v2")]
+use drive::MotorDriver;
+use drive::{Drive, CM_PER_FRAME, FRAME_US, PULSE_CENTER, PULSE_MAX, PULSE_MIN};
+#[cfg(feature = "v2")]
+use encoder::Encoder;
+use errlog::PanicPolicy;
+use expansion::{Addon, Expansion};
+use fixed::Fix;
+use flash::Flash;
+use fsm::RunState;
+use ghost::Ghost;
+use grove::Grove;
+#[cfg(feature = "v2")]
+use hbridge::HBridge;
+use headlight::Headlight;
+use images::*;
+use jitter::Jitter;
+use leds::Leds;
+use lights::{Effect, Rainbow};
+use maneuver::{EndOfLine, Maneuver};
+use manual::Manual;
+use markers::Markers;
+use odometer::Odometer;
+use params::Param;
+use photocell::PhotoCell;
+use radio::{Command, Radio, PAIRING};
+use scanhead::ScanHead;
+use screen::Screen;
+use script::{Interpreter, Script, ScriptCommand, Sensors};
+use search::Search;
+use serial::{Edge, Usb};
+use shell::Shell;
+use stats::Stats;
+use summary::{Replay, Summary};
+use telemetry::Event;
+use temperature::Temperature;
+use wizard::Press;
 
+#[cfg(feature = "v2")]
+use microbit::hal::twim;
 use microbit::{
     adc::{Adc, AdcConfig, Default},
-    board::Board,
+    board::{Board, Buttons},
     display::nonblocking::{BitImage, Display},
-    gpio::EDGE00,
     hal::{
-        gpio::{Floating, Input, Level},
+        clocks::Clocks,
+        ecb::Ecb,
+        gpio::{Floating, Input, Level, Pin},
         gpiote::*,
         pac::{self, interrupt, TIMER0, TIMER1},
         ppi::{self, ConfigurablePpi, Ppi},
+        rng::Rng,
     },
 };
 struct Analog {
     converter: Adc,
-    pin: EDGE00<Input<Floating>>,
-}
-
-const SMILE: BitImage = BitImage::new(&[
-    [0, 1, 0, 1, 0],
-    [1, 0, 1, 0, 1],
-    [0, 0, 1, 0, 0],
-    [1, 0, 0, 0, 1],
-    [0, 1, 1, 1, 0],
-]);
-
-const ARROW_LEFT: BitImage = BitImage::new(&[
-    [0, 0, 1, 0, 0],
-    [0, 1, 0, 0, 0],
-    [1, 1, 1, 1, 1],
-    [0, 1, 0, 0, 0],
-    [0, 0, 1, 0, 0],
-]);
-
-const ARROW_RIGHT: BitImage = BitImage::new(&[
-    [0, 0, 1, 0, 0],
-    [0, 0, 0, 1, 0],
-    [1, 1, 1, 1, 1],
-    [0, 0, 0, 1, 0],
-    [0, 0, 1, 0, 0],
-]);
-
-const ARROW_DOWN: BitImage = BitImage::new(&[
-    [0, 0, 1, 0, 0],
-    [0, 0, 1, 0, 0],
-    [1, 0, 1, 0, 1],
-    [0, 1, 1, 1, 0],
-    [0, 0, 1, 0, 0],
-]);
-
-const ARROW_UP: BitImage = BitImage::new(&[
-    [0, 0, 1, 0, 0],
-    [0, 1, 1, 1, 0],
-    [1, 0, 1, 0, 1],
-    [0, 0, 1, 0, 0],
-    [0, 0, 1, 0, 0],
-]);
+    // Owned as the photocell input, converted in the PAC (sampler.rs)
+    _sensor: Pin<Input<Floating>>,
+}
 
+#[derive(Clone, Copy)]
 enum CarState {
     Stopped,
     Forward,
@@ -76,71 +157,899 @@ enum CarState {
     Back,
 }
 
+#[derive(Clone, Copy)]
 struct StateSpeed {
     state: CarState,
-    lspeed: u32,
-    rspeed: u32,
+    throttle: Fix,
+    steering: Fix,
 }
 
 const STATE_STOPPED: StateSpeed = StateSpeed {
     state: CarState::Stopped,
-    lspeed: 1500,
-    rspeed: 1500,
+    throttle: Fix::ZERO,
+    steering: Fix::ZERO,
 };
 const STATE_FORWARD: StateSpeed = StateSpeed {
     state: CarState::Forward,
-    lspeed: 2500,
-    rspeed: 500,
+    throttle: Fix::ONE,
+    steering: Fix::ZERO,
 };
 const STATE_BACK: StateSpeed = StateSpeed {
     state: CarState::Back,
-    lspeed: 500,
-    rspeed: 2500,
+    throttle: Fix::from_int(-1),
+    steering: Fix::ZERO,
 };
 const STATE_LEFT: StateSpeed = StateSpeed {
     state: CarState::Left,
-    lspeed: 2500,
-    rspeed: 1500,
+    throttle: Fix::from_f32(0.5),
+    steering: Fix::from_f32(0.5),
 };
 const STATE_RIGHT: StateSpeed = StateSpeed {
     state: CarState::Right,
-    lspeed: 1500,
-    rspeed: 500,
+    throttle: Fix::from_f32(0.5),
+    steering: Fix::from_f32(-0.5),
 };
 
+impl StateSpeed {
+    // Free throttle and steering commands, the state only selects the image
+    fn new(throttle: Fix, steering: Fix) -> Self {
+        let state = if steering > TURN_THRESHOLD {
+            CarState::Left
+        } else if steering < -TURN_THRESHOLD {
+            CarState::Right
+        } else if throttle > Fix::ZERO {
+            CarState::Forward
+        } else if throttle < Fix::ZERO {
+            CarState::Back
+        } else {
+            CarState::Stopped
+        };
+        StateSpeed {
+            state,
+            throttle,
+            steering,
+        }
+    }
+
+    // Line following driving backwards: the sensor trails the wheels, so
+    // the car steers the other way to bring it back over the edge. The turn
+    // arrows stay on the side the leading end of the car heads to.
+    fn reversed(self) -> Self {
+        let state = match self.state {
+            CarState::Forward => CarState::Back,
+            CarState::Back => CarState::Forward,
+            state => state,
+        };
+        StateSpeed {
+            state,
+            throttle: -self.throttle,
+            steering: -self.steering,
+        }
+    }
+}
+
+// Proportional edge following: the setpoint is the middle of the threshold
+// classifier's forward band, the error is scaled to roughly -1..1.
+const PID_ERROR_SCALE: Scalar = scalar(1.0 / 160.0);
+const PID_THROTTLE: Fix = Fix::from_f32(0.6);
+// 1.5 s off the line while following it ends the run
+const END_OF_LINE_FRAMES: u32 = 75;
+// Steering beyond this shows a turn arrow
+const TURN_THRESHOLD: Fix = Fix::from_f32(0.25);
+
+// Frames are 20 ms
+const FRAME_MS: u32 = 20;
+// Blinker period, about 1.5 Hz
+const BLINK_FRAMES: u32 = 32;
+// The display image is picked on its own 10 Hz tick, not every frame.
+// Animations step with the tick.
+const DISPLAY_FRAMES: u32 = 5;
+// BLE advertisement every 100 ms with the `beacon` feature
+const BEACON_FRAMES: u32 = 5;
+// Grove modules are read at 10 Hz
+const GROVE_FRAMES: u32 = 5;
+// Servo interrupt jitter is checked once a second
+const JITTER_FRAMES: u32 = 50;
+// Per point of a servo curve measurement
+#[cfg(feature = "v2")]
+const CURVE_FRAMES: u32 = 50;
+
+// The servo timer interrupt preempts everything else, then come the BLE link
+// layer (feature `gatt`) and the display refresh. The NVIC keeps the upper priority bits, 2 on the nRF51 and
+// 3 on the nRF52, so the levels are set there.
+const PRIO_SERVO: u8 = 0;
+const PRIO_RADIO: u8 = 0x40;
+const PRIO_DISPLAY: u8 = 0x80;
+// Critical sections hold off the servo timer interrupt too, and it has to
+// reload CC1 and CC2 before the shortest pulse ends 500 us into the period.
+// Longer sections are reported. The WS2812 write on the V1 is the longest
+// at about 60 us.
+const MAX_CRITICAL_US: u32 = 200;
+// The servo timer runs the 20 ms frame as one period of CC[0] at the
+// default 50 Hz, or as several for a faster servo rate (`servo <hz>`, see
+// drive::frame_periods). CC1 and CC2 are reloaded every period.
+//
+// CC[3] compares in the middle of the gap between the longest pulse and the
+// next reload, 8.75 ms after each at 50 Hz. It starts the photocell
+// conversion through PPI, and in the first period of a frame its interrupt
+// starts the main loop's sensing and control, so that work and its critical
+// sections never crowd the reload at the frame start. Captures of the timer
+// position borrow CC[3] and restore it.
+const fn mid_period_us(period_us: u32) -> u32 {
+    (period_us + PULSE_MAX) / 2
+}
+// Timer ticks between a capture and the restore of CC[3]
+const CAPTURE_WINDOW_US: u32 = 4;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MidFrame {
+    Ahead,
+    // A capture held CC[3] while the timer passed the middle, the interrupt
+    // is pended by hand
+    Missed,
+    Done,
+}
+
 static SERVO_TIMER: Mutex<RefCell<Option<TIMER0>>> = Mutex::new(RefCell::new(None));
 static DISPLAY: Mutex<RefCell<Option<Display<TIMER1>>>> = Mutex::new(RefCell::new(None));
-static ANALOG: Mutex<RefCell<Option<Analog>>> = Mutex::new(RefCell::new(None));
-static ONOFF: Mutex<RefCell<Option<bool>>> = Mutex::new(RefCell::new(None));
+// Pulse widths for the next servo frame, staged by the main loop
+static DRIVE: Mutex<RefCell<Drive>> = Mutex::new(RefCell::new(Drive::new()));
+// Set by Timer0 in the middle of every servo frame
+static FRAME: Mutex<RefCell<bool>> = Mutex::new(RefCell::new(false));
+static MID_FRAME: Mutex<RefCell<MidFrame>> = Mutex::new(RefCell::new(MidFrame::Ahead));
+// The servo period within the frame, counted by Timer0
+static PERIOD: Mutex<RefCell<u32>> = Mutex::new(RefCell::new(0));
+// Timer0 interrupt latency, recorded by Timer0
+static JITTER: Mutex<RefCell<Jitter>> = Mutex::new(RefCell::new(Jitter::new()));
 
-fn display(cstate: &CarState) {
-    cortex_m::interrupt::free(|cs| {
+// Turn arrows blink together with the blinker LEDs
+fn state_image(cstate: &CarState, blink_on: bool) -> &'static BitImage {
+    match cstate {
+        CarState::Stopped => &SMILE,
+        CarState::Forward => &ARROW_DOWN,
+        CarState::Back => &ARROW_UP,
+        CarState::Left | CarState::Right if !blink_on => &BLANK,
+        CarState::Left => &ARROW_LEFT,
+        CarState::Right => &ARROW_RIGHT,
+    }
+}
+
+fn show(image: &BitImage) {
+    if !cfg!(feature = "display") {
+        return;
+    }
+    critical(|cs| {
         if let Some(display) = DISPLAY.borrow(cs).borrow_mut().as_mut() {
-            match cstate {
-                CarState::Stopped => display.show(&SMILE),
-                CarState::Forward => display.show(&ARROW_DOWN),
-                CarState::Back => display.show(&ARROW_UP),
-                CarState::Left => display.show(&ARROW_LEFT),
-                CarState::Right => display.show(&ARROW_RIGHT),
+            display.show(image);
+        }
+    });
+}
+
+// Threshold (bang-bang) classifier for the photocell reading, thresholds are
+// the upper ends of the left, forward and back bands
+fn classify(photo_cell: i16, thresholds: &[i16; 3]) -> StateSpeed {
+    if photo_cell <= thresholds[0] {
+        STATE_LEFT
+    } else if photo_cell <= thresholds[1] {
+        STATE_FORWARD
+    } else if photo_cell <= thresholds[2] {
+        STATE_BACK
+    } else {
+        STATE_RIGHT
+    }
+}
+
+// Stretches the default bands between the readings on the two surfaces,
+// the default thresholds fit readings of 64 and 321
+fn calibrated_thresholds(surface1: i16, surface2: i16) -> [i16; 3] {
+    const LOW: i32 = 64;
+    const HIGH: i32 = 321;
+    let low = surface1.min(surface2) as i32;
+    let span = (surface1.max(surface2) as i32 - low).max(3);
+    Config::DEFAULT
+        .thresholds
+        .map(|threshold| (low + (threshold as i32 - LOW) * span / (HIGH - LOW)) as i16)
+}
+
+struct LineFollower {
+    pid: Pid<Scalar>,
+    filter: LowPass<Scalar>,
+    thresholds: [i16; 3],
+    deadband: Scalar,
+    // Frames in a row with the reading in the outer bands
+    lost_frames: u32,
+}
+
+impl LineFollower {
+    fn new(config: &Config) -> Self {
+        let mut follower = LineFollower {
+            pid: Pid::new(Scalar::ZERO, Scalar::ZERO, Scalar::ZERO),
+            filter: LowPass::new(scalar(0.5)),
+            thresholds: config.thresholds,
+            deadband: Scalar::ZERO,
+            lost_frames: 0,
+        };
+        follower.configure(config);
+        follower
+    }
+
+    // Takes over the thresholds, deadband and gains without a reset
+    fn configure(&mut self, config: &Config) {
+        let gain = |milli: u16| Scalar::from_i32(milli as i32) / Scalar::from_i32(1000);
+        let [kp, ki, kd] = config.gains;
+        self.pid.set_gains(gain(kp), gain(ki), gain(kd));
+        self.thresholds = config.thresholds;
+        self.deadband = Scalar::from_i32(config.deadband as i32) * PID_ERROR_SCALE;
+    }
+
+    fn reset(&mut self) {
+        self.pid.reset();
+        self.lost_frames = 0;
+    }
+
+    // The reading stayed all bright or all dark, past the end of the tape
+    fn end_of_line(&self) -> bool {
+        self.lost_frames >= END_OF_LINE_FRAMES
+    }
+
+    fn count_lost(&mut self, lost: bool) {
+        if lost {
+            self.lost_frames += 1;
+        } else {
+            self.lost_frames = 0;
+        }
+    }
+
+    fn step(&mut self, photo_cell: i16) -> StateSpeed {
+        self.count_lost(photo_cell <= self.thresholds[0] || photo_cell > self.thresholds[2]);
+        if !cfg!(feature = "pid") {
+            return classify(photo_cell, &self.thresholds);
+        }
+        let setpoint = (self.thresholds[0] as i32 + self.thresholds[1] as i32) / 2;
+        self.steer(setpoint - photo_cell as i32)
+    }
+
+    // With the second photocell, see differential.rs
+    fn step_differential(&mut self, left: i16, right: i16) -> StateSpeed {
+        self.count_lost(differential::lost(left, right, &self.thresholds));
+        let error = differential::error(left, right, &self.thresholds);
+        if !cfg!(feature = "pid") {
+            // Half the forward band either way drives straight
+            let straight = (self.thresholds[1] - self.thresholds[0]) as i32 / 2;
+            return match error {
+                error if error > straight => STATE_LEFT,
+                error if error < -straight => STATE_RIGHT,
+                _ => STATE_FORWARD,
+            };
+        }
+        self.steer(error)
+    }
+
+    // PID steering on an error in photocell counts, positive steers left
+    fn steer(&mut self, error: i32) -> StateSpeed {
+        let error = Scalar::from_i32(error) * PID_ERROR_SCALE;
+        let error = self.filter.update(error);
+        // Inside the deadband the car drives straight. Outside the error
+        // starts from zero at its edge, so leaving it doesn't kick the
+        // derivative term.
+        let error = if error > self.deadband {
+            error - self.deadband
+        } else if error < -self.deadband {
+            error + self.deadband
+        } else {
+            self.pid.update(Scalar::ZERO);
+            return StateSpeed::new(PID_THROTTLE, Fix::ZERO);
+        };
+        let steering = self.pid.update(error).to_fix();
+        StateSpeed::new(PID_THROTTLE, steering)
+    }
+}
+
+// One conversion, only the V2 SAADC reports errors
+fn convert_photo_cell(_analog: &mut Analog) -> Result<i16, ()> {
+    sampler::convert(pins::SENSOR.ain())
+}
+
+// With retries, for the reads outside the frame path
+fn read_photo_cell(analog: &mut Analog) -> Result<i16, ()> {
+    photocell::convert(|| convert_photo_cell(analog))
+}
+
+// Hold B during reset to select the radio group 0..=9: A steps through the
+// groups, B saves the selection and continues.
+fn radio_setup(buttons: &mut Buttons, config: &mut Config, flash: &mut Flash) {
+    let mut group = config.radio.group % 10;
+    let mut a_was_low = false;
+    let mut b_was_low = true;
+    loop {
+        show(&DIGITS[group as usize]);
+        // Polling once per frame also debounces the buttons
+        while !take_frame() {}
+        let a_low = buttons.button_a.is_low() == Ok(true);
+        let b_low = buttons.button_b.is_low() == Ok(true);
+        if a_low && !a_was_low {
+            group = (group + 1) % 10;
+        }
+        if b_low && !b_was_low {
+            break;
+        }
+        a_was_low = a_low;
+        b_was_low = b_low;
+    }
+    config.radio.group = group;
+    config.save(flash);
+}
+
+// Hold A+B during reset to calibrate the photocell: put the sensor over the
+// first surface (digit 1) and press A, then over the second surface (digit 2)
+// and press A again. The thresholds are saved to flash.
+fn calibrate(buttons: &mut Buttons, analog: &mut Analog, config: &mut Config, flash: &mut Flash) {
+    config.thresholds = measure_surfaces(buttons, analog);
+    config.save(flash);
+}
+
+// The thresholds from the readings on the two surfaces
+fn measure_surfaces(buttons: &mut Buttons, analog: &mut Analog) -> [i16; 3] {
+    let mut readings = [0; 2];
+    for (step, reading) in readings.iter_mut().enumerate() {
+        show(&DIGITS[step + 1]);
+        // Also waits for the buttons held during reset to be released
+        let mut a_was_low = true;
+        loop {
+            while !take_frame() {}
+            let a_low = buttons.button_a.is_low() == Ok(true);
+            if a_low && !a_was_low {
+                break;
+            }
+            a_was_low = a_low;
+        }
+        let mut sum = 0;
+        let mut samples = 0;
+        while samples < CALIBRATION_SAMPLES {
+            while !take_frame() {}
+            if let Ok(reading) = read_photo_cell(analog) {
+                sum += reading as i32;
+                samples += 1;
+            }
+        }
+        *reading = (sum / CALIBRATION_SAMPLES) as i16;
+    }
+    calibrated_thresholds(readings[0], readings[1])
+}
+
+// Averaged over 320 ms
+const CALIBRATION_SAMPLES: i32 = 16;
+
+// Waits for the buttons to be released, then for the next press, with the
+// image shown
+fn wait_press(buttons: &mut Buttons, image: &BitImage) -> Press {
+    show(image);
+    while buttons.button_a.is_low() == Ok(true) || buttons.button_b.is_low() == Ok(true) {
+        while !take_frame() {}
+    }
+    let (mut a, mut b) = (false, false);
+    loop {
+        while !take_frame() {}
+        let a_low = buttons.button_a.is_low() == Ok(true);
+        let b_low = buttons.button_b.is_low() == Ok(true);
+        if a_low || b_low {
+            a |= a_low;
+            b |= b_low;
+        } else if let Some(press) = Press::new(a, b) {
+            return press;
+        }
+    }
+}
+
+// The setup wizard, see wizard.rs. The results are only saved at the end.
+fn wizard(buttons: &mut Buttons, analog: &mut Analog, config: &mut Config, flash: &mut Flash) {
+    let mut tuned = *config;
+    for step in 0..wizard::STEPS {
+        if wait_press(buttons, &DIGITS[step + 1]) != Press::A {
+            continue;
+        }
+        match step {
+            0 => tuned.thresholds = measure_surfaces(buttons, analog),
+            1 => {
+                let center = tuned.thresholds[1];
+                let noise = noise::measure(wizard::NOISE_SAMPLES, || {
+                    read_photo_cell(analog).unwrap_or(center)
+                });
+                tuned.deadband = wizard::deadband(&noise);
+            }
+            2 => trim_runs(buttons, &mut tuned),
+            _ => test_lap(buttons, analog, &tuned),
+        }
+    }
+    if wait_press(buttons, &CALIBRATED) == Press::A {
+        *config = tuned;
+        config.save(flash);
+    }
+    critical(|cs| DRIVE.borrow(cs).borrow_mut().set_trim(trim(config)));
+}
+
+// Two seconds straight ahead per try, B trims right, A left, both keep it
+fn trim_runs(buttons: &mut Buttons, config: &mut Config) {
+    loop {
+        let speed = percent(config.speed);
+        critical(|cs| {
+            let mut drive = DRIVE.borrow(cs).borrow_mut();
+            drive.set_trim(trim(config));
+            drive.set(speed, Fix::ZERO);
+        });
+        show(&ARROW_DOWN);
+        // B, alone or in the A+B kill switch, stops the car and ends the step
+        let mut stopped = false;
+        for _ in 0..WIZARD_TRIM_FRAMES {
+            while !take_frame() {}
+            if buttons.button_b.is_low() == Ok(true) {
+                stopped = true;
+                break;
             }
         }
+        critical(|cs| DRIVE.borrow(cs).borrow_mut().set(Fix::ZERO, Fix::ZERO));
+        if stopped {
+            return;
+        }
+        let step = match wait_press(buttons, &SMILE) {
+            Press::A => 1,
+            Press::B => -1,
+            Press::Both => return,
+        };
+        config.trim = (config.trim + step).clamp(-config::TRIM_MAX, config::TRIM_MAX);
+    }
+}
+
+// Line following with the settings until the end of the line or B
+fn test_lap(buttons: &mut Buttons, analog: &mut Analog, config: &Config) {
+    let mut follower = LineFollower::new(config);
+    critical(|cs| DRIVE.borrow(cs).borrow_mut().set_trim(trim(config)));
+    for _ in 0..WIZARD_LAP_FRAMES {
+        while !take_frame() {}
+        if buttons.button_b.is_low() == Ok(true) {
+            break;
+        }
+        let Ok(reading) = read_photo_cell(analog) else {
+            break;
+        };
+        let state = follower.step(reading);
+        if follower.end_of_line() {
+            break;
+        }
+        let throttle = state.throttle * percent(config.speed);
+        critical(|cs| DRIVE.borrow(cs).borrow_mut().set(throttle, state.steering));
+        show(state_image(&state.state, true));
+    }
+    critical(|cs| DRIVE.borrow(cs).borrow_mut().set(Fix::ZERO, Fix::ZERO));
+}
+
+const WIZARD_TRIM_FRAMES: u32 = 100;
+// A minute at most
+const WIZARD_LAP_FRAMES: u32 = 3000;
+
+// Hold A during reset to tune kp, speed and trim with the buttons, a long
+// press saves them, see adjust.rs
+fn adjust(buttons: &mut Buttons, config: &mut Config, flash: &mut Flash) {
+    // The button held during reset is no press
+    while buttons.button_a.is_low() == Ok(true) {
+        while !take_frame() {}
+    }
+    let mut adjust = Adjust::new();
+    loop {
+        while !take_frame() {}
+        let a_low = buttons.button_a.is_low() == Ok(true);
+        let b_low = buttons.button_b.is_low() == Ok(true);
+        if adjust.update(config, a_low, b_low) {
+            break;
+        }
+        show(adjust.image(config));
+    }
+    config.save(flash);
+    // Released, so the long press doesn't start or stop the car
+    while buttons.button_a.is_low() == Ok(true) || buttons.button_b.is_low() == Ok(true) {
+        while !take_frame() {}
+    }
+}
+
+// Hold A+B for 2 s while stopped to pair with a transmitter
+const PAIR_HOLD_FRAMES: u32 = 100;
+// Between the first and the second button of a chord
+const CHORD_MS: u32 = 80;
+const PAIR_TIMEOUT_FRAMES: u32 = 1500;
+
+// The car offers a random session address on the well known pairing settings
+// until a transmitter in pairing mode accepts it on that address. The address
+// is stored, so the car only listens to that one transmitter. B cancels.
+fn pair(
+    radio: &mut Radio,
+    rng: &mut Rng,
+    buttons: &mut Buttons,
+    config: &mut Config,
+    flash: &mut Flash,
+) {
+    let mut session = config::RadioConfig {
+        address: rng.random_u32(),
+        ..config.radio
+    };
+    rng.random(&mut session.key);
+    show(&PAIRING_IMAGE);
+    let mut b_was_low = true;
+    for frame in 0..PAIR_TIMEOUT_FRAMES {
+        while !take_frame() {}
+        radio.new_frame();
+        let b_low = buttons.button_b.is_low() == Ok(true);
+        if b_low && !b_was_low {
+            break;
+        }
+        b_was_low = b_low;
+        if frame % 10 == 0 {
+            radio.configure(&PAIRING);
+            radio.send_pair_offer(&session);
+            radio.configure(&session);
+            radio.listen();
+        }
+        if let Some(Command::PairAccept) = radio.receive() {
+            config.radio = session;
+            config.save(flash);
+            break;
+        }
+    }
+    radio.configure(&config.radio);
+    radio.listen();
+}
+
+fn percent(value: u8) -> Fix {
+    Fix::from_int(value as i32) * Fix::from_f32(0.01)
+}
+
+// Threshold of the tight turn mode, 0 turns it off
+fn tight_turn(config: &Config) -> Option<Fix> {
+    (config.tight_turn > 0).then(|| percent(config.tight_turn))
+}
+
+// Percent to -1..1, negative trims right
+fn trim(config: &Config) -> Fix {
+    Fix::from_int(config.trim as i32) * Fix::from_f32(0.01)
+}
+
+// Takes changed settings over while driving, after `set` and tuning
+fn apply_settings(config: &Config, follower: &mut LineFollower, steering: &mut JerkLimiter<Fix>) {
+    follower.configure(config);
+    steering.set_limits(percent(config.steer_rate), percent(config.steer_accel));
+    critical(|cs| {
+        let mut drive = DRIVE.borrow(cs).borrow_mut();
+        drive.set_tight_turn(tight_turn(config));
+        drive.set_trim(trim(config));
     });
 }
 
+// -1..1 to -100..100
+fn to_percent(value: Fix) -> i8 {
+    (value * Fix::from_int(100)).to_int() as i8
+}
+
+// Waits the given number of frames, false when A was pressed meanwhile
+fn wait_frames(buttons: &mut Buttons, frames: u32) -> bool {
+    for _ in 0..frames {
+        while !take_frame() {}
+        if buttons.button_a.is_low() == Ok(true) {
+            return false;
+        }
+    }
+    true
+}
+
+// Shows a number digit by digit with a short blank in between, so repeated
+// digits can be told apart
+fn show_number(buttons: &mut Buttons, number: u32) -> bool {
+    let mut divisor = 1;
+    while number / divisor >= 10 {
+        divisor *= 10;
+    }
+    while divisor > 0 {
+        show(&DIGITS[(number / divisor % 10) as usize]);
+        if !wait_frames(buttons, 20) {
+            return false;
+        }
+        show(&BLANK);
+        if !wait_frames(buttons, 5) {
+            return false;
+        }
+        divisor /= 10;
+    }
+    true
+}
+
+// Press B while stopped: runs, minutes and meters, each after its header
+// image. A ends the page early.
+fn show_stats(buttons: &mut Buttons, stats: &Stats, odometer: &Odometer) {
+    let pages = [
+        (&STATS_RUNS, stats.runs),
+        (&STATS_TIME, stats.runtime_s / 60),
+        (&STATS_DISTANCE, stats.distance_cm / 100),
+        (&ODOMETER, odometer.total_cm / 100),
+    ];
+    for (header, value) in pages {
+        show(header);
+        if !wait_frames(buttons, 50) || !show_number(buttons, value) {
+            return;
+        }
+    }
+}
+
+// Holds one servo at each point of its curve and measures the wheel with the
+// encoder, a second per point so the last speed window runs at full speed.
+// The display counts the points.
+#[cfg(feature = "v2")]
+fn measure_curve(encoder: &mut Encoder, servo: Servo) -> Curve {
+    let mut speeds = [0; curve::POINTS];
+    for (point, speed) in speeds.iter_mut().enumerate() {
+        show(&DIGITS[point + 1]);
+        let pulse = curve::point_pulse(point);
+        critical(|cs| DRIVE.borrow(cs).borrow_mut().set_pulse(servo, pulse));
+        for _ in 0..CURVE_FRAMES {
+            while !take_frame() {}
+            encoder.update(monotonic::now_ms());
+        }
+        *speed = encoder.speed_mm_s().clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+    }
+    critical(|cs| DRIVE.borrow(cs).borrow_mut().set_pulse(servo, PULSE_CENTER));
+    Curve::new(speeds)
+}
+
+// Compares two captures of the servo timer a few microseconds apart
+fn servo_timer_running() -> bool {
+    let capture = || critical(servo_timer_us);
+    let first = capture();
+    cortex_m::asm::delay(1000);
+    let second = capture();
+    second.is_some() && second != first
+}
+
+const SELF_TEST_SAMPLES: i32 = 8;
+
+// Stops at the first failed check. The I2C bus is only checked when a device
+// is configured.
+fn self_test(
+    analog: &mut Analog,
+    i2c: &mut i2c::Bus,
+    config: &Config,
+    flash: &Flash,
+) -> Result<(), Fault> {
+    let mut sum = 0;
+    for _ in 0..SELF_TEST_SAMPLES {
+        sum += read_photo_cell(analog).map_err(|_| Fault::Adc)? as i32;
+    }
+    diagnostics::check_photo_cell((sum / SELF_TEST_SAMPLES) as i16)?;
+    if Config::corrupt(flash) {
+        return Err(Fault::Config);
+    }
+    if !servo_timer_running() {
+        return Err(Fault::ServoTimer);
+    }
+    diagnostics::check_battery(battery::read_mv(&mut analog.converter))?;
+    if let Some(address) = config.i2c_device {
+        diagnostics::check_i2c(address, i2c.scan())?;
+    }
+    Ok(())
+}
+
+// Clean shutdown for a remote reset: the servos get the stop pulse, pending
+// flash writes are done, then the core resets
+fn shutdown(
+    stats: &mut Stats,
+    odometer: &mut Odometer,
+    config: &mut Config,
+    mode: Mode,
+    flash: &mut Flash,
+) -> ! {
+    critical(|cs| DRIVE.borrow(cs).borrow_mut().set(Fix::ZERO, Fix::ZERO));
+    // The ISR loads the stop pulse at the start of the next frame
+    take_frame();
+    while !take_frame() {}
+    stats.update(flash, false, Fix::ZERO);
+    odometer.update(flash, false, Fix::ZERO, 0);
+    if mode != config.mode {
+        config.mode = mode;
+        config.save(flash);
+    }
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
+// System OFF after the idle time, the car has long been stopped. The timer
+// stops after the pulses of a frame so the servo outputs stay low, A, B or
+// with motion a jolt wake the car with a reset.
+fn power_off(motion: bool) -> ! {
+    show(&BLANK);
+    // A full display refresh with all LEDs off, and past the pulses
+    take_frame();
+    while !take_frame() {}
+    cortex_m::interrupt::disable();
+    #[cfg(feature = "v2")]
+    hbridge::halt();
+    // Safe as the interrupts are off for good
+    let timer = unsafe { &*TIMER0::ptr() };
+    timer.tasks_stop.write(|w| unsafe { w.bits(1) });
+    wake::power_off(motion)
+}
+
+// Event records in the telemetry, between the frame records
+fn log_event(stream: &mut telemetry::Stream, clock: &Clock, event: Event) {
+    if cfg!(feature = "telemetry") {
+        stream.event(clock.now(monotonic::now_ms()), &event);
+    }
+}
+
+// Console a request came from
+#[derive(Clone, Copy)]
+enum Source {
+    Shell,
+    Serial,
+    Usb,
+    Ble,
+}
+
+fn console<'a>(
+    source: Source,
+    shell: &'a mut Console<Shell>,
+    serial: &'a mut Option<Console<Edge>>,
+    usb: &'a mut Option<Console<Usb>>,
+    ble: &'a mut Option<Ble>,
+) -> &'a mut dyn Reply {
+    match (source, serial.as_mut(), usb.as_mut(), ble.as_mut()) {
+        (Source::Serial, Some(serial), _, _) => serial,
+        (Source::Usb, _, Some(usb), _) => usb,
+        (Source::Ble, _, _, Some(ble)) => ble,
+        _ => shell,
+    }
+}
+
+// Length of a servo period, CC[0]
+fn servo_period_us(cs: &CriticalSection) -> Option<u32> {
+    SERVO_TIMER
+        .borrow(cs)
+        .borrow()
+        .as_ref()
+        .map(|timer| drive::micros(timer.cc[0].read().bits()))
+}
+
+// Position in the current servo period
+fn servo_timer_us(cs: &CriticalSection) -> Option<u32> {
+    SERVO_TIMER
+        .borrow(cs)
+        .borrow_mut()
+        .as_mut()
+        .map(|timer| capture_us(cs, timer))
+}
+
+fn capture_us(cs: &CriticalSection, timer: &TIMER0) -> u32 {
+    let mid_us = mid_period_us(drive::micros(timer.cc[0].read().bits()));
+    timer.tasks_capture[3].write(|w| unsafe { w.bits(1) });
+    let us = drive::micros(timer.cc[3].read().bits());
+    timer.cc[3].write(|w| unsafe { w.bits(drive::ticks(mid_us)) });
+    if (mid_us - CAPTURE_WINDOW_US..mid_us).contains(&us) {
+        let mut mid_frame = MID_FRAME.borrow(cs).borrow_mut();
+        if *mid_frame == MidFrame::Ahead {
+            *mid_frame = MidFrame::Missed;
+            pac::NVIC::pend(pac::Interrupt::TIMER0);
+        }
+    }
+    us
+}
+
+// cortex_m::interrupt::free that reports sections over MAX_CRITICAL_US
+fn critical<R>(f: impl FnOnce(&CriticalSection) -> R) -> R {
+    cortex_m::interrupt::free(|cs| {
+        let start = servo_timer_us(cs);
+        let result = f(cs);
+        if let (Some(start), Some(end)) = (start, servo_timer_us(cs)) {
+            let period_us = servo_period_us(cs).unwrap_or(FRAME_US);
+            let us = (end + period_us - start) % period_us;
+            if us > MAX_CRITICAL_US {
+                defmt::warn!("critical section took {} us", us);
+            }
+        }
+        result
+    })
+}
+
+// The panic policies past halting, from the panic handler. The main loop is
+// gone and the panic may have hit anywhere, so with the interrupts off the
+// timer and display events are polled here, and borrowed statics are left
+// alone.
+fn panic_stop(policy: PanicPolicy) -> ! {
+    cortex_m::interrupt::disable();
+    #[cfg(feature = "v2")]
+    hbridge::halt();
+    let cs = unsafe { CriticalSection::new() };
+    let timer = unsafe { &*TIMER0::ptr() };
+    let mut display = DISPLAY.borrow(&cs).try_borrow_mut().ok();
+    let mut display = display.as_mut().and_then(|display| display.as_mut());
+    let periods = FRAME_US / drive::micros(timer.cc[0].read().bits()).max(1);
+    let ticks = match policy {
+        PanicPolicy::Reset(seconds) => seconds as u32 * 1000 / FRAME_MS * periods,
+        // As good as forever
+        _ => u32::MAX,
+    };
+    let stop = Drive::new();
+    let mut stopped = false;
+    let mut lit = None;
+    for tick in 0..ticks {
+        while timer.events_compare[0].read().bits() == 0 {
+            if let Some(display) = display.as_mut() {
+                if pac::NVIC::is_pending(pac::Interrupt::TIMER1) {
+                    display.handle_display_event();
+                    pac::NVIC::unpend(pac::Interrupt::TIMER1);
+                }
+            }
+        }
+        timer.events_compare[0].write(|w| unsafe { w.bits(0) });
+        // The same reload rule as in the servo interrupt
+        if !stopped {
+            timer.tasks_capture[3].write(|w| unsafe { w.bits(1) });
+            let timer_us = drive::micros(timer.cc[3].read().bits());
+            let current = [
+                drive::micros(timer.cc[1].read().bits()),
+                drive::micros(timer.cc[2].read().bits()),
+            ];
+            let staged = [stop.lpulse(), stop.rpulse()];
+            if drive::can_commit(timer_us, current, staged) {
+                timer.cc[1].write(|w| unsafe { w.bits(drive::ticks(staged[0])) });
+                timer.cc[2].write(|w| unsafe { w.bits(drive::ticks(staged[1])) });
+                stopped = true;
+            }
+        }
+        let on = diagnostics::blink_on(diagnostics::PANIC_CODE, tick / periods);
+        if let Some(display) = display.as_mut().filter(|_| lit != Some(on)) {
+            display.show(if on { &FAULT } else { &BLANK });
+            lit = Some(on);
+        }
+    }
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
+// True once per servo frame
+fn take_frame() -> bool {
+    critical(|cs| FRAME.borrow(cs).replace(false))
+}
+
 #[entry]
 fn main() -> ! {
+    let channels = rtt_target::rtt_init! {
+        up: {
+            0: { size: 1024, name: "defmt" }
+            1: { size: 512, name: "telemetry" }
+            2: { size: 256, name: "shell" }
+        }
+        down: {
+            0: { size: 64, name: "shell" }
+        }
+    };
+    rtt_target::set_defmt_channel(channels.up.0);
+    let mut telemetry_stream = telemetry::Stream::new(channels.up.1);
+    let mut shell = Console::new(Shell::new(channels.up.2, channels.down.0));
     if let Some(mut board) = Board::take() {
-        let display = Display::new(board.TIMER1, board.display_pins);
+        if cfg!(feature = "joystick") {
+            joystick::run(board);
+        }
+        if cfg!(feature = "bench") {
+            bench::run(&mut board.SYST);
+        }
+        let led_display = Display::new(board.TIMER1, board.display_pins);
         let adc: Adc = Adc::new(board.ADC, AdcConfig::default_10bit());
-        let anapin = board.edge.e00.into_floating_input(); // PAD0
-        let analog = Analog {
+        let (sensor, [servo_left, servo_right]) = pins::assign([
+            board.edge.e00.degrade(),
+            board.edge.e01.degrade(),
+            board.edge.e02.degrade(),
+        ]);
+        let mut analog = Analog {
             converter: adc,
-            pin: anapin,
+            _sensor: sensor.into_floating_input(),
         };
         let gpiote = Gpiote::new(board.GPIOTE);
         // Servo output pins
-        let servopin1 = board.edge.e01.into_push_pull_output(Level::Low).degrade(); // PAD1
-        let servopin2 = board.edge.e02.into_push_pull_output(Level::Low).degrade(); // PAD2
+        let servopin1 = servo_left.into_push_pull_output(Level::Low);
+        let servopin2 = servo_right.into_push_pull_output(Level::Low);
 
         // Output channel for Servo 1
         gpiote
@@ -156,6 +1065,8 @@ fn main() -> ! {
             .task_out_polarity(TaskOutPolarity::Toggle)
             .init_low();
         gpiote.channel1().task_out().write(|w| unsafe { w.bits(1) });
+        // A and B wake the sleeping main loop
+        wake::enable(&gpiote);
 
         let ppi_channels = ppi::Parts::new(board.PPI);
         // Set both servo outputs high form Timer0 CC[0]
@@ -177,42 +1088,1129 @@ fn main() -> ! {
         ppi3.set_task_endpoint(gpiote.channel1().task_out());
         ppi3.set_event_endpoint(&board.TIMER0.events_compare[2]);
         ppi3.enable();
+        // Photocell conversions in the middle of the frame, see sampler.rs
+        let mut ppi5 = ppi_channels.ppi5;
+        #[cfg(feature = "v1")]
+        ppi5.set_task_endpoint(unsafe { &(*pac::ADC::ptr()).tasks_start });
+        #[cfg(feature = "v2")]
+        ppi5.set_task_endpoint(unsafe { &(*pac::SAADC::ptr()).tasks_sample });
+        ppi5.set_event_endpoint(&board.TIMER0.events_compare[3]);
+        ppi5.enable();
+        sampler::arm();
+
+        // Before the servo timer starts, for the servo rate
+        let mut flash = Flash::new(board.NVMC, &board.FICR);
+        let mut config = Config::load(&flash);
+        let period_us = drive::period_us(config.servo_periods).unwrap_or(FRAME_US);
 
         // The Timer PAC is used directly as the HAL does not give full access to all registers
         board.TIMER0.mode.write(|w| unsafe { w.bits(0) });
-        board.TIMER0.bitmode.write(|w| unsafe { w.bits(0) });
-        // CC[0] every 20 ms (50 Hz)
-        board.TIMER0.cc[0].write(|w| unsafe { w.bits(20000) });
+        // The CC registers count ticks, see drive::PRESCALER
+        board
+            .TIMER0
+            .prescaler
+            .write(|w| unsafe { w.bits(drive::PRESCALER) });
+        board
+            .TIMER0
+            .bitmode
+            .write(|w| unsafe { w.bits(drive::BITMODE) });
+        // CC[0] every servo period, 20 ms (50 Hz) by default
+        board.TIMER0.cc[0].write(|w| unsafe { w.bits(drive::ticks(period_us)) });
         board.TIMER0.shorts.write(|w| unsafe { w.bits(1) });
         // Servo duty cycle is from 0.5 ms to 2.5 ms with 1.5 ms for center position
-        board.TIMER0.cc[1].write(|w| unsafe { w.bits(1500) });
-        board.TIMER0.cc[2].write(|w| unsafe { w.bits(1500) });
+        board.TIMER0.cc[1].write(|w| unsafe { w.bits(drive::ticks(PULSE_CENTER)) });
+        board.TIMER0.cc[2].write(|w| unsafe { w.bits(drive::ticks(PULSE_CENTER)) });
+        board.TIMER0.cc[3].write(|w| unsafe { w.bits(drive::ticks(mid_period_us(period_us))) });
         board.TIMER0.tasks_start.write(|w| unsafe { w.bits(1) });
-        // Timer0 interrupt on CC[0]
-        board.TIMER0.intenset.write(|w| unsafe { w.bits(1 << 16) });
+        // Timer0 interrupt on CC[0] and CC[3]
+        board
+            .TIMER0
+            .intenset
+            .write(|w| unsafe { w.bits(1 << 16 | 1 << 19) });
 
-        cortex_m::interrupt::free(move |cs| {
-            *ONOFF.borrow(cs).borrow_mut() = Some(false);
+        critical(move |cs| {
             *SERVO_TIMER.borrow(cs).borrow_mut() = Some(board.TIMER0);
-            *DISPLAY.borrow(cs).borrow_mut() = Some(display);
-            *ANALOG.borrow(cs).borrow_mut() = Some(analog);
+            *DISPLAY.borrow(cs).borrow_mut() = Some(led_display);
         });
         unsafe {
+            board.NVIC.set_priority(pac::Interrupt::TIMER0, PRIO_SERVO);
+            board
+                .NVIC
+                .set_priority(pac::Interrupt::TIMER1, PRIO_DISPLAY);
             pac::NVIC::unmask(pac::Interrupt::TIMER0);
-            pac::NVIC::unmask(pac::Interrupt::TIMER1);
+            // Without the display nothing refreshes it, TIMER1 stays quiet
+            if cfg!(feature = "display") {
+                pac::NVIC::unmask(pac::Interrupt::TIMER1);
+            }
+            pac::NVIC::unmask(pac::Interrupt::GPIOTE);
         }
 
+        // The radio needs the crystal oscillator. The monotonic clock runs on
+        // LFCLK synthesized from it, the RC oscillator drifts by up to 2%.
+        let _clocks = Clocks::new(board.CLOCK)
+            .enable_ext_hfosc()
+            .set_lfclk_src_synth()
+            .start_lfclk();
+        monotonic::start(board.RTC1);
+        unsafe {
+            board.NVIC.set_priority(pac::Interrupt::RTC1, PRIO_DISPLAY);
+            pac::NVIC::unmask(pac::Interrupt::RTC1);
+        }
+        errlog::set_panic_policy(config.panic);
+        let a_low = board.buttons.button_a.is_low() == Ok(true);
+        let b_low = board.buttons.button_b.is_low() == Ok(true);
+        let mut screen = Screen::new();
+        if a_low && b_low {
+            calibrate(&mut board.buttons, &mut analog, &mut config, &mut flash);
+            screen.queue(&CALIBRATED, 10);
+        } else if b_low && cfg!(feature = "radio") {
+            radio_setup(&mut board.buttons, &mut config, &mut flash);
+        } else if a_low {
+            adjust(&mut board.buttons, &mut config, &mut flash);
+            screen.queue(&CALIBRATED, 10);
+        } else if !Config::saved(&flash) {
+            wizard(&mut board.buttons, &mut analog, &mut config, &mut flash);
+        }
+        // The GATT service takes the radio, the micro:bit radio link and the
+        // beacon are off then
+        let (mut radio, mut ble) = if cfg!(feature = "gatt") {
+            let ble = Ble::start(board.RADIO, board.RTC0, ppi_channels.ppi6, &board.FICR);
+            unsafe {
+                board.NVIC.set_priority(pac::Interrupt::RADIO, PRIO_RADIO);
+                board.NVIC.set_priority(pac::Interrupt::RTC0, PRIO_RADIO);
+                pac::NVIC::unmask(pac::Interrupt::RADIO);
+                pac::NVIC::unmask(pac::Interrupt::RTC0);
+            }
+            (None, Some(ble))
+        } else {
+            let mut radio = Radio::new(board.RADIO, Ecb::init(board.ECB), &config.radio);
+            #[cfg(feature = "v1")]
+            radio.apply_trim(&board.FICR);
+            if cfg!(feature = "radio") {
+                radio.listen();
+            }
+            (Some(radio), None)
+        };
+        let mut rng = Rng::new(board.RNG);
+        let mut temperature = Temperature::new(board.TEMP);
+        // Spare edge pins in the order of expansion::Spare
+        #[cfg(feature = "v1")]
+        let spares = [
+            board.edge.e08.degrade(),
+            board.edge.e12.degrade(),
+            board.pins.p0_23.degrade(),
+            board.pins.p0_22.degrade(),
+            board.pins.p0_21.degrade(),
+            board.edge.e16.degrade(),
+        ];
+        #[cfg(feature = "v2")]
+        let spares = [
+            board.edge.e08.degrade(),
+            board.edge.e12.degrade(),
+            board.pins.p0_17.degrade(),
+            board.pins.p0_01.degrade(),
+            board.pins.p0_13.degrade(),
+            board.edge.e16.degrade(),
+        ];
+        let mut expansion = Expansion::new(spares);
+        let tx = expansion.claim(&config.addons, Addon::SerialTx);
+        let rx = expansion.claim(&config.addons, Addon::SerialRx);
+        // The V1 has one UART, the USB serial port only gets it when neither
+        // pin is assigned
+        #[cfg(feature = "v1")]
+        let (mut serial, mut usb) = match (tx, rx) {
+            (Some(tx), Some(rx)) => {
+                let edge = Edge::new(board.UART0, serial::edge_pins(tx, rx));
+                (Some(Console::new(edge)), None)
+            }
+            (None, None) => (
+                None,
+                Some(Console::new(Usb::new(board.UART0, board.uart.into()))),
+            ),
+            _ => (None, None),
+        };
+        #[cfg(feature = "v2")]
+        let mut serial = match (tx, rx) {
+            (Some(tx), Some(rx)) => {
+                let edge = Edge::new(board.UARTE1, serial::edge_pins(tx, rx));
+                Some(Console::new(edge))
+            }
+            _ => None,
+        };
+        #[cfg(feature = "v2")]
+        let mut usb = Some(Console::new(Usb::new(board.UARTE0, board.uart.into())));
+        #[cfg(feature = "v1")]
+        let led_driver = board.SPI1;
+        #[cfg(feature = "v2")]
+        let led_driver = board.PWM0;
+        let mut headlight = expansion
+            .claim(&config.addons, Addon::Headlight)
+            .map(Headlight::new);
+        let mut rainbow = Rainbow::new();
+        let mut leds = expansion
+            .claim(&config.addons, Addon::Leds)
+            .filter(|_| cfg!(feature = "leds"))
+            .map(|pin| Leds::new(led_driver, pin));
+        // The V2 speaker unless a buzzer add-on is configured
+        let buzzer = expansion.claim(&config.addons, Addon::Buzzer);
+        #[cfg(feature = "v2")]
+        let buzzer = buzzer.or(Some(board.speaker_pin.degrade()));
+        let mut markers = expansion
+            .claim(&config.addons, Addon::Marker)
+            .map(Markers::new);
+        // The first Grove module configured
+        let mut grove = grove::Kind::ALL.into_iter().find_map(|kind| {
+            expansion
+                .claim(&config.addons, kind.addon())
+                .map(|pin| Grove::new(kind, pin))
+        });
+        // DC motors with all four pins assigned
+        #[cfg(feature = "v2")]
+        let mut hbridge = match [
+            Addon::PwmLeft,
+            Addon::PwmRight,
+            Addon::DirLeft,
+            Addon::DirRight,
+        ]
+        .map(|addon| expansion.claim(&config.addons, addon))
+        {
+            [Some(pwm_left), Some(pwm_right), Some(dir_left), Some(dir_right)] => Some(
+                HBridge::new(board.PWM1, [pwm_left, pwm_right, dir_left, dir_right]),
+            ),
+            _ => None,
+        };
+        #[cfg(feature = "v2")]
+        let mut encoder = match [Addon::EncoderA, Addon::EncoderB]
+            .map(|addon| expansion.claim(&config.addons, addon))
+        {
+            [Some(a), Some(b)] => Some(Encoder::new(board.QDEC, a, b)),
+            _ => None,
+        };
+        let mut scan_head = expansion
+            .claim(&config.addons, Addon::Pan)
+            .map(ScanHead::new);
+        let mut audio = buzzer
+            .filter(|_| cfg!(feature = "audio"))
+            .map(|pin| Audio::new(board.TIMER2, ppi_channels.ppi4, pin));
+        #[cfg(feature = "v1")]
+        let mut i2c = i2c::Bus::new(board.TWI0, board.i2c);
+        #[cfg(feature = "v2")]
+        let mut i2c = i2c::Bus::new(board.TWIM0, board.i2c_external);
+        // The accelerometer bus. The board only has the instance as TWI1 and
+        // SPI1, both unused.
+        #[cfg(feature = "v2")]
+        let mut internal_i2c = twim::Twim::new(
+            unsafe { pac::Peripherals::steal() }.TWIM1,
+            board.i2c_internal.into(),
+            twim::Frequency::K100,
+        );
+
+        for record in errlog::records() {
+            if let Some(kind) = record.kind() {
+                defmt::warn!(
+                    "logged {} {=str} {}",
+                    kind.name(),
+                    record.file(),
+                    record.value
+                );
+            }
+        }
+        let mut fault = self_test(&mut analog, &mut i2c, &config, &flash).err();
+        if let Some(fault) = fault {
+            errlog::push(errlog::Kind::SelfTest, fault.code(), "");
+            // Before the fault code starts blinking
+            screen.queue(&FAULT, 20);
+            defmt::error!("self test failed: {}", fault.name());
+            // Before any sync the event time is the local time
+            log_event(
+                &mut telemetry_stream,
+                &Clock::new(),
+                Event::Fault {
+                    code: fault.code() as u8,
+                },
+            );
+            shell.fault(fault);
+            if let Some(serial) = serial.as_mut() {
+                serial.fault(fault);
+            }
+            if let Some(usb) = usb.as_mut() {
+                usb.fault(fault);
+            }
+        } else if wake::woke_from_off() {
+            screen.queue(&ARMED_PROMPT, 30);
+        }
+
+        let mut follower = LineFollower::new(&config);
+        let mut curves = curve::load(&flash);
+        critical(|cs| {
+            let mut drive = DRIVE.borrow(cs).borrow_mut();
+            drive.set_tight_turn(tight_turn(&config));
+            drive.set_trim(trim(&config));
+            drive.set_curves(curve::both(&curves));
+        });
+        let mut steering =
+            JerkLimiter::new(percent(config.steer_rate), percent(config.steer_accel));
+        let mut mode = config.mode;
+        let mut is_on = false;
+        let mut remote = STATE_STOPPED;
+        let mut last_command_ms = None;
+        let mut pair_hold = 0;
+        let mut beacon = Beacon::new(&board.FICR);
+        let mut stats = Stats::load(&flash);
+        let mut odometer = Odometer::load(&flash);
+        let mut was_on = false;
+        let mut a_was_low = false;
+        let mut b_was_low = false;
+        // Until the next audio update
+        let mut horn = false;
+        // A+B pressed and one of them still held, and whether that stopped
+        // the car
+        let mut kill_held = false;
+        let mut kill_stopped = false;
+        // A pressed alone, it starts the car after the chord window
+        let mut a_pressed_ms: Option<u32> = None;
+        // The interlock refused a start on A, reported once until A is up
+        let mut a_refused = false;
+        let mut frame = 0u32;
+        let mut status_to = None;
+        let mut battery_mv = 0;
+        // Largest servo interrupt jitter of the last second
+        let mut jitter_us = 0;
+        let mut clock = Clock::new();
+        // While line following
+        let mut was_on_line = None;
+        let mut start_at = None;
+        // Stopped at the end of the line, until the next start
+        let mut finished = false;
+        let mut photo = PhotoCell::new();
+        // Cars just ahead on the same track, heard over the radio
+        let mut convoy = Convoy::new();
+        // The second photocell on edge pin 4 with `set sensors 2`
+        let mut right_sensor: Option<RightSensor> = None;
+        let mut photo_right = PhotoCell::new();
+        let mut summary = Summary::new(0);
+        let mut replay: Option<Replay> = None;
+        // The best lap since reset and the delta to it
+        let mut ghost = Ghost::new();
+        // Following the line backwards, from `reverse` and the shuttle
+        let mut backwards = config.reverse;
+        let mut maneuver: Option<Maneuver> = None;
+        // Looking for the line at its end, with `end search`
+        let mut search: Option<Search> = None;
+        let mut script = Script::load(&flash);
+        let mut program: Option<Interpreter> = None;
+        // Steering with the pot on P3, A is the throttle
+        let mut manual: Option<Manual> = None;
+        let mut run_state = RunState::Boot;
+        // Last input while stopped
+        let mut idle_since_ms = 0u32;
         loop {
-            if let Ok(true) = board.buttons.button_a.is_low() {
-                cortex_m::interrupt::free(move |cs| {
-                    *ONOFF.borrow(cs).borrow_mut() = Some(true);
+            let a_low = board.buttons.button_a.is_low() == Ok(true);
+            let b_low = board.buttons.button_b.is_low() == Ok(true);
+            // A+B is the kill switch, enforced below after everything else
+            // that can start the car
+            let kill = a_low && b_low;
+            let running = is_on || start_at.is_some();
+            kill_held = (kill_held || kill) && (a_low || b_low);
+            kill_stopped = (kill_stopped || kill && running) && kill_held;
+            // A pressed a little before B for the chord doesn't start the
+            // car, a tap shorter than the window does on release
+            let button_ms = monotonic::now_ms();
+            let a_start = if kill_held {
+                a_pressed_ms = None;
+                false
+            } else if a_low {
+                let since = *a_pressed_ms.get_or_insert(button_ms);
+                button_ms.wrapping_sub(since) >= CHORD_MS
+            } else {
+                a_pressed_ms
+                    .take()
+                    .is_some_and(|since| button_ms.wrapping_sub(since) < CHORD_MS)
+            };
+            a_refused = a_refused && a_low;
+            if a_start && manual.is_none() && !a_refused {
+                mode = Mode::LineFollow;
+                is_on = true;
+            }
+            if b_low {
+                is_on = false;
+                start_at = None;
+            }
+            if let Some(markers) = markers.as_mut() {
+                let laps = markers.laps();
+                if markers.poll(monotonic::now_ms()) {
+                    defmt::debug!("segment {}", markers.segment());
+                    let segment = markers.segment();
+                    log_event(&mut telemetry_stream, &clock, Event::Marker { segment });
+                }
+                if markers.laps() != laps {
+                    let laps = markers.laps();
+                    log_event(&mut telemetry_stream, &clock, Event::Lap { laps });
+                }
+            }
+            let mut command = radio
+                .as_mut()
+                .filter(|_| cfg!(feature = "radio"))
+                .and_then(Radio::receive);
+            let (request, source) = match shell.poll() {
+                Some(request) => (Some(request), Source::Shell),
+                None => match serial.as_mut().and_then(Console::poll) {
+                    Some(request) => (Some(request), Source::Serial),
+                    None => match usb.as_mut().and_then(Console::poll) {
+                        Some(request) => (Some(request), Source::Usb),
+                        None => (ble.as_mut().and_then(Ble::poll), Source::Ble),
+                    },
+                },
+            };
+            if a_low || b_low || request.is_some() || command.is_some() {
+                idle_since_ms = monotonic::now_ms();
+            }
+            if let Some(request) = request {
+                let console = console(source, &mut shell, &mut serial, &mut usb, &mut ble);
+                match request {
+                    Request::Command(console_command) => {
+                        command = Some(console_command);
+                        console.reply("ok");
+                    }
+                    Request::Status => status_to = Some(source),
+                    Request::Scan => console.scan_result(i2c.scan()),
+                    Request::Stats => console.stats(&stats),
+                    Request::Log => console.log(),
+                    Request::Addon { addon, spare } => {
+                        config.addons[addon as usize] = spare;
+                        config.save(&mut flash);
+                        console.reply("ok");
+                    }
+                    Request::Effect(effect) => {
+                        config.effect = effect;
+                        config.save(&mut flash);
+                        console.reply("ok");
+                    }
+                    Request::Headlight(headlight) => {
+                        config.headlight = headlight;
+                        config.save(&mut flash);
+                        console.reply("ok");
+                    }
+                    Request::HeadlightThreshold(threshold) => {
+                        config.headlight_threshold = threshold;
+                        config.save(&mut flash);
+                        console.reply("ok");
+                    }
+                    Request::Speed(speed) => {
+                        config.speed = speed;
+                        config.save(&mut flash);
+                        console.reply("ok");
+                    }
+                    Request::Zone { first, last, speed } => {
+                        config.zones[first as usize..=last as usize].fill(speed);
+                        config.save(&mut flash);
+                        console.reply("ok");
+                    }
+                    // While driving the car continues afterwards, otherwise it
+                    // stops again
+                    Request::Move(motion) => {
+                        let motion = Maneuver::new().then_motion(motion);
+                        maneuver = Some(if is_on { motion } else { motion.then_stop() });
+                        is_on = true;
+                        console.reply("ok");
+                    }
+                    Request::EndOfLine(end_of_line) => {
+                        config.end_of_line = end_of_line;
+                        config.save(&mut flash);
+                        console.reply("ok");
+                    }
+                    // Takes effect at the next start
+                    Request::Reverse(reverse) => {
+                        config.reverse = reverse;
+                        config.save(&mut flash);
+                        console.reply("ok");
+                    }
+                    Request::Deadband(deadband) => {
+                        config.deadband = deadband;
+                        config.save(&mut flash);
+                        follower.configure(&config);
+                        console.reply("ok");
+                    }
+                    Request::RunLimit { seconds, meters } => {
+                        config.run_limit_s = seconds;
+                        config.run_limit_m = meters;
+                        config.save(&mut flash);
+                        console.reply("ok");
+                    }
+                    Request::Panic(policy) => {
+                        config.panic = policy;
+                        config.save(&mut flash);
+                        errlog::set_panic_policy(policy);
+                        console.reply("ok");
+                    }
+                    Request::I2cDevice(address) => {
+                        config.i2c_device = address;
+                        config.save(&mut flash);
+                        console.reply("ok");
+                    }
+                    Request::SteerLimits { rate, accel } => {
+                        config.steer_rate = rate;
+                        config.steer_accel = accel;
+                        config.save(&mut flash);
+                        steering.set_limits(percent(rate), percent(accel));
+                        console.reply("ok");
+                    }
+                    // Takes effect right away, only saved by a save request
+                    Request::Set(param, value) if param.set(&mut config, value) => {
+                        apply_settings(&config, &mut follower, &mut steering);
+                        console.reply("ok");
+                    }
+                    Request::Set(..) => console.reply("error"),
+                    Request::Get(Param::Adc) => match read_photo_cell(&mut analog) {
+                        Ok(reading) => console.value(Param::Adc, reading as i32),
+                        Err(()) => console.reply("error"),
+                    },
+                    Request::Get(param) => {
+                        let value = match param {
+                            Param::AdcErrors => (photo.errors() + photo_right.errors()) as i32,
+                            Param::Battery => battery::read_mv(&mut analog.converter) as i32,
+                            Param::Jitter => jitter_us as i32,
+                            Param::Grove => grove.as_ref().map_or(0, Grove::reading) as i32,
+                            #[cfg(feature = "v2")]
+                            Param::Wheel => encoder.as_ref().map_or(0, Encoder::speed_mm_s),
+                            _ => param.get(&config).unwrap_or(0),
+                        };
+                        console.value(param, value);
+                    }
+                    Request::Save => {
+                        config.save(&mut flash);
+                        console.reply("ok");
+                    }
+                    Request::Help => console.help(),
+                    Request::Menu(Some(page)) => console.menu_page(page, &config),
+                    Request::Menu(None) => console.reply("ok"),
+                    Request::Fsm => console.fsm(),
+                    Request::ServoRate(periods) => {
+                        config.servo_periods = periods;
+                        config.save(&mut flash);
+                        console.reply("ok");
+                    }
+                    // At rest, the servos would add their own noise
+                    Request::Noise(_) if is_on => console.reply("error"),
+                    Request::Manual if is_on || fault.is_some() => console.reply("error"),
+                    Request::Manual => {
+                        manual = Some(Manual::enter());
+                        is_on = true;
+                        console.reply("ok");
+                    }
+                    Request::Horn => {
+                        horn = true;
+                        console.reply("ok");
+                    }
+                    Request::Wizard if is_on => console.reply("error"),
+                    Request::Wizard => {
+                        wizard(&mut board.buttons, &mut analog, &mut config, &mut flash);
+                        apply_settings(&config, &mut follower, &mut steering);
+                        screen.invalidate();
+                        console.reply("ok");
+                    }
+                    Request::Curve(_) if is_on => console.reply("error"),
+                    Request::Curve(None) => {
+                        curves = [None, None];
+                        curve::save(&mut flash, &curves);
+                        critical(|cs| DRIVE.borrow(cs).borrow_mut().set_curves(None));
+                        console.reply("ok");
+                    }
+                    Request::Curve(Some(servo)) => {
+                        #[cfg(feature = "v1")]
+                        let measured = {
+                            let _ = servo;
+                            None
+                        };
+                        #[cfg(feature = "v2")]
+                        let measured = encoder
+                            .as_mut()
+                            .map(|encoder| measure_curve(encoder, servo));
+                        match measured {
+                            Some(measured) => {
+                                curves[servo as usize] = Some(measured);
+                                curve::save(&mut flash, &curves);
+                                let both = curve::both(&curves);
+                                critical(|cs| DRIVE.borrow(cs).borrow_mut().set_curves(both));
+                                screen.invalidate();
+                                console.reply("ok");
+                            }
+                            None => console.reply("error"),
+                        }
+                    }
+                    Request::Noise(samples) => {
+                        let mut failed = false;
+                        let noise = noise::measure(samples, || {
+                            read_photo_cell(&mut analog).unwrap_or_else(|_| {
+                                failed = true;
+                                0
+                            })
+                        });
+                        match failed {
+                            true => console.reply("error"),
+                            false => console.noise(&noise),
+                        }
+                    }
+                }
+            }
+            match command {
+                Some(Command::Start) => is_on = true,
+                Some(Command::Stop) => {
+                    is_on = false;
+                    start_at = None;
+                }
+                Some(Command::Sync { time_ms }) => clock.sync(time_ms, monotonic::now_ms()),
+                Some(Command::Position {
+                    segment,
+                    segment_ms,
+                }) => {
+                    if let Some(markers) = markers.as_ref() {
+                        let now_ms = monotonic::now_ms();
+                        let own = (markers.track_segment(), markers.segment_ms(now_ms));
+                        convoy.heard((segment, segment_ms), own, now_ms);
+                    }
+                }
+                Some(Command::StartAt { time_ms }) if clock.synced() => start_at = Some(time_ms),
+                Some(Command::StartAt { .. }) => defmt::warn!("start at needs a clock sync"),
+                Some(Command::Drive { throttle, steering }) => {
+                    mode = Mode::Remote;
+                    is_on = true;
+                    remote = StateSpeed::new(throttle, steering);
+                    last_command_ms = Some(monotonic::now_ms());
+                }
+                Some(Command::Script(ScriptCommand::Clear)) => script.clear(),
+                Some(Command::Script(ScriptCommand::Append(chunk))) => script.append(&chunk),
+                Some(Command::Script(ScriptCommand::Save)) => {
+                    let saved = script.save(&mut flash);
+                    if !saved {
+                        defmt::warn!("script too long");
+                    }
+                }
+                Some(Command::Script(ScriptCommand::Run)) => {
+                    program = Some(script.run());
+                    is_on = true;
+                }
+                Some(Command::Reset) => {
+                    shutdown(&mut stats, &mut odometer, &mut config, mode, &mut flash)
+                }
+                _ => (),
+            }
+            // Whatever the mode, a behavior, a console request or a radio
+            // command did meanwhile, until both buttons are up again
+            if kill_held {
+                is_on = false;
+                start_at = None;
+                maneuver = None;
+                program = None;
+                search = None;
+                remote = STATE_STOPPED;
+                last_command_ms = None;
+                // Staged now, the next servo period already stops
+                critical(|cs| DRIVE.borrow(cs).borrow_mut().set(Fix::ZERO, Fix::ZERO));
+                #[cfg(feature = "v2")]
+                if let Some(hbridge) = hbridge.as_mut() {
+                    hbridge.set_wheels(critical(|cs| DRIVE.borrow(cs).borrow().wheels()));
+                }
+            }
+            // Sensing and control run once per servo frame, outside the interrupt,
+            // so they can never delay the pulse width reload.
+            if !take_frame() {
+                // The serial ports and the marker ticks are polled, otherwise
+                // the loop sleeps until the next interrupt
+                if serial.is_none() && usb.is_none() && markers.is_none() {
+                    wake::sleep();
+                }
+                continue;
+            }
+            if let Some(radio) = radio.as_mut() {
+                radio.new_frame();
+            }
+            frame = frame.wrapping_add(1);
+            let now_ms = monotonic::now_ms();
+            if tuning::update(&mut config) {
+                apply_settings(&config, &mut follower, &mut steering);
+            }
+            // Only written on a change, a flash page survives about 10000 erases
+            if mode != config.mode {
+                config.mode = mode;
+                config.save(&mut flash);
+            }
+            // Not after stopping the car with A+B
+            if a_low && b_low && !is_on && !kill_stopped {
+                pair_hold += 1;
+            } else {
+                pair_hold = 0;
+            }
+            if a_low && !a_was_low {
+                log_event(&mut telemetry_stream, &clock, Event::ButtonA);
+            }
+            if b_low && !b_was_low {
+                log_event(&mut telemetry_stream, &clock, Event::ButtonB);
+            }
+            if b_low && !b_was_low && !was_on {
+                show_stats(&mut board.buttons, &stats, &odometer);
+                screen.invalidate();
+                replay = None;
+            }
+            a_was_low = a_low;
+            b_was_low = b_low;
+            if let Some(radio) = radio
+                .as_mut()
+                .filter(|_| pair_hold == PAIR_HOLD_FRAMES && cfg!(feature = "radio"))
+            {
+                pair(radio, &mut rng, &mut board.buttons, &mut config, &mut flash);
+                screen.invalidate();
+            }
+            if let Some(time_ms) = start_at {
+                if clock.reached(now_ms, time_ms) {
+                    is_on = true;
+                    start_at = None;
+                }
+            }
+            // A failed self test keeps the car from arming
+            if fault.is_some() {
+                is_on = false;
+            }
+            // The arming interlock, remote control doesn't need the photocell
+            if is_on && !was_on {
+                let reading = match mode {
+                    _ if manual.is_some() => Some(config.thresholds[1]),
+                    Mode::Remote => Some(config.thresholds[1]),
+                    // Unplugged
+                    Mode::LineFollow
+                        if floating::floating(pins::SENSOR.gpio(), || {
+                            convert_photo_cell(&mut analog)
+                        }) =>
+                    {
+                        None
+                    }
+                    Mode::LineFollow
+                        if right_sensor.as_ref().is_some_and(|sensor| {
+                            floating::floating(differential::GPIO, || sensor.convert())
+                        }) =>
+                    {
+                        None
+                    }
+                    // Fails the photocell check
+                    Mode::LineFollow => Some(read_photo_cell(&mut analog).unwrap_or(i16::MIN)),
+                };
+                let mv = battery::read_mv(&mut analog.converter);
+                let checked = match reading {
+                    Some(reading) => diagnostics::check_arming(reading, &config.thresholds, mv),
+                    None => Err(Fault::NoSensor),
+                };
+                if let Err(interlock) = checked {
+                    defmt::warn!("start refused: {}", interlock.name());
+                    screen.queue(&FAULT, 5);
+                    screen.queue(&DIGITS[interlock.code() as usize], 10);
+                    shell.interlock(interlock);
+                    if let Some(serial) = serial.as_mut() {
+                        serial.interlock(interlock);
+                    }
+                    if let Some(usb) = usb.as_mut() {
+                        usb.interlock(interlock);
+                    }
+                    is_on = false;
+                    maneuver = None;
+                    program = None;
+                    a_refused = a_low;
+                }
+            }
+            if is_on && !was_on {
+                finished = false;
+                summary = Summary::new(now_ms);
+                replay = None;
+                ghost.start();
+                backwards = config.reverse;
+                if let Some(markers) = markers.as_mut() {
+                    markers.reset(now_ms);
+                }
+            }
+            let segment = markers.as_ref().map_or(0, Markers::segment);
+            if let Some(grove) = grove
+                .as_mut()
+                .filter(|_| frame.is_multiple_of(GROVE_FRAMES))
+            {
+                grove.update();
+            }
+            // The scanning head has a single sensor of its own
+            let dual = config.sensors == 2 && scan_head.is_none();
+            match (dual, right_sensor.take()) {
+                (true, None) => right_sensor = Some(RightSensor::enter()),
+                (false, Some(sensor)) => sensor.leave(),
+                (_, sensor) => right_sensor = sensor,
+            }
+            // The line following works on the reconstructed reading of the
+            // scanning head, the ambient light level on the raw one
+            let mut sampled = sampler::take();
+            let read = photo
+                .read(|| match sampled.take() {
+                    Some(reading) => Ok(reading),
+                    None => convert_photo_cell(&mut analog),
+                })
+                .and_then(|reading| match right_sensor.as_ref() {
+                    Some(sensor) => Ok((reading, Some(photo_right.read(|| sensor.convert())?))),
+                    None => Ok((reading, None)),
+                });
+            let (raw_photo_cell, right_cell) = match read {
+                Ok(readings) => readings,
+                Err(sensor) => {
+                    if fault.is_none() {
+                        errlog::push(errlog::Kind::SelfTest, sensor.code(), "");
+                        defmt::error!("photocell failed, {} conversion errors", photo.errors());
+                        let code = sensor.code() as u8;
+                        log_event(&mut telemetry_stream, &clock, Event::Fault { code });
+                        shell.fault(sensor);
+                        if let Some(serial) = serial.as_mut() {
+                            serial.fault(sensor);
+                        }
+                        if let Some(usb) = usb.as_mut() {
+                            usb.fault(sensor);
+                        }
+                    }
+                    fault = Some(sensor);
+                    is_on = false;
+                    // Stopped, only the headlight sees it
+                    (config.headlight_threshold, None)
+                }
+            };
+            let photo_cell = match scan_head.as_mut() {
+                Some(head) => {
+                    head.pulse(|| critical(servo_timer_us).unwrap_or(0));
+                    head.update(raw_photo_cell, &config.thresholds)
+                }
+                None => raw_photo_cell,
+            };
+            let on_line = match right_cell {
+                Some(right) => !differential::lost(photo_cell, right, &config.thresholds),
+                None => photo_cell > config.thresholds[0] && photo_cell <= config.thresholds[2],
+            };
+            let following = is_on && mode == Mode::LineFollow && manual.is_none();
+            match (following.then_some(on_line), was_on_line) {
+                (Some(false), Some(true)) => {
+                    log_event(&mut telemetry_stream, &clock, Event::LineLost)
+                }
+                (Some(true), Some(false)) => {
+                    log_event(&mut telemetry_stream, &clock, Event::LineFound)
+                }
+                _ => (),
+            }
+            was_on_line = following.then_some(on_line);
+            let failsafe = last_command_ms
+                .is_none_or(|ms: u32| now_ms.wrapping_sub(ms) > config.failsafe_ms as u32);
+            if search.is_some() && on_line {
+                defmt::info!("line found");
+                search = None;
+                maneuver = None;
+            }
+            if let Some(sweeps) = search.as_mut().filter(|_| maneuver.is_none()) {
+                maneuver = sweeps.sweep(&mut rng);
+                if maneuver.is_none() {
+                    defmt::info!("line search failed");
+                    search = None;
+                    is_on = false;
+                    finished = true;
+                }
+            }
+            let maneuver_step = maneuver.as_mut().and_then(Maneuver::next);
+            if maneuver_step.is_none() {
+                if maneuver.as_ref().is_some_and(Maneuver::stops) {
+                    is_on = false;
+                }
+                maneuver = None;
+            }
+            // Maneuvers, like the turn at the end of the line, pause a
+            // script, the car stops when it ends
+            let script_step = match (maneuver_step, program.as_mut()) {
+                (None, Some(program)) => program.next(&Sensors {
+                    on_line,
+                    segment,
+                    grove: grove.as_ref().map_or(0, Grove::reading),
+                }),
+                _ => None,
+            };
+            if program.is_some() && maneuver_step.is_none() && script_step.is_none() {
+                is_on = false;
+            }
+            if !is_on {
+                maneuver = None;
+                program = None;
+                search = None;
+                if let Some(manual) = manual.take() {
+                    manual.leave();
+                }
+            }
+            let state = match (mode, maneuver_step.or(script_step)) {
+                _ if !is_on => {
+                    follower.reset();
+                    STATE_STOPPED
+                }
+                (_, Some((throttle, steering))) => StateSpeed::new(throttle, steering),
+                _ if manual.is_some() => {
+                    let throttle = if a_low { manual::THROTTLE } else { Fix::ZERO };
+                    match manual.as_ref().and_then(Manual::steering) {
+                        Some(steering) => StateSpeed::new(throttle, steering),
+                        None => STATE_STOPPED,
+                    }
+                }
+                (Mode::LineFollow, None) => {
+                    let step = match right_cell {
+                        Some(right) => follower.step_differential(photo_cell, right),
+                        None => follower.step(photo_cell),
+                    };
+                    if backwards {
+                        step.reversed()
+                    } else {
+                        step
+                    }
+                }
+                // Radio loss failsafe
+                (Mode::Remote, None) if failsafe => STATE_STOPPED,
+                (Mode::Remote, None) => remote,
+            };
+            if is_on && mode == Mode::LineFollow && follower.end_of_line() {
+                defmt::info!("end of line");
+                follower.reset();
+                match config.end_of_line {
+                    EndOfLine::Stop => {
+                        is_on = false;
+                        finished = true;
+                    }
+                    EndOfLine::TurnAround => {
+                        maneuver = Some(Maneuver::new().then(maneuver::THREE_POINT_TURN));
+                    }
+                    // Sweeps from the next frame on
+                    EndOfLine::Search => search = Some(Search::new()),
+                    EndOfLine::Shuttle => backwards = !backwards,
+                }
+            }
+            // Maneuvers and scripts are timed for their own speed
+            let throttle = match maneuver_step.or(script_step) {
+                Some(_) => state.throttle,
+                None if following => {
+                    let speed = percent(config.segment_speed(segment)) * convoy.speed(now_ms);
+                    state.throttle * speed
+                }
+                None => state.throttle * percent(config.segment_speed(segment)),
+            };
+            let steer = steering.update(state.steering);
+            critical(|cs| DRIVE.borrow(cs).borrow_mut().set(throttle, steer));
+            #[cfg(feature = "v2")]
+            if let Some(hbridge) = hbridge.as_mut() {
+                hbridge.set_wheels(critical(|cs| DRIVE.borrow(cs).borrow().wheels()));
+            }
+            let frame_cm = throttle * CM_PER_FRAME;
+            #[cfg(feature = "v2")]
+            let frame_cm = match encoder.as_mut() {
+                Some(encoder) => encoder.update(now_ms),
+                None => frame_cm,
+            };
+            stats.update(&mut flash, is_on, frame_cm);
+            odometer.update(&mut flash, is_on, frame_cm, now_ms);
+            if is_on {
+                let laps = markers.as_ref().map_or(0, Markers::laps);
+                summary.update(now_ms, throttle, laps, battery_mv);
+                if let Some(markers) = markers.as_ref() {
+                    ghost.update(now_ms, laps, markers.track_segment());
+                }
+            } else if was_on {
+                replay = Some(summary.replay());
+            }
+            // Keeps a car that escaped the track from driving until the
+            // battery is flat
+            if stats.run_limit_reached(config.run_limit_s, config.run_limit_m) {
+                defmt::warn!("run limit reached");
+                is_on = false;
+            }
+            if let Some(audio) = audio.as_mut() {
+                if core::mem::take(&mut horn) {
+                    audio.play(Voice::Horn, audio::HORN);
+                }
+                if is_on != was_on {
+                    let signal = if is_on { audio::START } else { audio::STOP };
+                    audio.play(Voice::Signal, signal);
+                }
+                audio.update();
+            }
+            was_on = is_on;
+            // Keeps a forgotten car from draining its batteries, waiting for
+            // a start at time counts as in use
+            if is_on || start_at.is_some() {
+                idle_since_ms = now_ms;
+            }
+            let idle_ms = now_ms.wrapping_sub(idle_since_ms);
+            let power_off_ms = config.power_off_min as u32 * 60_000;
+            if power_off_ms > 0 && idle_ms >= power_off_ms {
+                defmt::info!("idle, power off");
+                shell.power_off();
+                if let Some(serial) = serial.as_mut() {
+                    serial.power_off();
+                }
+                if let Some(usb) = usb.as_mut() {
+                    usb.power_off();
+                }
+                if let Some(leds) = leds.as_mut() {
+                    leds.write(&lights::off());
+                }
+                if let Some(headlight) = headlight.as_mut() {
+                    headlight.off();
+                }
+                #[cfg(feature = "v1")]
+                let motion = motion::arm(i2c.device());
+                #[cfg(feature = "v2")]
+                let motion = motion::arm(&mut internal_i2c);
+                if !motion {
+                    defmt::warn!("no accelerometer, only the buttons wake the car");
+                }
+                power_off(motion);
+            }
+            let next_state = match mode {
+                _ if fault.is_some() => RunState::Fault,
+                _ if maneuver.is_some() => RunState::Maneuver,
+                _ if program.is_some() => RunState::Script,
+                _ if manual.is_some() => RunState::Manual,
+                Mode::LineFollow if is_on => RunState::LineFollow,
+                Mode::Remote if is_on => RunState::Remote,
+                _ if finished => RunState::Finished,
+                _ if start_at.is_some() => RunState::Armed,
+                _ => RunState::Idle,
+            };
+            if next_state != run_state {
+                invariant!(
+                    fsm::allowed(run_state, next_state),
+                    "transition {=str} -> {=str} missing in fsm.rs",
+                    run_state.name(),
+                    next_state.name()
+                );
+                if !fsm::allowed(run_state, next_state) {
+                    defmt::warn!(
+                        "transition {=str} -> {=str} missing in fsm.rs",
+                        run_state.name(),
+                        next_state.name()
+                    );
+                }
+                defmt::debug!("{=str} -> {=str}", run_state.name(), next_state.name());
+                let (from, to) = (run_state as u8, next_state as u8);
+                log_event(&mut telemetry_stream, &clock, Event::State { from, to });
+                run_state = next_state;
+            }
+            let blink_on = frame % BLINK_FRAMES < BLINK_FRAMES / 2;
+            let fault_on = fault.is_some_and(|fault| fault.blink_on(frame));
+            let attract = fault.is_none() && attract::active(idle_ms);
+            if frame.is_multiple_of(DISPLAY_FRAMES) {
+                let summary_image = replay.as_mut().and_then(Replay::next);
+                if summary_image.is_none() {
+                    replay = None;
+                }
+                // The delta to the ghost lap while driving
+                let summary_image = summary_image.or_else(|| ghost.next().filter(|_| is_on));
+                let image = match (fault, summary_image) {
+                    (Some(_), _) if fault_on => &FAULT,
+                    (Some(_), _) => &BLANK,
+                    (None, _) if attract => attract::image(idle_ms),
+                    (None, Some(image)) => image,
+                    (None, None) if finished => &FINISHED,
+                    (None, None) => state_image(&state.state, blink_on),
+                };
+                if let Some(image) = screen.update(image) {
+                    show(image);
+                }
+            }
+            if let Some(headlight) = headlight.as_mut() {
+                headlight.update(config.headlight, config.headlight_threshold, raw_photo_cell);
+            }
+            if let Some(leds) = leds.as_mut() {
+                let (speed, braking) = critical(|cs| {
+                    let drive = DRIVE.borrow(cs).borrow();
+                    (drive.forward(), drive.braking())
+                });
+                let mut colors = match config.effect {
+                    _ if fault.is_some() => lights::fault(fault_on),
+                    _ if braking => lights::brake(),
+                    _ if attract => attract::colors(idle_ms, frame, &mut rainbow),
+                    _ if !is_on => lights::idle(frame),
+                    Effect::Status => lights::status(state.state),
+                    Effect::Rainbow => rainbow.update(speed),
+                };
+                if is_on {
+                    if let Some(delta_ms) = ghost.delta_ms().filter(|_| !braking && fault.is_none())
+                    {
+                        colors = lights::ghost(delta_ms);
+                    }
+                    lights::blinker(&mut colors, state.state, blink_on);
+                }
+                leds.write(&colors);
+            }
+            if frame.is_multiple_of(JITTER_FRAMES) {
+                temperature.update();
+                jitter_us = critical(|cs| JITTER.borrow(cs).borrow_mut().take_max());
+                if jitter_us > jitter::LIMIT_US {
+                    defmt::warn!("servo interrupt jitter {} us", jitter_us);
+                }
+                let deferred = critical(|cs| JITTER.borrow(cs).borrow_mut().take_deferred());
+                if deferred > 0 {
+                    defmt::warn!("{} late servo reloads deferred", deferred);
+                }
+            }
+            let battery_due = frame.is_multiple_of(BEACON_FRAMES);
+            if battery_due || status_to.is_some() {
+                battery_mv = battery::read_mv(&mut analog.converter);
+            }
+            if let Some(ble) = ble.as_mut().filter(|_| battery_due) {
+                ble.update(&gatt::Values {
+                    state: state.state as u8,
+                    is_on,
+                    remote: matches!(mode, Mode::Remote),
+                    throttle: to_percent(throttle),
+                    steering: to_percent(steer),
+                    speed: config.speed,
+                    zones: config.zones,
+                    photo_cell,
+                    battery_mv,
                 });
             }
-            if let Ok(true) = board.buttons.button_b.is_low() {
-                cortex_m::interrupt::free(move |cs| {
-                    *ONOFF.borrow(cs).borrow_mut() = Some(false);
+            // After the last ADC read of the frame
+            sampler::arm();
+            if cfg!(feature = "telemetry") {
+                telemetry_stream.send(&telemetry::Record {
+                    frame,
+                    time_ms: clock.now(now_ms),
+                    state: state.state as u8,
+                    is_on,
+                    remote: matches!(mode, Mode::Remote),
+                    photo_cell,
+                    segment,
+                    throttle: to_percent(throttle),
+                    steering: to_percent(steer),
+                    battery_mv,
+                    temperature: temperature.quarters(),
                 });
             }
+            // For the cars behind, between the battery frames
+            if let Some(markers) = markers.as_ref().filter(|_| {
+                following && cfg!(feature = "radio") && frame % convoy::SEND_FRAMES == 2
+            }) {
+                let segment_ms = markers.segment_ms(now_ms).min(u16::MAX as u32) as u16;
+                if let Some(radio) = radio.as_mut() {
+                    radio.send_position(markers.track_segment(), segment_ms);
+                }
+            }
+            let advertise = cfg!(feature = "beacon") && battery_due;
+            if advertise || status_to.is_some() {
+                let telemetry = beacon::Telemetry {
+                    state: state.state as u8,
+                    is_on,
+                    remote: matches!(mode, Mode::Remote),
+                    photo_cell,
+                    battery_mv,
+                };
+                if let Some(radio) = radio.as_mut().filter(|_| advertise) {
+                    radio.advertise(beacon.address(), &beacon.payload(&telemetry));
+                }
+                if let Some(source) = status_to.take() {
+                    console(source, &mut shell, &mut serial, &mut usb, &mut ble).status(&telemetry);
+                }
+            }
         }
     }
     panic!("End");
@@ -221,51 +2219,89 @@ fn main() -> ! {
 #[interrupt]
 fn TIMER0() {
     // Change Servo position at the start of the duty cycle. Then there is no race condition
-    // between changing the duty cycle and a CC event.
-    static mut STATE: StateSpeed = STATE_STOPPED;
-    static mut PHOTO_CELL: i16 = 0;
-    static mut IS_ON: bool = false;
-
+    // between changing the duty cycle and a CC event, unless the interrupt runs
+    // late, see drive::can_commit. Nothing else happens here, apart from the
+    // latency measurement. In the middle of the first period of a frame the
+    // main loop is woken for the control work.
     cortex_m::interrupt::free(|cs| {
-        if let Some(timer) = SERVO_TIMER.borrow(cs).borrow_mut().as_mut() {
-            timer.cc[1].write(|w| unsafe { w.bits(STATE.lspeed) });
-            timer.cc[2].write(|w| unsafe { w.bits(STATE.rspeed) });
+        let mut timer = SERVO_TIMER.borrow(cs).borrow_mut();
+        let Some(timer) = timer.as_mut() else {
+            return;
+        };
+        let mut mid_frame = MID_FRAME.borrow(cs).borrow_mut();
+        let mut period = PERIOD.borrow(cs).borrow_mut();
+        let period_us = drive::micros(timer.cc[0].read().bits());
+        if timer.events_compare[0].read().bits() != 0 {
+            timer.tasks_capture[3].write(|w| unsafe { w.bits(1) });
+            let timer_us = drive::micros(timer.cc[3].read().bits());
+            timer.cc[3].write(|w| unsafe { w.bits(drive::ticks(mid_period_us(period_us))) });
+            let mut jitter = JITTER.borrow(cs).borrow_mut();
+            jitter.record(timer_us);
+            let drive = DRIVE.borrow(cs).borrow();
+            let current = [
+                drive::micros(timer.cc[1].read().bits()),
+                drive::micros(timer.cc[2].read().bits()),
+            ];
+            let staged = [drive.lpulse(), drive.rpulse()];
+            invariant!(
+                staged
+                    .iter()
+                    .all(|pulse| (PULSE_MIN..=PULSE_MAX).contains(pulse)),
+                "servo pulses {} us out of range",
+                staged
+            );
+            if drive::can_commit(timer_us, current, staged) {
+                timer.cc[1].write(|w| unsafe { w.bits(drive::ticks(staged[0])) });
+                timer.cc[2].write(|w| unsafe { w.bits(drive::ticks(staged[1])) });
+            } else {
+                jitter.defer();
+            }
             timer.events_compare[0].write(|w| unsafe { w.bits(0) });
+            *period = (*period + 1) % (FRAME_US / period_us);
+            if *period == 0 {
+                *mid_frame = MidFrame::Ahead;
+            }
         }
-        #[cfg(feature = "v1")]
-        if let Some(analog) = ANALOG.borrow(cs).borrow_mut().as_mut() {
-            *PHOTO_CELL = analog.converter.read_channel(&mut analog.pin);
-        }
-        #[cfg(feature = "v2")]
-        if let Some(analog) = ANALOG.borrow(cs).borrow_mut().as_mut() {
-            match analog.converter.read_channel(&mut analog.pin) {
-                Ok(v) => *PHOTO_CELL = v,
-                Err(_e) => *PHOTO_CELL = 0,
-            };
-        }
-        if let Some(onoff) = ONOFF.borrow(cs).borrow().as_ref() {
-            *IS_ON = *onoff;
+        if timer.events_compare[3].read().bits() != 0 || *mid_frame == MidFrame::Missed {
+            timer.events_compare[3].write(|w| unsafe { w.bits(0) });
+            // Once per frame, a pended and a real compare can both arrive
+            if *period == 0 && *mid_frame != MidFrame::Done {
+                *mid_frame = MidFrame::Done;
+                *FRAME.borrow(cs).borrow_mut() = true;
+                cortex_m::asm::sev();
+            }
         }
     });
+}
 
-    if *IS_ON {
-        match PHOTO_CELL {
-            i16::MIN..=64 => *STATE = STATE_LEFT,
-            65..=220 => *STATE = STATE_FORWARD,
-            221..=320 => *STATE = STATE_BACK,
-            321..=i16::MAX => *STATE = STATE_RIGHT,
-        }
-    } else {
-        *STATE = STATE_STOPPED;
-    }
-    display(&STATE.state);
+#[interrupt]
+fn RTC1() {
+    monotonic::overflow();
+}
+
+#[interrupt]
+fn RADIO() {
+    ble::radio_interrupt();
+}
+
+#[interrupt]
+fn RTC0() {
+    ble::rtc_interrupt();
+}
+
+#[interrupt]
+fn GPIOTE() {
+    wake::acknowledge();
 }
 
 #[interrupt]
 fn TIMER1() {
-    cortex_m::interrupt::free(|cs| {
-        if let Some(display) = DISPLAY.borrow(cs).borrow_mut().as_mut() {
-            display.handle_display_event();
-        }
-    });
+    // No global critical section, so the servo timer interrupt can preempt
+    // the display refresh. The main loop only touches the display in
+    // critical sections, which mask this interrupt, and Timer0 never does.
+    let cs = unsafe { CriticalSection::new() };
+    let mut display = DISPLAY.borrow(&cs).borrow_mut();
+    if let Some(display) = display.as_mut() {
+        display.handle_display_event();
+    }
 }
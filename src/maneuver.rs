@@ -0,0 +1,258 @@
+// Maneuvers: timed sequences of throttle and steering commands that the main
+// loop plays one step per servo frame instead of the line follower or remote
+// control, so a maneuver never blocks the loop.
+//
+// Behaviors are written as sequences of motion primitives (forward, turn in
+// place, arc) or canned step tables. There is no wheel odometry, distances
+// and angles are converted to frames from the wheel speed and track width in
+// drive.rs, and from the wheel speeds the mixer makes of a step. The V2 has
+// an accelerometer and a magnetometer but no gyro, and the magnetometer is
+// disturbed by the servo motors, so turns are open loop on both boards.
+// Maneuvers drive at their own speed, the speed profile doesn't scale them,
+// and the tight turn mode bends arcs steered beyond its threshold.
+//
+// Steps with a ramp follow an S-curve speed profile: throttle and steering
+// rise along a smoothstep over the ramp frames, cruise and come down the
+// same way, so a start from standstill doesn't spin the wheels and a stop
+// doesn't skid on a smooth floor. The ramps cover half the distance of their
+// frames, the step is one ramp longer than its frames and still covers the
+// distance. Steering scales with the throttle and keeps the radius.
+
+use crate::drive::{CM_PER_FRAME, TRACK_CM};
+use crate::fixed::Fix;
+use crate::mixer::Mixer;
+
+// 20 m, a wider arc is as good as straight on a table top track
+pub const MAX_RADIUS_CM: u16 = 2000;
+
+#[derive(Clone, Copy)]
+pub struct Step {
+    pub throttle: Fix,
+    pub steering: Fix,
+    // At full throttle and steering
+    pub frames: u16,
+    // Frames of each ramp, 0 jumps to the speed
+    pub ramp: u16,
+}
+
+impl Step {
+    // Frames the step plays for
+    pub fn duration(&self) -> u16 {
+        self.frames.saturating_add(self.ramp())
+    }
+
+    // Throttle and steering at a frame of the step
+    pub fn at(&self, frame: u16) -> (Fix, Fix) {
+        let ramp = self.ramp();
+        let from_end = self.duration().saturating_sub(frame + 1);
+        let scale = match frame.min(from_end) {
+            i if i < ramp => s_curve(i, ramp),
+            _ => Fix::ONE,
+        };
+        (self.throttle * scale, self.steering * scale)
+    }
+
+    // Steps shorter than a ramp are all ramp
+    fn ramp(&self) -> u16 {
+        self.ramp.min(self.frames)
+    }
+}
+
+// Smoothstep at the middle of frame i of n, the frames of a ramp add up to
+// half of n
+fn s_curve(i: u16, n: u16) -> Fix {
+    let x = Fix::from_int(2 * i as i32 + 1) / Fix::from_int(2 * n as i32);
+    x * x * (Fix::from_int(3) - Fix::from_int(2) * x)
+}
+
+const fn step(throttle: f32, steering: f32, frames: u16) -> Step {
+    Step {
+        throttle: Fix::from_f32(throttle),
+        steering: Fix::from_f32(steering),
+        frames,
+        ramp: RAMP_FRAMES,
+    }
+}
+
+// Turns the car around in a confined space: forward arc, reverse arc, forward
+// arc, with short stops in between. With differential drive the steering
+// alone sets the turn direction, so the reverse arc keeps the same steering
+// and the nose keeps swinging the same way. Each arc pivots about the inner
+// wheel by about 60°.
+pub const THREE_POINT_TURN: &[Step] = &[
+    step(0.5, 0.5, 26),
+    step(0.0, 0.0, 5),
+    step(-0.5, 0.5, 26),
+    step(0.0, 0.0, 5),
+    step(0.5, 0.5, 26),
+];
+
+const MAX_STEPS: usize = 8;
+// Throttle, or wheel speed when turning in place, of the motion primitives
+const MOTION_SPEED: Fix = Fix::from_f32(0.5);
+// 200 ms up to speed and back down, for canned steps and motion primitives
+const RAMP_FRAMES: u16 = 10;
+
+// What the line follower does at the end of the line
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EndOfLine {
+    Stop,
+    TurnAround,
+    // Sweeps for the line, see search.rs
+    Search,
+    // Follows the line back the other way
+    Shuttle,
+}
+
+// Motion primitives, positive angles turn towards CarState::Left
+#[derive(Clone, Copy)]
+pub enum Motion {
+    // Negative distances drive backwards
+    Forward {
+        cm: i16,
+    },
+    // In place, with counter-rotating wheels
+    Turn {
+        degrees: i16,
+    },
+    // Forward along a circle at speed percent, the radius is at the center
+    // of the car. Radii below half the track width pivot about the inner
+    // wheel, radii are at most MAX_RADIUS_CM.
+    Arc {
+        radius_cm: u16,
+        degrees: i16,
+        speed: u8,
+    },
+}
+
+impl Motion {
+    pub fn step(self) -> Step {
+        match self {
+            Motion::Forward { cm } => Step {
+                throttle: with_sign(cm, MOTION_SPEED),
+                steering: Fix::ZERO,
+                frames: frames(cm.unsigned_abs() as i64 * 100, MOTION_SPEED),
+                ramp: RAMP_FRAMES,
+            },
+            // Each wheel runs along a circle of half the track width
+            Motion::Turn { degrees } => Step {
+                throttle: Fix::ZERO,
+                steering: with_sign(degrees, MOTION_SPEED),
+                frames: frames(arc_cm(TRACK_CM / 2, degrees), MOTION_SPEED),
+                ramp: RAMP_FRAMES,
+            },
+            Motion::Arc {
+                radius_cm,
+                degrees,
+                speed,
+            } => arc(
+                radius_cm,
+                degrees,
+                Fix::from_int(speed as i32) * Fix::from_f32(0.01),
+            ),
+        }
+    }
+}
+
+// The wheels run at throttle +- steering, the radius is throttle / steering
+// half track widths. A small radius at a high speed asks more than full
+// speed of the outer wheel, the mixer then slows down both, and the frames
+// follow from the speed it leaves.
+pub fn arc(radius_cm: u16, degrees: i16, speed: Fix) -> Step {
+    let radius = (radius_cm as i32).clamp(TRACK_CM / 2, MAX_RADIUS_CM as i32);
+    let steering = speed * Fix::from_int(TRACK_CM) / Fix::from_int(2 * radius);
+    let wheels = Mixer::new().mix(speed, steering);
+    Step {
+        throttle: speed,
+        steering: with_sign(degrees, steering),
+        frames: frames(arc_cm(radius, degrees), wheels.forward()),
+        ramp: RAMP_FRAMES,
+    }
+}
+
+fn with_sign(sign: i16, value: Fix) -> Fix {
+    if sign < 0 {
+        -value
+    } else {
+        value
+    }
+}
+
+// In hundredths of a cm, as many turns and a long radius don't fit a Fix
+fn arc_cm(radius_cm: i32, degrees: i16) -> i64 {
+    radius_cm as i64 * degrees.unsigned_abs() as i64 * 31_416 / 18_000
+}
+
+// Frames to cover the distance in hundredths of a cm at speed
+fn frames(hundredths: i64, speed: Fix) -> u16 {
+    // In ten thousandths of a cm, a fraction of a cm per frame
+    let per_frame = (speed * CM_PER_FRAME * Fix::from_int(10_000))
+        .to_int()
+        .max(1);
+    (hundredths * 100 / per_frame as i64).clamp(0, u16::MAX as i64) as u16
+}
+
+pub struct Maneuver {
+    steps: [Step; MAX_STEPS],
+    len: usize,
+    index: usize,
+    frames: u16,
+    stop: bool,
+}
+
+impl Maneuver {
+    pub const fn new() -> Self {
+        Maneuver {
+            steps: [step(0.0, 0.0, 0); MAX_STEPS],
+            len: 0,
+            index: 0,
+            frames: 0,
+            stop: false,
+        }
+    }
+
+    // Appends steps, beyond MAX_STEPS in total they are dropped
+    pub fn then(mut self, steps: &[Step]) -> Self {
+        for step in steps {
+            if self.len < MAX_STEPS {
+                self.steps[self.len] = *step;
+                self.len += 1;
+            }
+        }
+        self
+    }
+
+    pub fn then_motion(self, motion: Motion) -> Self {
+        self.then(&[motion.step()])
+    }
+
+    // Stops the car once the maneuver is over, instead of returning to the
+    // line follower or remote control
+    pub fn then_stop(mut self) -> Self {
+        self.stop = true;
+        self
+    }
+
+    pub fn stops(&self) -> bool {
+        self.stop
+    }
+
+    // Throttle and steering for this frame, None once the maneuver is over
+    pub fn next(&mut self) -> Option<(Fix, Fix)> {
+        // A step of no length, like `forward 0`, is done at once
+        while self.steps[..self.len]
+            .get(self.index)
+            .is_some_and(|step| step.duration() == 0)
+        {
+            self.index += 1;
+        }
+        let step = self.steps[..self.len].get(self.index)?;
+        let speeds = step.at(self.frames);
+        self.frames += 1;
+        if self.frames >= step.duration() {
+            self.index += 1;
+            self.frames = 0;
+        }
+        Some(speeds)
+    }
+}
@@ -0,0 +1,58 @@
+// Manual mode for testing the mixer and the servos without a radio: a
+// potentiometer on edge pin 3 steers, A is the throttle. The wiper goes to
+// P3, the ends to 3V and GND. P3 is a display column, so while manual mode
+// runs the pin is switched to an input and that column stays dark. The
+// conversion runs between taking the frame's photocell sample and arming the
+// next one, see sampler.rs.
+
+use crate::fixed::Fix;
+use crate::pins::port;
+use crate::sampler;
+
+// Edge pin 3 is P0.04, AIN5 on the V1 and P0.31, AIN7 on the V2
+#[cfg(feature = "v1")]
+const POT_PIN: usize = 4;
+#[cfg(feature = "v1")]
+const POT_AIN: u8 = 5;
+#[cfg(feature = "v2")]
+const POT_PIN: usize = 31;
+#[cfg(feature = "v2")]
+const POT_AIN: u8 = 7;
+
+// Input with the input buffer disconnected, for an analog pin
+const INPUT_ANALOG: u32 = 1 << 1;
+// The middle of the 10 bit range is straight ahead
+const CENTER: i32 = 512;
+// Turns of the pot this close to the middle count as straight ahead
+const DEADBAND: i32 = 16;
+pub const THROTTLE: Fix = Fix::from_f32(0.5);
+
+// Only the pin config changes, the display writes the output levels only
+pub struct Manual {
+    // The display's pin config, restored on leave
+    pin_cnf: u32,
+}
+
+impl Manual {
+    pub fn enter() -> Self {
+        let pin_cnf = port().pin_cnf[POT_PIN].read().bits();
+        port().pin_cnf[POT_PIN].write(|w| unsafe { w.bits(INPUT_ANALOG) });
+        Manual { pin_cnf }
+    }
+
+    pub fn leave(self) {
+        port().pin_cnf[POT_PIN].write(|w| unsafe { w.bits(self.pin_cnf) });
+    }
+
+    // Steering from the pot, -1 fully right to 1 fully left, None if the
+    // conversion failed
+    pub fn steering(&self) -> Option<Fix> {
+        // Values below ground read negative on the V2
+        let offset = sampler::convert(POT_AIN).ok()?.max(0) as i32 - CENTER;
+        if offset.abs() < DEADBAND {
+            return Some(Fix::ZERO);
+        }
+        let steering = Fix::from_int(offset) / Fix::from_int(CENTER);
+        Some(steering.clamp(-Fix::ONE, Fix::ONE))
+    }
+}
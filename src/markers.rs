@@ -0,0 +1,100 @@
+// Side marker ticks for track segmentation (add-on `marker`): a digital
+// reflectance sensor beside the line, like a TCRT5000 module, reads high
+// over black. A short dark blip is a marker tick and starts the next
+// segment, longer dark stretches are crossings or the line itself and are
+// ignored. Two ticks in a row within 400 ms mark the start and finish line
+// and count a lap, both still start a segment. The pin is polled from the
+// main loop, not per frame, so ticks shorter than a frame are seen at full
+// speed.
+
+use embedded_hal::digital::InputPin;
+use microbit::hal::gpio::{Disconnected, Floating, Input, Pin};
+
+// A tick lasts about 50 ms at full speed for a 1 cm marker
+const TICK_MIN_MS: u32 = 5;
+const TICK_MAX_MS: u32 = 250;
+// From the end of one tick to the end of the next
+const LAP_MARK_MS: u32 = 400;
+
+pub struct Markers {
+    pin: Pin<Input<Floating>>,
+    dark_since: Option<u32>,
+    segment: u8,
+    // The segment at the last lap mark and the start of the current one
+    lap_segment: u8,
+    entered_ms: u32,
+    last_tick: Option<u32>,
+    laps: u16,
+}
+
+impl Markers {
+    pub fn new(pin: Pin<Disconnected>) -> Self {
+        Markers {
+            pin: pin.into_floating_input(),
+            dark_since: None,
+            segment: 0,
+            lap_segment: 0,
+            entered_ms: 0,
+            last_tick: None,
+            laps: 0,
+        }
+    }
+
+    // Back to segment 0 and no laps at the start of a run
+    pub fn reset(&mut self, now_ms: u32) {
+        self.segment = 0;
+        self.lap_segment = 0;
+        self.entered_ms = now_ms;
+        self.last_tick = None;
+        self.laps = 0;
+    }
+
+    // Lap marks passed since the start of the run
+    pub fn laps(&self) -> u16 {
+        self.laps
+    }
+
+    // Segment index since the start of the run, wraps after 255 ticks
+    pub fn segment(&self) -> u8 {
+        self.segment
+    }
+
+    // Segment index since the last lap mark, the same on every car on the
+    // track. Until the first lap mark the run is taken to start at one.
+    pub fn track_segment(&self) -> u8 {
+        self.segment.wrapping_sub(self.lap_segment)
+    }
+
+    // Time in the current segment
+    pub fn segment_ms(&self, now_ms: u32) -> u32 {
+        now_ms.wrapping_sub(self.entered_ms)
+    }
+
+    // True when a tick just ended
+    pub fn poll(&mut self, now_ms: u32) -> bool {
+        let dark = self.pin.is_high() == Ok(true);
+        match (dark, self.dark_since) {
+            (true, None) => self.dark_since = Some(now_ms),
+            (false, Some(since)) => {
+                self.dark_since = None;
+                let length = now_ms.wrapping_sub(since);
+                if (TICK_MIN_MS..=TICK_MAX_MS).contains(&length) {
+                    self.segment = self.segment.wrapping_add(1);
+                    self.entered_ms = now_ms;
+                    // The second tick of a lap mark doesn't start another one
+                    match self.last_tick {
+                        Some(last) if now_ms.wrapping_sub(last) <= LAP_MARK_MS => {
+                            self.laps = self.laps.wrapping_add(1);
+                            self.lap_segment = self.segment;
+                            self.last_tick = None;
+                        }
+                        _ => self.last_tick = Some(now_ms),
+                    }
+                    return true;
+                }
+            }
+            _ => (),
+        }
+        false
+    }
+}
@@ -0,0 +1,57 @@
+// Paged settings browser on a console, entered with `menu`. Each page lists
+// a few settings with their number, value and range:
+//
+//   settings 1/3
+//   0 kp=1.200 0.000..65.000
+//   ..
+//   <n> <value> edit, n next, p previous, s save, q quit
+//
+// `<n> <value>` is a `set` of setting n, `s` a `save`, other commands work
+// as usual while the menu is open. Edits are answered with "ok" or "error".
+
+use core::fmt::Write;
+
+use crate::config::Config;
+use crate::console::{self, write_number, Request};
+use crate::params::SETTINGS;
+
+const PAGE_LEN: usize = 4;
+pub const PAGES: u8 = SETTINGS.len().div_ceil(PAGE_LEN) as u8;
+
+// Input while the menu shows page
+pub fn parse(line: &str, page: u8) -> Option<Request> {
+    let mut words = line.split_ascii_whitespace();
+    let request = match words.next()? {
+        "n" => Request::Menu(Some((page + 1) % PAGES)),
+        "p" => Request::Menu(Some((page + PAGES - 1) % PAGES)),
+        "s" => Request::Save,
+        "q" => Request::Menu(None),
+        number => match number.parse::<usize>() {
+            Ok(number) => {
+                let param = *SETTINGS.get(number)?;
+                Request::Set(param, console::value(param, words.next()?)?)
+            }
+            Err(_) => return console::parse(line),
+        },
+    };
+    match words.next() {
+        Some(_) => None,
+        None => Some(request),
+    }
+}
+
+pub fn write_page<W: Write + ?Sized>(out: &mut W, page: u8, config: &Config) -> core::fmt::Result {
+    writeln!(out, "settings {}/{}", page + 1, PAGES)?;
+    let first = page as usize * PAGE_LEN;
+    for (number, param) in SETTINGS.iter().enumerate().skip(first).take(PAGE_LEN) {
+        let (min, max) = param.range().unwrap_or((0, 0));
+        write!(out, "{} {}=", number, param.name())?;
+        write_number(out, *param, param.get(config).unwrap_or(0))?;
+        write!(out, " ")?;
+        write_number(out, *param, min)?;
+        write!(out, "..")?;
+        write_number(out, *param, max)?;
+        writeln!(out)?;
+    }
+    writeln!(out, "<n> <value> edit, n next, p previous, s save, q quit")
+}
@@ -0,0 +1,75 @@
+// Differential drive mixer: throttle and steering, each -1..1, to the speeds
+// of the left and right wheel, also -1..1. Line following, remote control,
+// maneuvers and scripts all drive through it via drive.rs.
+//
+// The wheels run at throttle +- steering, positive steering speeds up the
+// left wheel and turns towards CarState::Left. With both at full the outer
+// wheel would need more than full speed, then both wheels are scaled down
+// together, so the ratio of the wheel speeds and with it the turn radius is
+// kept instead of the outer wheel saturating alone.
+//
+// The trim adds steering in proportion to the throttle, for a car that
+// drifts to one side on a straight line. Stopped it does nothing.
+
+use crate::fixed::Fix;
+
+#[derive(Clone, Copy)]
+pub struct Wheels {
+    pub left: Fix,
+    pub right: Fix,
+}
+
+impl Wheels {
+    // Speed of the center of the car
+    pub fn forward(&self) -> Fix {
+        (self.left + self.right) * Fix::from_f32(0.5)
+    }
+}
+
+pub struct Mixer {
+    tight_turn: Option<Fix>,
+    trim: Fix,
+}
+
+impl Mixer {
+    pub const fn new() -> Self {
+        Mixer {
+            tight_turn: None,
+            trim: Fix::ZERO,
+        }
+    }
+
+    // Steering at full throttle, positive turns left
+    pub fn set_trim(&mut self, trim: Fix) {
+        self.trim = trim;
+    }
+
+    // Steering beyond the threshold drives the inner wheel backwards, more
+    // the further beyond, up to spinning in place at full steering. Without
+    // a threshold the inner wheel only slows down.
+    pub fn set_tight_turn(&mut self, threshold: Option<Fix>) {
+        self.tight_turn = threshold;
+    }
+
+    pub fn mix(&self, throttle: Fix, steering: Fix) -> Wheels {
+        let throttle = throttle.clamp(-Fix::ONE, Fix::ONE);
+        let steering = (steering + self.trim * throttle).clamp(-Fix::ONE, Fix::ONE);
+        let mut left = throttle + steering;
+        let mut right = throttle - steering;
+        if let Some(threshold) = self.tight_turn.filter(|t| steering.abs() > *t) {
+            let reverse = (steering.abs() - threshold) / (Fix::ONE - threshold);
+            let (outer, inner) = if steering > Fix::ZERO {
+                (left, &mut right)
+            } else {
+                (right, &mut left)
+            };
+            *inner = (*inner).min(-outer * reverse);
+        }
+        let max = left.abs().max(right.abs());
+        if max > Fix::ONE {
+            left = left / max;
+            right = right / max;
+        }
+        Wheels { left, right }
+    }
+}
@@ -0,0 +1,50 @@
+// Monotonic time since boot in ms from RTC1 on the low frequency clock, for
+// timestamps and timeouts. It keeps counting when the servo timer is
+// stopped or reconfigured and doesn't depend on frames being processed. The
+// 24 bit counter at 32768 Hz overflows every 512 s, the overflow interrupt
+// extends it to 32 bits of ms, which wrap after 49 days.
+
+use core::cell::RefCell;
+use cortex_m::interrupt::Mutex;
+use microbit::hal::pac::RTC1;
+
+const OVRFLW: u32 = 1 << 1;
+const TICK_HZ: u64 = 32_768;
+
+static OVERFLOWS: Mutex<RefCell<u32>> = Mutex::new(RefCell::new(0));
+
+// Needs the LFCLK running
+pub fn start(rtc: RTC1) {
+    rtc.prescaler.write(|w| unsafe { w.bits(0) });
+    rtc.evtenset.write(|w| unsafe { w.bits(OVRFLW) });
+    rtc.intenset.write(|w| unsafe { w.bits(OVRFLW) });
+    rtc.tasks_start.write(|w| unsafe { w.bits(1) });
+}
+
+// From the RTC1 interrupt
+pub fn overflow() {
+    // Safe as start handed over RTC1, only its events are touched
+    let rtc = unsafe { &*RTC1::ptr() };
+    cortex_m::interrupt::free(|cs| {
+        if rtc.events_ovrflw.read().bits() != 0 {
+            rtc.events_ovrflw.write(|w| unsafe { w.bits(0) });
+            *OVERFLOWS.borrow(cs).borrow_mut() += 1;
+        }
+    });
+}
+
+pub fn now_ms() -> u32 {
+    // Safe as start handed over RTC1, only the counter and events are read
+    let rtc = unsafe { &*RTC1::ptr() };
+    cortex_m::interrupt::free(|cs| {
+        let mut overflows = *OVERFLOWS.borrow(cs).borrow();
+        let mut ticks = rtc.counter.read().bits();
+        // An overflow the interrupt hasn't counted yet, the counter is read
+        // again as the first read may be from before it
+        if rtc.events_ovrflw.read().bits() != 0 {
+            overflows += 1;
+            ticks = rtc.counter.read().bits();
+        }
+        (((overflows as u64) << 24 | ticks as u64) * 1000 / TICK_HZ) as u32
+    })
+}
@@ -0,0 +1,89 @@
+// Motion wakeup from System OFF through the accelerometer interrupt. Before
+// the car powers off the accelerometer is set up to pull its interrupt line
+// low on a jolt, wake.rs senses the line like the buttons. The V2 has an
+// LSM303AGR on the internal bus, interrupt on P0.25 shared with the other
+// internal devices. The V1 has an MMA8653FC or, from board revision 1.5, an
+// LSM303AGR on the shared bus, interrupt 1 on P0.28.
+
+use embedded_hal::i2c::I2c;
+
+const LSM303AGR: u8 = 0x19;
+const LSM303AGR_ID: u8 = 0x33;
+const MMA8653: u8 = 0x1d;
+const MMA8653_ID: u8 = 0x5a;
+
+// LSM303AGR accelerometer: 10 Hz in low power mode, the interrupt on high
+// values of the high-pass filtered axes, so gravity doesn't count. Latched
+// and active low. 16 mg per threshold step at ±2 g.
+const LSM303AGR_WAKE: [(u8, u8); 8] = [
+    // CTRL_REG1_A
+    (0x20, 0x2f),
+    // CTRL_REG2_A, high-pass filter for interrupt 1
+    (0x21, 0x01),
+    // CTRL_REG3_A, interrupt 1 on INT1
+    (0x22, 0x40),
+    // CTRL_REG4_A, ±2 g
+    (0x23, 0x00),
+    // CTRL_REG5_A, latched
+    (0x24, 0x08),
+    // CTRL_REG6_A, active low
+    (0x25, 0x02),
+    // INT1_THS_A, about 100 mg
+    (0x32, 6),
+    // INT1_CFG_A, any of X, Y or Z high
+    (0x30, 0x2a),
+];
+const LSM303AGR_WHO_AM_I: u8 = 0x0f;
+// Reading REFERENCE sets the filter to the current acceleration
+const LSM303AGR_REFERENCE: u8 = 0x26;
+const LSM303AGR_INT1_SRC: u8 = 0x31;
+
+// MMA8653FC: motion detection on X and Y, Z always sees gravity while the
+// car stands flat. Latched, the interrupt pin is active low by default. 63
+// mg per threshold step.
+const MMA8653_WAKE: [(u8, u8); 5] = [
+    // CTRL_REG1, standby while configuring
+    (0x2a, 0x00),
+    // FF_MT_CFG, latched motion on X or Y
+    (0x15, 0xd8),
+    // FF_MT_THS, about 250 mg
+    (0x17, 4),
+    // CTRL_REG4 and CTRL_REG5, the motion interrupt on INT1
+    (0x2d, 0x04),
+    (0x2e, 0x04),
+];
+const MMA8653_WHO_AM_I: u8 = 0x0d;
+const MMA8653_FF_MT_SRC: u8 = 0x16;
+// CTRL_REG1, active at 12.5 Hz
+const MMA8653_ACTIVE: (u8, u8) = (0x2a, 0x29);
+
+fn read<I: I2c>(i2c: &mut I, address: u8, register: u8) -> Option<u8> {
+    let mut value = [0];
+    i2c.write_read(address, &[register], &mut value).ok()?;
+    Some(value[0])
+}
+
+fn write_all<I: I2c>(i2c: &mut I, address: u8, registers: &[(u8, u8)]) -> Option<()> {
+    for (register, value) in registers {
+        i2c.write(address, &[*register, *value]).ok()?;
+    }
+    Some(())
+}
+
+// Sets up the wakeup on whichever accelerometer answers and clears a latched
+// interrupt, false without one
+pub fn arm<I: I2c>(i2c: &mut I) -> bool {
+    if read(i2c, LSM303AGR, LSM303AGR_WHO_AM_I) == Some(LSM303AGR_ID) {
+        return write_all(i2c, LSM303AGR, &LSM303AGR_WAKE)
+            .and_then(|_| read(i2c, LSM303AGR, LSM303AGR_REFERENCE))
+            .and_then(|_| read(i2c, LSM303AGR, LSM303AGR_INT1_SRC))
+            .is_some();
+    }
+    if read(i2c, MMA8653, MMA8653_WHO_AM_I) == Some(MMA8653_ID) {
+        return write_all(i2c, MMA8653, &MMA8653_WAKE)
+            .and_then(|_| write_all(i2c, MMA8653, &[MMA8653_ACTIVE]))
+            .and_then(|_| read(i2c, MMA8653, MMA8653_FF_MT_SRC))
+            .is_some();
+    }
+    false
+}
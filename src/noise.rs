@@ -0,0 +1,58 @@
+// Photocell noise characterization for `noise [samples]` on a console: with
+// the car stopped the reading is sampled back to back, across the whole
+// servo frame, and summed up as min, max, mean, standard deviation and a
+// coarse histogram. The spread shows the wiring quality, a tail in the
+// histogram spikes like switching noise, and the standard deviation is a
+// guide for the deadband and the filters.
+//
+// The histogram takes a second batch of samples, binned between the min and
+// max of the first, so it needs no sample buffer. Outliers of the second
+// batch land in the end bins.
+
+pub const DEFAULT_SAMPLES: u16 = 2048;
+pub const MAX_SAMPLES: u16 = 10_000;
+pub const BINS: usize = 8;
+
+pub struct Noise {
+    pub samples: u16,
+    pub min: i16,
+    pub max: i16,
+    // In tenths
+    pub mean: i32,
+    pub stddev: i32,
+    // Lower end of the first bin and the bin width
+    pub low: i16,
+    pub width: i16,
+    pub histogram: [u16; BINS],
+}
+
+pub fn measure(samples: u16, mut read: impl FnMut() -> i16) -> Noise {
+    let (mut min, mut max) = (i16::MAX, i16::MIN);
+    let (mut sum, mut sum_sq) = (0i64, 0i64);
+    for _ in 0..samples {
+        let sample = read();
+        min = min.min(sample);
+        max = max.max(sample);
+        sum += sample as i64;
+        sum_sq += sample as i64 * sample as i64;
+    }
+    let n = samples.max(1) as i64;
+    // Variance in hundredths, for the tenths of the standard deviation
+    let variance = (100 * (n * sum_sq - sum * sum) / (n * n)).max(0) as u64;
+    let width = ((max as i32 - min as i32) / BINS as i32 + 1) as i16;
+    let mut histogram = [0; BINS];
+    for _ in 0..samples {
+        let bin = (read() as i32 - min as i32) / width as i32;
+        histogram[bin.clamp(0, BINS as i32 - 1) as usize] += 1;
+    }
+    Noise {
+        samples,
+        min,
+        max,
+        mean: (10 * sum / n) as i32,
+        stddev: variance.isqrt() as i32,
+        low: min,
+        width,
+        histogram,
+    }
+}
@@ -0,0 +1,79 @@
+// Odometer: the total distance driven, in a flash log of its own. It is
+// saved when the car stops and every few minutes while it drives, so
+// pulling the batteries mid run loses little of it, unlike the statistics.
+//
+// Each save programs the next erased word of the page with the total and the
+// last programmed word is the reading, the page is only erased when all
+// SLOTS words are used. A save takes one word write and a cell sees one
+// erase per SLOTS saves. Erasing halts the CPU for up to 90 ms, so a full
+// log is only erased while the car is stopped.
+
+use crate::fixed::Fix;
+use crate::flash::{Flash, Page};
+
+const SLOTS: usize = 64;
+const ERASED: u32 = 0xffff_ffff;
+const SAVE_MS: u32 = 5 * 60_000;
+
+pub struct Odometer {
+    pub total_cm: u32,
+    partial_cm: Fix,
+    // The next erased word, SLOTS when the log is full
+    next: usize,
+    saved_cm: u32,
+    saved_ms: u32,
+    running: bool,
+}
+
+impl Odometer {
+    pub fn load(flash: &Flash) -> Self {
+        let mut words = [0u32; SLOTS];
+        flash.read(Page::Odometer, &mut words);
+        let next = words
+            .iter()
+            .position(|&word| word == ERASED)
+            .unwrap_or(SLOTS);
+        let total_cm = next.checked_sub(1).map_or(0, |last| words[last]);
+        Odometer {
+            total_cm,
+            partial_cm: Fix::ZERO,
+            next,
+            saved_cm: total_cm,
+            saved_ms: 0,
+            running: false,
+        }
+    }
+
+    // Once per frame with the distance driven in it
+    pub fn update(&mut self, flash: &mut Flash, running: bool, cm: Fix, now_ms: u32) {
+        if running {
+            self.partial_cm = self.partial_cm + cm.abs();
+            let whole = self.partial_cm.to_int();
+            self.total_cm = self.total_cm.saturating_add(whole as u32);
+            self.partial_cm = self.partial_cm - Fix::from_int(whole);
+        }
+        let due = running && now_ms.wrapping_sub(self.saved_ms) >= SAVE_MS;
+        let stopped = self.running && !running;
+        if due || stopped {
+            self.save(flash, !running);
+            self.saved_ms = now_ms;
+        }
+        self.running = running;
+    }
+
+    fn save(&mut self, flash: &mut Flash, can_erase: bool) {
+        if self.total_cm == self.saved_cm {
+            return;
+        }
+        if self.next == SLOTS {
+            if !can_erase {
+                return;
+            }
+            flash.erase(Page::Odometer);
+            self.next = 0;
+        }
+        flash.write_word(Page::Odometer, self.next, self.total_cm);
+        self.next += 1;
+        self.saved_cm = self.total_cm;
+    }
+}
@@ -0,0 +1,192 @@
+// Named settings for the `set` and `get` console commands. Settable values
+// live in the config and are only written to flash by `save`, so a tuning
+// session can be thrown away with a reset. Live values are read only and
+// come from main.
+
+use crate::config::{Config, POWER_OFF_MAX_MIN, TRIM_MAX};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Param {
+    Kp,
+    Ki,
+    Kd,
+    Speed,
+    Deadband,
+    Left,
+    Forward,
+    Back,
+    SteerRate,
+    SteerAccel,
+    Failsafe,
+    Headlight,
+    TightTurn,
+    PowerOff,
+    Sensors,
+    Trim,
+    Adc,
+    AdcErrors,
+    Battery,
+    Jitter,
+    Grove,
+    Wheel,
+}
+
+pub const PARAMS: [Param; 22] = [
+    Param::Kp,
+    Param::Ki,
+    Param::Kd,
+    Param::Speed,
+    Param::Deadband,
+    Param::Left,
+    Param::Forward,
+    Param::Back,
+    Param::SteerRate,
+    Param::SteerAccel,
+    Param::Failsafe,
+    Param::Headlight,
+    Param::TightTurn,
+    Param::PowerOff,
+    Param::Sensors,
+    Param::Trim,
+    Param::Adc,
+    Param::AdcErrors,
+    Param::Battery,
+    Param::Jitter,
+    Param::Grove,
+    Param::Wheel,
+];
+
+// The settable params, live values come last in PARAMS
+pub const SETTINGS: &[Param] = PARAMS.split_at(16).0;
+
+impl Param {
+    pub fn name(self) -> &'static str {
+        match self {
+            Param::Kp => "kp",
+            Param::Ki => "ki",
+            Param::Kd => "kd",
+            Param::Speed => "speed",
+            Param::Deadband => "deadband",
+            Param::Left => "left",
+            Param::Forward => "forward",
+            Param::Back => "back",
+            Param::SteerRate => "rate",
+            Param::SteerAccel => "accel",
+            Param::Failsafe => "failsafe",
+            Param::Headlight => "headlight",
+            Param::TightTurn => "tight",
+            Param::PowerOff => "off",
+            Param::Sensors => "sensors",
+            Param::Trim => "trim",
+            Param::Adc => "adc",
+            Param::AdcErrors => "adcerr",
+            Param::Battery => "battery",
+            Param::Jitter => "jitter",
+            Param::Grove => "grove",
+            Param::Wheel => "wheel",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Param> {
+        PARAMS.iter().copied().find(|param| param.name() == name)
+    }
+
+    // Values are integers, the gains in thousandths. None for live values.
+    pub fn range(self) -> Option<(i32, i32)> {
+        match self {
+            Param::Kp | Param::Ki | Param::Kd => Some((0, 65_000)),
+            Param::Speed | Param::SteerRate | Param::SteerAccel | Param::TightTurn => {
+                Some((0, 100))
+            }
+            Param::Deadband => Some((0, 1023)),
+            Param::Left | Param::Forward | Param::Back | Param::Headlight => Some((0, 1023)),
+            Param::Failsafe => Some((20, 60_000)),
+            Param::PowerOff => Some((0, POWER_OFF_MAX_MIN as i32)),
+            Param::Sensors => Some((1, 2)),
+            Param::Trim => Some((-TRIM_MAX as i32, TRIM_MAX as i32)),
+            Param::Adc
+            | Param::AdcErrors
+            | Param::Battery
+            | Param::Jitter
+            | Param::Grove
+            | Param::Wheel => None,
+        }
+    }
+
+    // Shown and typed with three decimals
+    pub fn milli(self) -> bool {
+        matches!(self, Param::Kp | Param::Ki | Param::Kd)
+    }
+
+    pub fn get(self, config: &Config) -> Option<i32> {
+        let value = match self {
+            Param::Kp => config.gains[0] as i32,
+            Param::Ki => config.gains[1] as i32,
+            Param::Kd => config.gains[2] as i32,
+            Param::Speed => config.speed as i32,
+            Param::Deadband => config.deadband as i32,
+            Param::Left => config.thresholds[0] as i32,
+            Param::Forward => config.thresholds[1] as i32,
+            Param::Back => config.thresholds[2] as i32,
+            Param::SteerRate => config.steer_rate as i32,
+            Param::SteerAccel => config.steer_accel as i32,
+            Param::Failsafe => config.failsafe_ms as i32,
+            Param::Headlight => config.headlight_threshold as i32,
+            Param::TightTurn => config.tight_turn as i32,
+            Param::PowerOff => config.power_off_min as i32,
+            Param::Sensors => config.sensors as i32,
+            Param::Trim => config.trim as i32,
+            Param::Adc
+            | Param::AdcErrors
+            | Param::Battery
+            | Param::Jitter
+            | Param::Grove
+            | Param::Wheel => return None,
+        };
+        Some(value)
+    }
+
+    // Within the range, false for live values
+    pub fn accepts(self, value: i32) -> bool {
+        self.range()
+            .is_some_and(|(min, max)| (min..=max).contains(&value))
+    }
+
+    // False if the value is out of range, or the thresholds wouldn't be
+    // increasing any more
+    pub fn set(self, config: &mut Config, value: i32) -> bool {
+        if !self.accepts(value) {
+            return false;
+        }
+        let mut thresholds = config.thresholds;
+        match self {
+            Param::Kp => config.gains[0] = value as u16,
+            Param::Ki => config.gains[1] = value as u16,
+            Param::Kd => config.gains[2] = value as u16,
+            Param::Speed => config.speed = value as u8,
+            Param::Deadband => config.deadband = value as u16,
+            Param::Left => thresholds[0] = value as i16,
+            Param::Forward => thresholds[1] = value as i16,
+            Param::Back => thresholds[2] = value as i16,
+            Param::SteerRate => config.steer_rate = value as u8,
+            Param::SteerAccel => config.steer_accel = value as u8,
+            Param::Failsafe => config.failsafe_ms = value as u16,
+            Param::Headlight => config.headlight_threshold = value as i16,
+            Param::TightTurn => config.tight_turn = value as u8,
+            Param::PowerOff => config.power_off_min = value as u8,
+            Param::Sensors => config.sensors = value as u8,
+            Param::Trim => config.trim = value as i8,
+            Param::Adc
+            | Param::AdcErrors
+            | Param::Battery
+            | Param::Jitter
+            | Param::Grove
+            | Param::Wheel => return false,
+        }
+        if thresholds[0] >= thresholds[1] || thresholds[1] >= thresholds[2] {
+            return false;
+        }
+        config.thresholds = thresholds;
+        true
+    }
+}
@@ -0,0 +1,64 @@
+// Photocell reads that ride out ADC conversion errors. A zero in place of a
+// failed conversion reads as the darkest surface, the classifier would
+// steer hard left. A failed conversion is retried right away, when the
+// retries fail too the last good reading stands in for a few frames. Every
+// failure is counted (`get adcerr`), past the hold time the reader gives up
+// with the sensor fault and main stops the car as after a failed self test.
+
+use crate::diagnostics::Fault;
+
+const RETRIES: u8 = 2;
+// 100 ms at one read per frame
+const HOLD_READS: u8 = 5;
+
+// A conversion and its retries
+pub fn convert(mut read: impl FnMut() -> Result<i16, ()>) -> Result<i16, ()> {
+    for _ in 0..RETRIES {
+        if let Ok(reading) = read() {
+            return Ok(reading);
+        }
+    }
+    read()
+}
+
+pub struct PhotoCell {
+    last: Option<i16>,
+    // Reads in a row that fell back to the last good reading
+    held: u8,
+    errors: u32,
+}
+
+impl PhotoCell {
+    pub const fn new() -> Self {
+        PhotoCell {
+            last: None,
+            held: 0,
+            errors: 0,
+        }
+    }
+
+    // Failed conversions since boot, retries included
+    pub fn errors(&self) -> u32 {
+        self.errors
+    }
+
+    pub fn read(&mut self, mut read: impl FnMut() -> Result<i16, ()>) -> Result<i16, Fault> {
+        let result = convert(|| {
+            let result = read();
+            self.errors += result.is_err() as u32;
+            result
+        });
+        match (result, self.last) {
+            (Ok(reading), _) => {
+                self.last = Some(reading);
+                self.held = 0;
+                Ok(reading)
+            }
+            (Err(()), Some(last)) if self.held < HOLD_READS => {
+                self.held += 1;
+                Ok(last)
+            }
+            (Err(()), _) => Err(Fault::Adc),
+        }
+    }
+}
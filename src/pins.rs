@@ -0,0 +1,121 @@
+// Pin mapping, chosen at build time. Ring:bit accessory stacks occupy
+// different pads, so the pins the car itself needs are picked here from the
+// three big pads: the photocell (it needs an analog input, all three have
+// one) and the two servos. The add-on pins start at the defaults below and
+// can still be moved with `addon` on the console. The checks at the end
+// fail the build for a mapping that uses a pin twice.
+
+use microbit::hal::{
+    gpio::{Disconnected, Pin},
+    pac,
+};
+
+use crate::expansion::Spare;
+
+// The big edge pads
+#[derive(Clone, Copy)]
+pub enum Pad {
+    P0,
+    P1,
+    P2,
+}
+
+impl Pad {
+    // P0.03, P0.02 and P0.01 on the V1, P0.02, P0.03 and P0.04 on the V2
+    #[cfg(feature = "v1")]
+    pub const fn gpio(self) -> usize {
+        match self {
+            Pad::P0 => 3,
+            Pad::P1 => 2,
+            Pad::P2 => 1,
+        }
+    }
+    #[cfg(feature = "v2")]
+    pub const fn gpio(self) -> usize {
+        match self {
+            Pad::P0 => 2,
+            Pad::P1 => 3,
+            Pad::P2 => 4,
+        }
+    }
+
+    // AIN4, AIN3 and AIN2 on the V1, AIN0, AIN1 and AIN2 on the V2
+    #[cfg(feature = "v1")]
+    pub const fn ain(self) -> u8 {
+        match self {
+            Pad::P0 => 4,
+            Pad::P1 => 3,
+            Pad::P2 => 2,
+        }
+    }
+    #[cfg(feature = "v2")]
+    pub const fn ain(self) -> u8 {
+        match self {
+            Pad::P0 => 0,
+            Pad::P1 => 1,
+            Pad::P2 => 2,
+        }
+    }
+}
+
+pub const SENSOR: Pad = Pad::P0;
+// Left and right servo
+pub const SERVOS: [Pad; 2] = [Pad::P1, Pad::P2];
+
+// Default add-on pins
+pub const SERIAL_TX: Option<Spare> = Some(Spare::P8);
+pub const SERIAL_RX: Option<Spare> = Some(Spare::P12);
+pub const HEADLIGHT: Option<Spare> = None;
+pub const ENCODER: [Option<Spare>; 2] = [None, None];
+pub const SONAR: Option<Spare> = None;
+
+const _: () = assert!(
+    SENSOR as u8 != SERVOS[0] as u8
+        && SENSOR as u8 != SERVOS[1] as u8
+        && SERVOS[0] as u8 != SERVOS[1] as u8,
+    "a pad is mapped twice"
+);
+const _: () = assert!(
+    distinct(&[SERIAL_TX, SERIAL_RX, HEADLIGHT, ENCODER[0], ENCODER[1], SONAR]),
+    "a spare pin is the default of two add-ons"
+);
+
+const fn distinct(spares: &[Option<Spare>]) -> bool {
+    let mut i = 0;
+    while i < spares.len() {
+        let mut j = i + 1;
+        while j < spares.len() {
+            if let (Some(a), Some(b)) = (spares[i], spares[j]) {
+                if a as u8 == b as u8 {
+                    return false;
+                }
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
+
+// The photocell pin and the servo pins out of the pads in the order of Pad
+pub fn assign(pads: [Pin<Disconnected>; 3]) -> (Pin<Disconnected>, [Pin<Disconnected>; 2]) {
+    let mut pads = pads.map(Some);
+    let mut take = |pad: Pad| pads[pad as usize].take();
+    match (take(SENSOR), take(SERVOS[0]), take(SERVOS[1])) {
+        (Some(sensor), Some(left), Some(right)) => (sensor, [left, right]),
+        // Distinct pads, checked above
+        _ => unreachable!(),
+    }
+}
+
+// For the pins whose config the HAL doesn't cover, like SENSE or a pull on
+// an analog input. Safe as every user only touches the config of its own
+// pin.
+#[cfg(feature = "v1")]
+pub fn port() -> &'static pac::gpio::RegisterBlock {
+    unsafe { &*pac::GPIO::ptr() }
+}
+#[cfg(feature = "v2")]
+pub fn port() -> &'static pac::p0::RegisterBlock {
+    unsafe { &*pac::P0::ptr() }
+}
@@ -0,0 +1,392 @@
+// micro:bit style radio link (Nordic proprietary 1 Mbit mode).
+// The RADIO PAC is used directly, the HAL only has an IEEE 802.15.4 driver
+// for the nRF52. Frequency, group and address come from the flash config so
+// several cars in one room can be kept apart.
+//
+// Packet layout: [length, version, group, command, data..], the length byte
+// counts the bytes that follow it.
+//
+// Commands sent to the car carry [counter: u32 LE, mac: 4 bytes, args..] as
+// data. The MAC is the start of
+// AES-128(key, [counter, command, group, address, args.., 0..])
+// computed with the ECB peripheral, the counter has to increase with every
+// packet. Packets with a wrong MAC or a stale counter are dropped, so only the
+// paired transmitter can command the car. The counter is not persisted, a
+// replay is possible after the car rebooted until the next fresh command.
+//
+// Pairing is the weak spot: the pair offer carries the session key in
+// plaintext on the public pairing address, so a receiver listening while
+// the car pairs learns the key and can command the car like the paired
+// transmitter. The only mitigation is the short window, the offer is only
+// sent while A+B started pairing and for at most 30 s, and pairing again
+// replaces the key. Pair where no one else is listening.
+//
+// The exception is the clock sync broadcast of an event master,
+// [length, version, group, 7, time: u32 LE], which has no counter or MAC: it
+// goes to all cars of the group and can't move a car. The same goes for the
+// position broadcast of a car on the track, [length, version, group, 10,
+// segment, time in it: u16 LE], which can only slow a car down, see
+// convoy.rs.
+//
+// At most MAX_PER_FRAME packets are looked at per servo frame, so a flooding
+// transmitter can't starve the control loop. There is one packet buffer and
+// the radio disables itself after a packet, so packets that arrive while one
+// is waiting to be looked at are dropped.
+
+use microbit::hal::{ecb::Ecb, pac::RADIO};
+
+use crate::config::RadioConfig;
+use crate::fixed::Fix;
+use crate::script::{Chunk, ScriptCommand};
+
+const MAX_PAYLOAD: usize = 32;
+// Also holds a BLE advertising PDU: 2 byte header and 37 byte payload
+const BUF_LEN: usize = 39;
+const VERSION: u8 = 1;
+const MAX_ARGS: usize = 6;
+const MAX_PER_FRAME: u8 = 2;
+
+const CMD_START: u8 = 1;
+const CMD_STOP: u8 = 2;
+// Car -> transmitter: [address: u32 LE, group, frequency, key: 16 bytes],
+// repeated while pairing
+const CMD_PAIR_OFFER: u8 = 3;
+// Transmitter -> car, sent on the offered address
+const CMD_PAIR_ACCEPT: u8 = 4;
+// [throttle: i8, steering: i8], -127..127 = -1..1
+const CMD_DRIVE: u8 = 5;
+// Stop, save and reboot
+const CMD_RESET: u8 = 6;
+// Event master -> all cars: [time: u32 LE] in ms, unauthenticated
+const CMD_SYNC: u8 = 7;
+// [time: u32 LE], start the car when the synchronized clock reaches it
+const CMD_START_AT: u8 = 8;
+// [op, bytes..], op 0 clear, 1 append the bytes, 2 save, 3 run, see script.rs
+const CMD_SCRIPT: u8 = 9;
+// Car -> cars: [segment, time in it: u16 LE] in ms, unauthenticated
+const CMD_POSITION: u8 = 10;
+
+const ARG_SCALE: Fix = Fix::from_f32(1.0 / 127.0);
+
+// Well known settings both sides switch to while pairing
+pub const PAIRING: RadioConfig = RadioConfig {
+    frequency: 7,
+    group: 0,
+    address: 0x7061_6972, // "pair"
+    key: [0; 16],
+};
+
+pub enum Command {
+    Start,
+    Stop,
+    PairAccept,
+    Drive { throttle: Fix, steering: Fix },
+    Reset,
+    Sync { time_ms: u32 },
+    Position { segment: u8, segment_ms: u16 },
+    StartAt { time_ms: u32 },
+    Script(ScriptCommand),
+}
+
+pub struct Radio {
+    radio: RADIO,
+    ecb: Ecb,
+    config: RadioConfig,
+    last_counter: u32,
+    budget: u8,
+    buf: [u8; BUF_LEN],
+}
+
+impl Radio {
+    pub fn new(radio: RADIO, ecb: Ecb, config: &RadioConfig) -> Self {
+        // 0 dBm
+        radio.txpower.write(|w| unsafe { w.bits(0) });
+        radio.txaddress.write(|w| unsafe { w.bits(0) });
+        radio.rxaddresses.write(|w| unsafe { w.bits(1) });
+        // READY -> START, END -> DISABLE
+        radio.shorts.write(|w| unsafe { w.bits(0b11) });
+        let radio = Radio {
+            radio,
+            ecb,
+            config: *config,
+            last_counter: 0,
+            budget: MAX_PER_FRAME,
+            buf: [0; BUF_LEN],
+        };
+        radio.setup();
+        radio
+    }
+
+    // Call listen() afterwards to receive with the new settings
+    pub fn configure(&mut self, config: &RadioConfig) {
+        self.disable();
+        self.config = *config;
+        self.last_counter = 0;
+        self.setup();
+    }
+
+    // micro:bit radio settings from self.config
+    fn setup(&self) {
+        let config = &self.config;
+        // Nrf_1Mbit
+        self.radio.mode.write(|w| unsafe { w.bits(0) });
+        // 8 bit length field, no S0/S1
+        self.radio.pcnf0.write(|w| unsafe { w.bits(8) });
+        // Max payload, 4 byte base address, little endian, whitening on
+        self.radio
+            .pcnf1
+            .write(|w| unsafe { w.bits(1 << 25 | 4 << 16 | MAX_PAYLOAD as u32) });
+        // 16 bit CCITT CRC
+        self.radio.crccnf.write(|w| unsafe { w.bits(2) });
+        self.radio.crcinit.write(|w| unsafe { w.bits(0xffff) });
+        self.radio.crcpoly.write(|w| unsafe { w.bits(0x11021) });
+        self.radio.datawhiteiv.write(|w| unsafe { w.bits(0x18) });
+        self.radio
+            .frequency
+            .write(|w| unsafe { w.bits(config.frequency as u32) });
+        self.radio
+            .base0
+            .write(|w| unsafe { w.bits(config.address) });
+        self.radio
+            .prefix0
+            .write(|w| unsafe { w.bits(config.group as u32) });
+    }
+
+    // The nRF51 needs the factory trim values for the 1 Mbit mode
+    #[cfg(feature = "v1")]
+    pub fn apply_trim(&mut self, ficr: &microbit::hal::pac::FICR) {
+        if ficr.overrideen.read().bits() & 1 == 0 {
+            self.radio
+                .override0
+                .write(|w| unsafe { w.bits(ficr.nrf_1mbit[0].read().bits()) });
+            self.radio
+                .override1
+                .write(|w| unsafe { w.bits(ficr.nrf_1mbit[1].read().bits()) });
+            self.radio
+                .override2
+                .write(|w| unsafe { w.bits(ficr.nrf_1mbit[2].read().bits()) });
+            self.radio
+                .override3
+                .write(|w| unsafe { w.bits(ficr.nrf_1mbit[3].read().bits()) });
+            self.radio
+                .override4
+                .write(|w| unsafe { w.bits(ficr.nrf_1mbit[4].read().bits() | 1 << 31) });
+        }
+    }
+
+    // Must be called again whenever the Radio has been moved, the packet
+    // pointer refers to the buffer inside it.
+    pub fn listen(&mut self) {
+        self.radio
+            .packetptr
+            .write(|w| unsafe { w.bits(self.buf.as_ptr() as u32) });
+        self.radio.events_disabled.write(|w| unsafe { w.bits(0) });
+        self.radio.tasks_rxen.write(|w| unsafe { w.bits(1) });
+    }
+
+    fn disable(&mut self) {
+        if self.radio.state.read().bits() == 0 {
+            return;
+        }
+        self.radio.events_disabled.write(|w| unsafe { w.bits(0) });
+        self.radio.tasks_disable.write(|w| unsafe { w.bits(1) });
+        while self.radio.events_disabled.read().bits() == 0 {}
+    }
+
+    // Blocks for the ramp up and the packet (well below 1 ms), then listens
+    // again
+    pub fn send(&mut self, command: u8, data: &[u8]) {
+        let len = data.len().min(MAX_PAYLOAD - 3);
+        self.disable();
+        self.buf[0] = 3 + len as u8;
+        self.buf[1] = VERSION;
+        self.buf[2] = self.config.group;
+        self.buf[3] = command;
+        self.buf[4..4 + len].copy_from_slice(&data[..len]);
+        self.radio
+            .packetptr
+            .write(|w| unsafe { w.bits(self.buf.as_ptr() as u32) });
+        self.radio.events_disabled.write(|w| unsafe { w.bits(0) });
+        self.radio.tasks_txen.write(|w| unsafe { w.bits(1) });
+        while self.radio.events_disabled.read().bits() == 0 {}
+        self.listen();
+    }
+
+    // Sends a non-connectable BLE advertisement on the three advertising
+    // channels, then returns to the micro:bit radio settings and listens.
+    // `data` are AD structures, at most 31 bytes.
+    pub fn advertise(&mut self, address: [u8; 6], data: &[u8]) {
+        let len = data.len().min(31);
+        self.disable();
+        // Ble_1Mbit
+        self.radio.mode.write(|w| unsafe { w.bits(3) });
+        // 1 byte S0 (PDU header), 8 bit length
+        self.radio.pcnf0.write(|w| unsafe { w.bits(1 << 8 | 8) });
+        // 3 byte base address, little endian, whitening on
+        self.radio
+            .pcnf1
+            .write(|w| unsafe { w.bits(1 << 25 | 3 << 16 | 37) });
+        // Advertising access address 0x8E89BED6
+        self.radio.base0.write(|w| unsafe { w.bits(0x89be_d600) });
+        self.radio.prefix0.write(|w| unsafe { w.bits(0x8e) });
+        // 24 bit CRC, not including the address
+        self.radio.crccnf.write(|w| unsafe { w.bits(1 << 8 | 3) });
+        self.radio.crcpoly.write(|w| unsafe { w.bits(0x0000_065b) });
+        self.radio.crcinit.write(|w| unsafe { w.bits(0x0055_5555) });
+        // ADV_NONCONN_IND with a random address
+        self.buf[0] = 0x42;
+        self.buf[1] = 6 + len as u8;
+        self.buf[2..8].copy_from_slice(&address);
+        self.buf[8..8 + len].copy_from_slice(&data[..len]);
+        // Channels 37, 38 and 39
+        for (channel, frequency) in [(37, 2), (38, 26), (39, 80)] {
+            self.radio.frequency.write(|w| unsafe { w.bits(frequency) });
+            self.radio.datawhiteiv.write(|w| unsafe { w.bits(channel) });
+            self.radio
+                .packetptr
+                .write(|w| unsafe { w.bits(self.buf.as_ptr() as u32) });
+            self.radio.events_disabled.write(|w| unsafe { w.bits(0) });
+            self.radio.tasks_txen.write(|w| unsafe { w.bits(1) });
+            while self.radio.events_disabled.read().bits() == 0 {}
+        }
+        self.setup();
+        self.listen();
+    }
+
+    // The car's place on the track for the others, see convoy.rs
+    pub fn send_position(&mut self, segment: u8, segment_ms: u16) {
+        let [low, high] = segment_ms.to_le_bytes();
+        self.send(CMD_POSITION, &[segment, low, high]);
+    }
+
+    // Transmitter side of receive(), for the joystick firmware
+    fn send_authenticated(&mut self, counter: u32, command: u8, args: &[u8]) {
+        let args = &args[..args.len().min(MAX_ARGS)];
+        let Some(mac) = self.mac(counter, command, args) else {
+            return;
+        };
+        let mut data = [0; 8 + MAX_ARGS];
+        data[..4].copy_from_slice(&counter.to_le_bytes());
+        data[4..8].copy_from_slice(&mac);
+        data[8..8 + args.len()].copy_from_slice(args);
+        self.send(command, &data[..8 + args.len()]);
+    }
+
+    pub fn send_start(&mut self, counter: u32) {
+        self.send_authenticated(counter, CMD_START, &[]);
+    }
+
+    pub fn send_stop(&mut self, counter: u32) {
+        self.send_authenticated(counter, CMD_STOP, &[]);
+    }
+
+    // -127..127 each
+    pub fn send_drive(&mut self, counter: u32, throttle: i8, steering: i8) {
+        self.send_authenticated(counter, CMD_DRIVE, &[throttle as u8, steering as u8]);
+    }
+
+    // On the offered session settings
+    pub fn send_pair_accept(&mut self, counter: u32) {
+        self.send_authenticated(counter, CMD_PAIR_ACCEPT, &[]);
+    }
+
+    // A car's pair offer, heard on the PAIRING settings
+    pub fn receive_pair_offer(&mut self) -> Option<RadioConfig> {
+        if self.radio.events_disabled.read().bits() == 0 {
+            return None;
+        }
+        let crc_ok = self.radio.crcstatus.read().bits() & 1 == 1;
+        let packet = self.buf;
+        self.listen();
+        if !crc_ok || packet[0] != 3 + 22 || packet[1] != VERSION || packet[3] != CMD_PAIR_OFFER {
+            return None;
+        }
+        let data = &packet[4..26];
+        let mut key = [0; 16];
+        key.copy_from_slice(&data[6..]);
+        Some(RadioConfig {
+            address: u32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+            group: data[4],
+            frequency: data[5].min(100),
+            key,
+        })
+    }
+
+    pub fn send_pair_offer(&mut self, session: &RadioConfig) {
+        let mut data = [0; 22];
+        data[..4].copy_from_slice(&session.address.to_le_bytes());
+        data[4] = session.group;
+        data[5] = session.frequency;
+        data[6..].copy_from_slice(&session.key);
+        self.send(CMD_PAIR_OFFER, &data);
+    }
+
+    // Refills the per frame packet budget
+    pub fn new_frame(&mut self) {
+        self.budget = MAX_PER_FRAME;
+    }
+
+    pub fn receive(&mut self) -> Option<Command> {
+        if self.budget == 0 || self.radio.events_disabled.read().bits() == 0 {
+            return None;
+        }
+        self.budget -= 1;
+        let crc_ok = self.radio.crcstatus.read().bits() & 1 == 1;
+        let packet = self.buf;
+        self.listen();
+        let len = packet[0] as usize;
+        let header_ok = crc_ok && packet[1] == VERSION && packet[2] == self.config.group;
+        if header_ok && len == 7 && packet[3] == CMD_SYNC {
+            let time_ms = u32::from_le_bytes([packet[4], packet[5], packet[6], packet[7]]);
+            return Some(Command::Sync { time_ms });
+        }
+        if header_ok && len == 6 && packet[3] == CMD_POSITION {
+            return Some(Command::Position {
+                segment: packet[4],
+                segment_ms: u16::from_le_bytes([packet[5], packet[6]]),
+            });
+        }
+        // Every other command needs the counter and MAC
+        if !header_ok || len < 11 {
+            return None;
+        }
+        let counter = u32::from_le_bytes([packet[4], packet[5], packet[6], packet[7]]);
+        let args = &packet[12..(len + 1).min(12 + MAX_ARGS)];
+        if counter <= self.last_counter || self.mac(counter, packet[3], args)? != packet[8..12] {
+            return None;
+        }
+        self.last_counter = counter;
+        match (packet[3], args) {
+            (CMD_START, _) => Some(Command::Start),
+            (CMD_STOP, _) => Some(Command::Stop),
+            (CMD_PAIR_ACCEPT, _) => Some(Command::PairAccept),
+            (CMD_RESET, _) => Some(Command::Reset),
+            (CMD_START_AT, [a, b, c, d, ..]) => Some(Command::StartAt {
+                time_ms: u32::from_le_bytes([*a, *b, *c, *d]),
+            }),
+            (CMD_SCRIPT, [op, bytes @ ..]) => Some(Command::Script(match op {
+                0 => ScriptCommand::Clear,
+                1 => ScriptCommand::Append(Chunk::new(bytes)?),
+                2 => ScriptCommand::Save,
+                3 => ScriptCommand::Run,
+                _ => return None,
+            })),
+            (CMD_DRIVE, [throttle, steering, ..]) => Some(Command::Drive {
+                throttle: Fix::from_int(*throttle as i8 as i32) * ARG_SCALE,
+                steering: Fix::from_int(*steering as i8 as i32) * ARG_SCALE,
+            }),
+            _ => None,
+        }
+    }
+
+    fn mac(&mut self, counter: u32, command: u8, args: &[u8]) -> Option<[u8; 4]> {
+        let mut block = [0; 16];
+        block[..4].copy_from_slice(&counter.to_le_bytes());
+        block[4] = command;
+        block[5] = self.config.group;
+        block[6..10].copy_from_slice(&self.config.address.to_le_bytes());
+        block[10..10 + args.len()].copy_from_slice(args);
+        let cipher = self.ecb.encrypt_block(block, self.config.key).ok()?;
+        Some([cipher[0], cipher[1], cipher[2], cipher[3]])
+    }
+}
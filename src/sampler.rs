@@ -0,0 +1,126 @@
+// Photocell conversions started by the servo timer. A PPI channel starts
+// the conversion on the CC[3] compare in the middle of the frame, 7.5 ms
+// after the last pulse edge and with the servo outputs quiet, so the
+// switching noise of the pulses doesn't reach the reading. The compare also
+// wakes the main loop, which picks the result up right after.
+//
+// Other reads through the HAL, like the supply voltage, reconfigure the ADC.
+// The main loop arms the conversion again after every frame, and when a
+// conversion didn't run (a capture held CC[3] while the timer passed it, or
+// a read in between) it converts on the spot as before, with convert.
+
+#[cfg(feature = "v2")]
+use core::sync::atomic::{compiler_fence, AtomicI16, Ordering};
+
+use microbit::hal::pac;
+
+use crate::pins;
+
+// The DMA target of the SAADC
+#[cfg(feature = "v2")]
+static SAMPLE: AtomicI16 = AtomicI16::new(0);
+
+// The photocell's analog input from pins.rs
+#[cfg(feature = "v1")]
+pub fn arm() {
+    // Safe while the main loop holds the only Adc handle
+    let adc = unsafe { &*pac::ADC::ptr() };
+    select(adc, pins::SENSOR.ain());
+    adc.events_end.write(|w| unsafe { w.bits(0) });
+}
+
+#[cfg(feature = "v2")]
+pub fn arm() {
+    // Safe while the main loop holds the only Adc handle
+    let adc = unsafe { &*pac::SAADC::ptr() };
+    select(adc, pins::SENSOR.ain());
+    adc.result
+        .ptr
+        .write(|w| unsafe { w.ptr().bits(SAMPLE.as_ptr() as u32) });
+    adc.result.maxcnt.write(|w| unsafe { w.maxcnt().bits(1) });
+    adc.events_end.write(|w| unsafe { w.bits(0) });
+    compiler_fence(Ordering::SeqCst);
+    // The timer's SAMPLE task then fills the buffer
+    adc.tasks_start.write(|w| unsafe { w.bits(1) });
+}
+
+// The conversion of this frame, None if it didn't run
+#[cfg(feature = "v1")]
+pub fn take() -> Option<i16> {
+    let adc = unsafe { &*pac::ADC::ptr() };
+    while adc.busy.read().bits() != 0 {}
+    if adc.events_end.read().bits() == 0 {
+        return None;
+    }
+    adc.events_end.write(|w| unsafe { w.bits(0) });
+    Some(adc.result.read().result().bits() as i16)
+}
+
+#[cfg(feature = "v2")]
+pub fn take() -> Option<i16> {
+    let adc = unsafe { &*pac::SAADC::ptr() };
+    // A read through the HAL in between points the buffer elsewhere
+    if adc.result.ptr.read().bits() != SAMPLE.as_ptr() as u32 {
+        return None;
+    }
+    while adc.status.read().bits() != 0 {}
+    if adc.events_end.read().bits() == 0 {
+        return None;
+    }
+    adc.events_end.write(|w| unsafe { w.bits(0) });
+    compiler_fence(Ordering::SeqCst);
+    Some(SAMPLE.load(Ordering::Relaxed))
+}
+
+// An analog input, the PSEL field is one bit per input
+#[cfg(feature = "v1")]
+fn select(adc: &pac::adc::RegisterBlock, ain: u8) {
+    adc.config.modify(|_, w| unsafe { w.psel().bits(1 << ain) });
+}
+
+// An analog input, PSELP counts from 1, 0 is not connected
+#[cfg(feature = "v2")]
+fn select(adc: &pac::saadc::RegisterBlock, ain: u8) {
+    adc.ch[0]
+        .pselp
+        .write(|w| unsafe { w.pselp().bits(ain + 1) });
+}
+
+// A conversion of an analog input on the spot, as the HAL does it. Leaves the
+// input selected, arm selects the photocell again.
+#[cfg(feature = "v1")]
+pub fn convert(ain: u8) -> Result<i16, ()> {
+    // Safe while the main loop holds the only Adc handle
+    let adc = unsafe { &*pac::ADC::ptr() };
+    while adc.busy.read().bits() != 0 {}
+    select(adc, ain);
+    adc.events_end.write(|w| unsafe { w.bits(0) });
+    adc.tasks_start.write(|w| unsafe { w.bits(1) });
+    while adc.events_end.read().bits() == 0 {}
+    adc.events_end.write(|w| unsafe { w.bits(0) });
+    Ok(adc.result.read().result().bits() as i16)
+}
+
+#[cfg(feature = "v2")]
+pub fn convert(ain: u8) -> Result<i16, ()> {
+    // Safe while the main loop holds the only Adc handle
+    let adc = unsafe { &*pac::SAADC::ptr() };
+    while adc.status.read().bits() != 0 {}
+    let mut value = 0i16;
+    select(adc, ain);
+    adc.result
+        .ptr
+        .write(|w| unsafe { w.ptr().bits(&mut value as *mut i16 as u32) });
+    adc.result.maxcnt.write(|w| unsafe { w.maxcnt().bits(1) });
+    adc.events_end.write(|w| unsafe { w.bits(0) });
+    compiler_fence(Ordering::SeqCst);
+    adc.tasks_start.write(|w| unsafe { w.bits(1) });
+    adc.tasks_sample.write(|w| unsafe { w.bits(1) });
+    while adc.events_end.read().bits() == 0 {}
+    adc.events_end.write(|w| unsafe { w.bits(0) });
+    compiler_fence(Ordering::SeqCst);
+    match adc.result.amount.read().bits() {
+        1 => Ok(value),
+        _ => Err(()),
+    }
+}
@@ -0,0 +1,104 @@
+// Scanning sensor head (add-on `pan`): the photocell sits on a third,
+// positional servo that sweeps it across the line, one of POINTS positions
+// per frame, to build a line profile from the single sensor. The edge
+// position is interpolated from the profile and turned back into the
+// reading a fixed sensor would see, so the classifier, PID and the end of
+// line detection work on it unchanged, only with a wider view.
+//
+// TIMER0 has no compare left for a third pulse, the main loop times it
+// against the servo timer in the middle of the frame, well clear of the
+// wheel pulses. Interrupts can stretch it by a few us.
+
+use embedded_hal::digital::OutputPin;
+use microbit::hal::gpio::{Disconnected, Level, Output, Pin, PushPull};
+
+use crate::drive::PULSE_CENTER;
+
+const POINTS: usize = 5;
+// Pulse width change between the outer points and the center, about 35°
+const SWEEP_US: u32 = 400;
+// Profile positions in thousandths, -1000 and 1000 are the outer points
+const SPACING: i32 = 2000 / (POINTS as i32 - 1);
+
+pub struct ScanHead {
+    pin: Pin<Output<PushPull>>,
+    // Position of the last pulse, the sensor has arrived there by the next
+    // frame
+    point: usize,
+    outwards: bool,
+    profile: [i16; POINTS],
+}
+
+impl ScanHead {
+    pub fn new(pin: Pin<Disconnected>) -> Self {
+        ScanHead {
+            pin: pin.into_push_pull_output(Level::Low),
+            point: POINTS / 2,
+            outwards: true,
+            profile: [0; POINTS],
+        }
+    }
+
+    // Once per frame, after the photocell is read. now_us is the servo timer
+    // position.
+    pub fn pulse(&mut self, now_us: impl Fn() -> u32) {
+        let width =
+            PULSE_CENTER - SWEEP_US + self.next_point() as u32 * 2 * SWEEP_US / (POINTS as u32 - 1);
+        self.pin.set_high().ok();
+        let start = now_us();
+        loop {
+            let us = now_us();
+            // Also ends on a timer wrap
+            if us < start || us >= start + width {
+                break;
+            }
+        }
+        self.pin.set_low().ok();
+    }
+
+    // Sweeps back and forth between the outer points
+    fn next_point(&self) -> usize {
+        match (self.point, self.outwards) {
+            (0, _) => 1,
+            (point, _) if point == POINTS - 1 => POINTS - 2,
+            (point, true) => point + 1,
+            (point, false) => point - 1,
+        }
+    }
+
+    // Takes the reading at the position before the last pulse and returns
+    // the reconstructed one of a fixed sensor
+    pub fn update(&mut self, photo_cell: i16, thresholds: &[i16; 3]) -> i16 {
+        self.profile[self.point] = photo_cell;
+        let next = self.next_point();
+        self.outwards = next > self.point;
+        self.point = next;
+
+        let setpoint = (thresholds[0] as i32 + thresholds[1] as i32) / 2;
+        let offset = |point: usize| self.profile[point] as i32 - setpoint;
+        // The edge nearest to the center
+        let edge = (0..POINTS - 1)
+            .filter(|&point| (offset(point) < 0) != (offset(point + 1) < 0))
+            .min_by_key(|&point| (2 * point as i32 + 1 - (POINTS as i32 - 1)).abs());
+        let Some(point) = edge else {
+            // All on one side of the setpoint, off the line or past its end
+            return self.profile[POINTS / 2];
+        };
+        let (a, b) = (offset(point), offset(point + 1));
+        let x = -1000 + point as i32 * SPACING - a * SPACING / (b - a);
+        // Near the edge a fixed sensor sees setpoint - gradient * x. Scaled
+        // so an edge at an outer point reads like the surface on that side,
+        // which is in an outer band.
+        let rising = b > a;
+        let low = (x > 0) == rising;
+        let span = match low {
+            true => setpoint - *self.profile.iter().min().unwrap_or(&0) as i32,
+            false => *self.profile.iter().max().unwrap_or(&0) as i32 - setpoint,
+        };
+        let reading = match low {
+            true => setpoint - x.abs() * span / 1000,
+            false => setpoint + x.abs() * span / 1000,
+        };
+        reading as i16
+    }
+}
@@ -0,0 +1,63 @@
+// What the LED matrix shows on each display tick: the image main picks for
+// the state, overridden by queued one-shot images (calibration saved, fault)
+// while they last. The display is only written when the image changes.
+// Images are compared by address, they are all statics in images.rs.
+
+use microbit::display::nonblocking::BitImage;
+
+const QUEUE_LEN: usize = 4;
+
+#[derive(Clone, Copy)]
+struct OneShot {
+    image: &'static BitImage,
+    ticks: u8,
+}
+
+pub struct Screen {
+    shown: Option<&'static BitImage>,
+    // The first one shows until its ticks ran out
+    queue: [Option<OneShot>; QUEUE_LEN],
+}
+
+impl Screen {
+    pub const fn new() -> Self {
+        Screen {
+            shown: None,
+            queue: [None; QUEUE_LEN],
+        }
+    }
+
+    // Dropped when the queue is full, as buttons held down can repeat a
+    // message faster than it shows
+    pub fn queue(&mut self, image: &'static BitImage, ticks: u8) {
+        if let Some(slot) = self.queue.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(OneShot { image, ticks });
+        }
+    }
+
+    // Something else wrote the display, like the stats pages
+    pub fn invalidate(&mut self) {
+        self.shown = None;
+    }
+
+    // Called every display tick, returns the image to show if it changed
+    pub fn update(&mut self, state_image: &'static BitImage) -> Option<&'static BitImage> {
+        let image = match &mut self.queue[0] {
+            Some(one_shot) => {
+                let image = one_shot.image;
+                one_shot.ticks = one_shot.ticks.saturating_sub(1);
+                if one_shot.ticks == 0 {
+                    self.queue.rotate_left(1);
+                    self.queue[QUEUE_LEN - 1] = None;
+                }
+                image
+            }
+            None => state_image,
+        };
+        if self.shown.is_some_and(|shown| core::ptr::eq(shown, image)) {
+            return None;
+        }
+        self.shown = Some(image);
+        Some(image)
+    }
+}
@@ -0,0 +1,238 @@
+// Maneuver scripts: a tiny bytecode stored in its own flash page, so new
+// routines can be loaded over the serial console or the radio without
+// reflashing. The interpreter runs like a maneuver, one throttle and
+// steering pair per servo frame.
+//
+// Opcodes, multi byte arguments are little endian, jump targets are byte
+// offsets into the script:
+//
+//   0x00                      end, the car stops
+//   0x01 throttle steering n  drive for n frames, -100..100 percent each,
+//                             without a speed ramp
+//   0x02 cm: i16              forward, negative is backwards
+//   0x03 degrees: i16         turn in place, positive turns left
+//   0x04 n                    wait n frames with the car standing
+//   0x05 count target         jump to target count times, then fall through
+//   0x06 target               jump if the photocell sees the line
+//   0x07 segment target       jump if the track segment is at least segment
+//   0x08 value target         jump if the Grove reading is not 0 and below
+//                             value, like an obstacle closer than value cm
+//
+// There is a single loop counter, loops don't nest. A script that runs off
+// its end, has a bad opcode or a jump out of range ends, as does one that
+// jumps more than MAX_JUMPS times without driving.
+
+use crate::fixed::Fix;
+use crate::flash::{Flash, Page};
+use crate::maneuver::{Motion, Step};
+
+pub const SCRIPT_LEN: usize = 64;
+// Bytes per load command, a console line holds them in hex
+pub const CHUNK_LEN: usize = 24;
+
+const MAGIC: u32 = 0x5242_5343; // "RBSC"
+const WORDS: usize = 2 + SCRIPT_LEN / 4;
+const MAX_JUMPS: u8 = 16;
+const PERCENT: Fix = Fix::from_f32(0.01);
+
+const OP_END: u8 = 0x00;
+const OP_DRIVE: u8 = 0x01;
+const OP_FORWARD: u8 = 0x02;
+const OP_TURN: u8 = 0x03;
+const OP_WAIT: u8 = 0x04;
+const OP_LOOP: u8 = 0x05;
+const OP_IF_LINE: u8 = 0x06;
+const OP_IF_SEGMENT: u8 = 0x07;
+const OP_IF_GROVE: u8 = 0x08;
+
+#[derive(Clone, Copy)]
+pub struct Chunk {
+    pub bytes: [u8; CHUNK_LEN],
+    pub len: u8,
+}
+
+impl Chunk {
+    pub fn new(bytes: &[u8]) -> Option<Self> {
+        let mut chunk = Chunk {
+            bytes: [0; CHUNK_LEN],
+            len: bytes.len() as u8,
+        };
+        chunk.bytes.get_mut(..bytes.len())?.copy_from_slice(bytes);
+        Some(chunk)
+    }
+}
+
+// Loading: clear, append chunks, save. Run starts the saved script.
+#[derive(Clone, Copy)]
+pub enum ScriptCommand {
+    Clear,
+    Append(Chunk),
+    Save,
+    Run,
+}
+
+pub struct Script {
+    code: [u8; SCRIPT_LEN],
+    len: usize,
+    overflow: bool,
+}
+
+impl Script {
+    pub fn load(flash: &Flash) -> Self {
+        let mut words = [0u32; WORDS];
+        flash.read(Page::Script, &mut words);
+        let mut script = Script {
+            code: [0; SCRIPT_LEN],
+            len: 0,
+            overflow: false,
+        };
+        if words[0] == MAGIC && words[1] as usize <= SCRIPT_LEN {
+            script.len = words[1] as usize;
+            for (bytes, word) in script.code.chunks_mut(4).zip(&words[2..]) {
+                bytes.copy_from_slice(&word.to_le_bytes());
+            }
+        }
+        script
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.overflow = false;
+    }
+
+    // Bytes past SCRIPT_LEN are dropped and keep the script from being saved
+    pub fn append(&mut self, chunk: &Chunk) {
+        let bytes = &chunk.bytes[..chunk.len as usize];
+        if self.len + bytes.len() > SCRIPT_LEN {
+            self.overflow = true;
+            return;
+        }
+        self.code[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    // False if the script overflowed while loading
+    pub fn save(&self, flash: &mut Flash) -> bool {
+        if self.overflow {
+            return false;
+        }
+        let mut words = [0u32; WORDS];
+        words[0] = MAGIC;
+        words[1] = self.len as u32;
+        for (word, bytes) in words[2..].iter_mut().zip(self.code.chunks(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        flash.write(Page::Script, &words);
+        true
+    }
+
+    pub fn run(&self) -> Interpreter {
+        Interpreter {
+            code: self.code,
+            len: self.len,
+            pc: 0,
+            step: None,
+            frames: 0,
+            counter: None,
+        }
+    }
+}
+
+// What the script can branch on, sampled every frame
+pub struct Sensors {
+    pub on_line: bool,
+    pub segment: u8,
+    // 0 without a Grove module
+    pub grove: u16,
+}
+
+pub struct Interpreter {
+    code: [u8; SCRIPT_LEN],
+    len: usize,
+    pc: usize,
+    // Timed step in progress and frames done
+    step: Option<Step>,
+    frames: u16,
+    counter: Option<u8>,
+}
+
+impl Interpreter {
+    // Throttle and steering for this frame, None once the script is over
+    pub fn next(&mut self, sensors: &Sensors) -> Option<(Fix, Fix)> {
+        let mut jumps = 0;
+        loop {
+            if let Some(step) = self.step {
+                if self.frames < step.duration() {
+                    let speeds = step.at(self.frames);
+                    self.frames += 1;
+                    return Some(speeds);
+                }
+                self.step = None;
+            }
+            let code = &self.code[..self.len];
+            let op = *code.get(self.pc)?;
+            let arg = |i: usize| code.get(self.pc + i).copied();
+            let arg16 = |i: usize| Some(i16::from_le_bytes([arg(i)?, arg(i + 1)?]));
+            let (step, len) = match op {
+                OP_END => return None,
+                OP_DRIVE => {
+                    let throttle = percent(arg(1)?);
+                    let steering = percent(arg(2)?);
+                    let frames = arg(3)? as u16;
+                    let step = Step {
+                        throttle,
+                        steering,
+                        frames,
+                        ramp: 0,
+                    };
+                    (step, 4)
+                }
+                OP_FORWARD => (Motion::Forward { cm: arg16(1)? }.step(), 3),
+                OP_TURN => (Motion::Turn { degrees: arg16(1)? }.step(), 3),
+                OP_WAIT => {
+                    let step = Step {
+                        throttle: Fix::ZERO,
+                        steering: Fix::ZERO,
+                        frames: arg(1)? as u16,
+                        ramp: 0,
+                    };
+                    (step, 2)
+                }
+                OP_LOOP | OP_IF_LINE | OP_IF_SEGMENT | OP_IF_GROVE => {
+                    let (jump, len) = match op {
+                        OP_LOOP => {
+                            let count = self.counter.unwrap_or(arg(1)?);
+                            self.counter = count.checked_sub(1);
+                            (count > 0, 3)
+                        }
+                        OP_IF_LINE => (sensors.on_line, 2),
+                        OP_IF_SEGMENT => (sensors.segment >= arg(1)?, 3),
+                        _ => {
+                            let grove = sensors.grove;
+                            (grove != 0 && grove < arg(1)? as u16, 3)
+                        }
+                    };
+                    let target = arg(len - 1)? as usize;
+                    if jump {
+                        jumps += 1;
+                        if jumps > MAX_JUMPS {
+                            return None;
+                        }
+                        self.pc = target;
+                    } else {
+                        self.pc += len;
+                    }
+                    continue;
+                }
+                _ => return None,
+            };
+            self.pc += len;
+            self.step = Some(step);
+            self.frames = 0;
+        }
+    }
+}
+
+fn percent(arg: u8) -> Fix {
+    Fix::from_int((arg as i8).clamp(-100, 100) as i32) * PERCENT
+}
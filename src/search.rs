@@ -0,0 +1,60 @@
+// Line search at the end of the line (`end search`): instead of stopping,
+// the car sweeps the photocell across the floor in place, left, right and
+// back to its heading, and follows the line again as soon as it sees it.
+// The first sweep is always the same. A spot that defeats it, like a glare
+// patch or a gap at an odd angle, would defeat it every time, so further
+// sweeps draw their angle, direction and a small shift of the car from the
+// hardware RNG. After MAX_SWEEPS the car gives up and stops as with `end
+// stop`.
+
+use microbit::hal::rng::Rng;
+
+use crate::maneuver::{Maneuver, Motion};
+
+const MAX_SWEEPS: u8 = 4;
+const FIRST_DEGREES: i16 = 60;
+// The random sweeps turn 30..=120° each way and shift by up to ±5 cm first
+const MIN_DEGREES: i16 = 30;
+const DEGREES_RANGE: u8 = 91;
+const SHIFT_RANGE: u8 = 11;
+
+pub struct Search {
+    sweeps: u8,
+}
+
+impl Search {
+    pub fn new() -> Self {
+        Search { sweeps: 0 }
+    }
+
+    // The next sweep, None once the search has failed
+    pub fn sweep(&mut self, rng: &mut Rng) -> Option<Maneuver> {
+        if self.sweeps >= MAX_SWEEPS {
+            return None;
+        }
+        self.sweeps += 1;
+        if self.sweeps == 1 {
+            return Some(sweep(0, FIRST_DEGREES));
+        }
+        let degrees = MIN_DEGREES + (rng.random_u8() % DEGREES_RANGE) as i16;
+        let degrees = match rng.random_u8() & 1 {
+            0 => degrees,
+            _ => -degrees,
+        };
+        let shift_cm = (rng.random_u8() % SHIFT_RANGE) as i16 - SHIFT_RANGE as i16 / 2;
+        Some(sweep(shift_cm, degrees))
+    }
+}
+
+fn sweep(shift_cm: i16, degrees: i16) -> Maneuver {
+    let maneuver = match shift_cm {
+        0 => Maneuver::new(),
+        cm => Maneuver::new().then_motion(Motion::Forward { cm }),
+    };
+    maneuver
+        .then_motion(Motion::Turn { degrees })
+        .then_motion(Motion::Turn {
+            degrees: -2 * degrees,
+        })
+        .then_motion(Motion::Turn { degrees })
+}
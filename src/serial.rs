@@ -0,0 +1,131 @@
+// Serial ports for external boards (Raspberry Pi, ESP32) on the edge
+// connector and for the USB serial port of the interface chip. Both speak the
+// console protocol at 115200 baud, 8N1, 3.3 V levels. The V2 has two UARTEs,
+// the edge port runs on UARTE1 and the USB port on UARTE0, so both consoles
+// work at once. The V1 has a single UART, the USB port only gets it when the
+// tx and rx add-ons are both unassigned. Reception is polled, so the consoles
+// have to be polled faster than bytes arrive, the main loop does that.
+
+use core::fmt;
+
+use embedded_io::{Read, ReadReady};
+use microbit::hal::gpio::{Disconnected, Level, Pin};
+
+use crate::console::Port;
+
+#[cfg(feature = "v2")]
+use crate::console::LINE_LEN;
+#[cfg(feature = "v1")]
+use microbit::hal::{
+    pac::UART0,
+    uart::{Baudrate, Parity, Pins, Uart},
+};
+#[cfg(feature = "v2")]
+use microbit::hal::{
+    pac::{UARTE0, UARTE1},
+    uarte::{Baudrate, Instance, Parity, Pins, Uarte, UarteRx, UarteTx},
+};
+
+#[cfg(feature = "v1")]
+pub struct Serial {
+    uart: Uart<UART0>,
+}
+
+#[cfg(feature = "v2")]
+pub struct Serial<U: Instance> {
+    tx: UarteTx<U>,
+    rx: UarteRx<U>,
+}
+
+#[cfg(feature = "v1")]
+pub type Edge = Serial;
+#[cfg(feature = "v1")]
+pub type Usb = Serial;
+#[cfg(feature = "v2")]
+pub type Edge = Serial<UARTE1>;
+#[cfg(feature = "v2")]
+pub type Usb = Serial<UARTE0>;
+
+pub fn edge_pins(tx: Pin<Disconnected>, rx: Pin<Disconnected>) -> Pins {
+    Pins {
+        txd: tx.into_push_pull_output(Level::High),
+        rxd: rx.into_floating_input(),
+        cts: None,
+        rts: None,
+    }
+}
+
+#[cfg(feature = "v1")]
+impl Serial {
+    pub fn new(uart: UART0, pins: Pins) -> Self {
+        let uart = Uart::new(uart, pins, Parity::EXCLUDED, Baudrate::BAUD115200);
+        Serial { uart }
+    }
+}
+
+// The constructors take the port buffers from static memory, so each must
+// only be called once
+#[cfg(feature = "v2")]
+impl Serial<UARTE1> {
+    pub fn new(uarte: UARTE1, pins: Pins) -> Self {
+        let tx_buf = cortex_m::singleton!(: [u8; LINE_LEN] = [0; LINE_LEN]).unwrap();
+        let rx_buf = cortex_m::singleton!(: [u8; 1] = [0; 1]).unwrap();
+        Serial::split(uarte, pins, tx_buf, rx_buf)
+    }
+}
+
+#[cfg(feature = "v2")]
+impl Serial<UARTE0> {
+    pub fn new(uarte: UARTE0, pins: Pins) -> Self {
+        let tx_buf = cortex_m::singleton!(: [u8; LINE_LEN] = [0; LINE_LEN]).unwrap();
+        let rx_buf = cortex_m::singleton!(: [u8; 1] = [0; 1]).unwrap();
+        Serial::split(uarte, pins, tx_buf, rx_buf)
+    }
+}
+
+#[cfg(feature = "v2")]
+impl<U: Instance> Serial<U> {
+    fn split(
+        uarte: U,
+        pins: Pins,
+        tx_buf: &'static mut [u8; LINE_LEN],
+        rx_buf: &'static mut [u8; 1],
+    ) -> Self {
+        let uarte = Uarte::new(uarte, pins, Parity::EXCLUDED, Baudrate::BAUD115200);
+        let (tx, rx) = uarte.split(tx_buf, rx_buf).unwrap();
+        Serial { tx, rx }
+    }
+}
+
+#[cfg(feature = "v1")]
+impl Port for Serial {
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut byte = [0];
+        let ready = self.uart.read_ready() == Ok(true) && self.uart.read(&mut byte) == Ok(1);
+        ready.then_some(byte[0])
+    }
+}
+
+#[cfg(feature = "v1")]
+impl fmt::Write for Serial {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.uart.write_str(s)
+    }
+}
+
+#[cfg(feature = "v2")]
+impl<U: Instance> Port for Serial<U> {
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut byte = [0];
+        let ready =
+            matches!(self.rx.read_ready(), Ok(true)) && matches!(self.rx.read(&mut byte), Ok(1));
+        ready.then_some(byte[0])
+    }
+}
+
+#[cfg(feature = "v2")]
+impl<U: Instance> fmt::Write for Serial<U> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.tx.write_str(s)
+    }
+}
@@ -0,0 +1,34 @@
+// Console on RTT for a host with a debug probe, no wiring needed: commands
+// arrive on down channel 0 "shell", replies go to up channel 2 "shell". The
+// up channel doesn't block, replies are dropped while no host reads them.
+
+use core::fmt;
+
+use rtt_target::{DownChannel, UpChannel};
+
+use crate::console::Port;
+
+pub struct Shell {
+    up: UpChannel,
+    down: DownChannel,
+}
+
+impl Shell {
+    pub fn new(up: UpChannel, down: DownChannel) -> Self {
+        Shell { up, down }
+    }
+}
+
+impl Port for Shell {
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut byte = [0];
+        (self.down.read(&mut byte) == 1).then_some(byte[0])
+    }
+}
+
+impl fmt::Write for Shell {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.up.write(s.as_bytes());
+        Ok(())
+    }
+}
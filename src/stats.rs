@@ -0,0 +1,78 @@
+// Lifetime statistics persisted in flash: number of runs, driving time and
+// the distance, estimated from the throttle or measured by a wheel encoder.
+// They are saved whenever the car stops, on a page of their own so the
+// configuration is never erased for them.
+
+use crate::fixed::Fix;
+use crate::flash::{Flash, Page};
+
+const MAGIC: u32 = 0x5242_5354; // "RBST"
+const WORDS: usize = 4;
+const FRAMES_PER_S: u32 = 50;
+
+pub struct Stats {
+    pub runs: u32,
+    pub runtime_s: u32,
+    pub distance_cm: u32,
+    frames: u32,
+    partial_cm: Fix,
+    running: bool,
+    // Current run only
+    run_frames: u32,
+    run_cm: u32,
+}
+
+impl Stats {
+    pub fn load(flash: &Flash) -> Self {
+        let mut words = [0u32; WORDS];
+        flash.read(Page::Stats, &mut words);
+        if words[0] != MAGIC {
+            words = [MAGIC, 0, 0, 0];
+        }
+        Stats {
+            runs: words[1],
+            runtime_s: words[2],
+            distance_cm: words[3],
+            frames: 0,
+            partial_cm: Fix::ZERO,
+            running: false,
+            run_frames: 0,
+            run_cm: 0,
+        }
+    }
+
+    // Called once per frame with the distance driven in it
+    pub fn update(&mut self, flash: &mut Flash, running: bool, cm: Fix) {
+        if running && !self.running {
+            self.runs += 1;
+            self.run_frames = 0;
+            self.run_cm = 0;
+        }
+        if running {
+            self.run_frames += 1;
+            self.frames += 1;
+            self.runtime_s += self.frames / FRAMES_PER_S;
+            self.frames %= FRAMES_PER_S;
+            self.partial_cm = self.partial_cm + cm.abs();
+            let cm = self.partial_cm.to_int();
+            self.distance_cm += cm as u32;
+            self.run_cm += cm as u32;
+            self.partial_cm = self.partial_cm - Fix::from_int(cm);
+        }
+        if !running && self.running {
+            flash.write(
+                Page::Stats,
+                &[MAGIC, self.runs, self.runtime_s, self.distance_cm],
+            );
+        }
+        self.running = running;
+    }
+
+    // True once the current run is longer than either limit, 0 disables a
+    // limit
+    pub fn run_limit_reached(&self, limit_s: u16, limit_m: u16) -> bool {
+        let over_time = limit_s > 0 && self.run_frames >= limit_s as u32 * FRAMES_PER_S;
+        let over_distance = limit_m > 0 && self.run_cm >= limit_m as u32 * 100;
+        self.running && (over_time || over_distance)
+    }
+}
@@ -0,0 +1,117 @@
+// Post-run summary on the display: when a run ends the car shows its
+// results as pages like the lifetime statistics, a header icon and then the
+// number digit by digit:
+//
+//   run time in seconds, laps, best lap in tenths of a second, top speed
+//   in cm/s and lowest supply voltage in hundredths of a volt
+//
+// Laps need the marker add-on (markers.rs), without lap marks the two lap
+// pages are left out. As in ghost.rs the run up to the first lap mark only
+// starts a lap, it doesn't count as one. Unlike the statistics the pages
+// play without blocking the main loop, starting the car again ends them.
+
+use microbit::display::nonblocking::BitImage;
+
+use crate::drive::CM_PER_FRAME;
+use crate::fixed::Fix;
+use crate::images::{
+    Pacing, STATS_TIME, SUMMARY_BATTERY, SUMMARY_BEST, SUMMARY_LAPS, SUMMARY_SPEED,
+};
+
+// The speed estimate is per frame
+const FRAMES_PER_S: i32 = 50;
+// Display ticks at 10 Hz
+const PACING: Pacing = Pacing {
+    header: 10,
+    digit: 4,
+    gap: 1,
+};
+const PAGES: usize = 5;
+
+#[derive(Clone, Copy)]
+pub struct Summary {
+    start_ms: u32,
+    last_ms: u32,
+    // Lap marks passed and laps completed between them
+    marks: u16,
+    laps: u16,
+    lap_start_ms: Option<u32>,
+    best_lap_ms: Option<u32>,
+    top_throttle: Fix,
+    min_mv: Option<u16>,
+}
+
+impl Summary {
+    // At the start of a run
+    pub const fn new(now_ms: u32) -> Self {
+        Summary {
+            start_ms: now_ms,
+            last_ms: now_ms,
+            marks: 0,
+            laps: 0,
+            lap_start_ms: None,
+            best_lap_ms: None,
+            top_throttle: Fix::ZERO,
+            min_mv: None,
+        }
+    }
+
+    // Every frame of a run with the commanded throttle, the lap marks passed
+    // and the last supply voltage reading, 0 before the first one
+    pub fn update(&mut self, now_ms: u32, throttle: Fix, laps: u16, battery_mv: u16) {
+        self.last_ms = now_ms;
+        if laps != self.marks {
+            if let Some(start_ms) = self.lap_start_ms {
+                let lap = now_ms.wrapping_sub(start_ms);
+                self.best_lap_ms = Some(self.best_lap_ms.map_or(lap, |best| best.min(lap)));
+                self.laps = self.laps.wrapping_add(1);
+            }
+            self.lap_start_ms = Some(now_ms);
+            self.marks = laps;
+        }
+        self.top_throttle = self.top_throttle.max(throttle.abs());
+        if battery_mv > 0 {
+            self.min_mv = Some(self.min_mv.map_or(battery_mv, |min| min.min(battery_mv)));
+        }
+    }
+
+    fn pages(&self) -> [Option<(&'static BitImage, u32)>; PAGES] {
+        let laps = self.best_lap_ms.is_some();
+        let top_speed = self.top_throttle * CM_PER_FRAME * Fix::from_int(FRAMES_PER_S);
+        [
+            Some((&STATS_TIME, self.last_ms.wrapping_sub(self.start_ms) / 1000)),
+            laps.then_some((&SUMMARY_LAPS, self.laps as u32)),
+            self.best_lap_ms.map(|ms| (&SUMMARY_BEST, ms / 100)),
+            Some((&SUMMARY_SPEED, top_speed.to_int() as u32)),
+            self.min_mv.map(|mv| (&SUMMARY_BATTERY, mv as u32 / 10)),
+        ]
+    }
+
+    pub fn replay(self) -> Replay {
+        Replay {
+            pages: self.pages(),
+            tick: 0,
+        }
+    }
+}
+
+// The pages of a summary as they play
+pub struct Replay {
+    pages: [Option<(&'static BitImage, u32)>; PAGES],
+    tick: u32,
+}
+
+impl Replay {
+    // Every display tick, None once all pages are shown
+    pub fn next(&mut self) -> Option<&'static BitImage> {
+        let mut tick = self.tick;
+        self.tick += 1;
+        for (header, value) in self.pages.iter().flatten() {
+            if tick < PACING.ticks(*value) {
+                return PACING.image(header, *value, tick);
+            }
+            tick -= PACING.ticks(*value);
+        }
+        None
+    }
+}
@@ -0,0 +1,95 @@
+// Binary telemetry for host tools (plotting, replay) on RTT up channel 1
+// "telemetry", next to the defmt log on channel 0. One record per servo
+// frame, postcard encoded and COBS framed: every record ends with a 0 byte,
+// so a reader that attaches mid stream picks up at the next record.
+//
+// Record in postcard encoding (varint integers, zigzag for signed ones, bool
+// as one byte):
+//
+//   u8   version (4), changes whenever the record does
+//   u32  frame counter
+//   u32  event time in ms, shared by all cars after a radio clock sync
+//   u8   state: 0 stopped, 1 forward, 2 left, 3 right, 4 back
+//   bool on
+//   bool remote control
+//   i16  photocell reading
+//   u8   track segment, counted by the side marker sensor
+//   i8   throttle in percent, after the speed profile
+//   i8   steering in percent, after the jerk limiter
+//   u16  supply voltage in mV, refreshed every 100 ms
+//   i16  die temperature in 0.25 °C, refreshed every second
+//
+// Discrete events go out as records of their own between the frame records,
+// starting with 'E' (0x45) in place of the version, then the time and the
+// event, a varint tag followed by its fields:
+//
+//   u8   'E'
+//   u32  event time in ms, as in the frame records
+//   0    state change: u8 from, u8 to, run states in the order of fsm.rs
+//   1    line lost, the photocell left the bands while line following
+//   2    line found again
+//   3    marker tick: u8 the segment it starts
+//   4    lap mark: u16 laps
+//   5    fault: u8 code, the blink code of diagnostics.rs
+//   6    button A pressed
+//   7    button B pressed
+//
+// The channel doesn't block, records are dropped while no host reads them.
+
+use rtt_target::UpChannel;
+use serde::Serialize;
+
+const VERSION: u8 = 4;
+const EVENT: u8 = b'E';
+const BUF_LEN: usize = 32;
+
+#[derive(Serialize)]
+pub struct Record {
+    pub frame: u32,
+    pub time_ms: u32,
+    pub state: u8,
+    pub is_on: bool,
+    pub remote: bool,
+    pub photo_cell: i16,
+    pub segment: u8,
+    pub throttle: i8,
+    pub steering: i8,
+    pub battery_mv: u16,
+    pub temperature: i16,
+}
+
+#[derive(Serialize)]
+pub enum Event {
+    State { from: u8, to: u8 },
+    LineLost,
+    LineFound,
+    Marker { segment: u8 },
+    Lap { laps: u16 },
+    Fault { code: u8 },
+    ButtonA,
+    ButtonB,
+}
+
+pub struct Stream {
+    channel: UpChannel,
+}
+
+impl Stream {
+    pub fn new(channel: UpChannel) -> Self {
+        Stream { channel }
+    }
+
+    pub fn send(&mut self, record: &Record) {
+        let mut buf = [0; BUF_LEN];
+        if let Ok(bytes) = postcard::to_slice_cobs(&(VERSION, record), &mut buf) {
+            self.channel.write(bytes);
+        }
+    }
+
+    pub fn event(&mut self, time_ms: u32, event: &Event) {
+        let mut buf = [0; BUF_LEN];
+        if let Ok(bytes) = postcard::to_slice_cobs(&(EVENT, time_ms, event), &mut buf) {
+            self.channel.write(bytes);
+        }
+    }
+}
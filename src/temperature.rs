@@ -0,0 +1,33 @@
+// Die temperature from the TEMP peripheral for the telemetry, to line up the
+// drift of the photocell and the regulator with long runs. A measurement
+// takes about 36 µs, so it is started once and picked up on the next
+// update instead of waiting for it.
+
+use microbit::hal::pac::TEMP;
+
+pub struct Temperature {
+    temp: TEMP,
+    // Last reading in 0.25 °C steps
+    quarters: i16,
+}
+
+impl Temperature {
+    pub fn new(temp: TEMP) -> Self {
+        temp.tasks_start.write(|w| unsafe { w.bits(1) });
+        Temperature { temp, quarters: 0 }
+    }
+
+    // Every second or so, takes the finished measurement and starts the next
+    pub fn update(&mut self) {
+        if self.temp.events_datardy.read().bits() == 0 {
+            return;
+        }
+        self.temp.events_datardy.write(|w| unsafe { w.bits(0) });
+        self.quarters = self.temp.temp.read().bits() as i32 as i16;
+        self.temp.tasks_start.write(|w| unsafe { w.bits(1) });
+    }
+
+    pub fn quarters(&self) -> i16 {
+        self.quarters
+    }
+}
@@ -0,0 +1,79 @@
+// Settings block in RAM for host tools attached over SWD, like probe-rs, to
+// tune the car while it drives without a serial transport. The block is the
+// symbol RINGBIT_TUNING, its address comes from the ELF (`nm`) and doesn't
+// move while the firmware isn't rebuilt. Little endian words:
+//
+//   0   magic      "RBTP" 0x5242_5450
+//   4   version    1
+//   8   count      number of settings, N
+//   12  request    the host increments it after writing pending
+//   16  applied    request, once the firmware took pending over
+//   20  values     N i32, the settings as they are, updated every frame
+//   20 + 4 N       pending, N i32 written by the host
+//
+// The settings are those of `set` in the order of `help` (params.rs), gains
+// in thousandths. To tune, copy values to pending, change some, increment
+// request and wait for applied to follow. Pending values are checked like a
+// `set`, rejected ones leave the setting as it is, values shows the outcome.
+// Like `set` the changes are kept until a reset, `save` writes them to
+// flash.
+
+use core::ptr::{addr_of, addr_of_mut};
+
+use crate::config::Config;
+use crate::params::SETTINGS;
+
+const MAGIC: u32 = 0x5242_5450;
+const VERSION: u32 = 1;
+const COUNT: usize = SETTINGS.len();
+
+#[repr(C)]
+pub struct Block {
+    magic: u32,
+    version: u32,
+    count: u32,
+    request: u32,
+    applied: u32,
+    values: [i32; COUNT],
+    pending: [i32; COUNT],
+}
+
+// Written by the debugger behind the firmware's back, so every access is
+// volatile
+#[no_mangle]
+static mut RINGBIT_TUNING: Block = Block {
+    magic: MAGIC,
+    version: VERSION,
+    count: COUNT as u32,
+    request: 0,
+    applied: 0,
+    values: [0; COUNT],
+    pending: [0; COUNT],
+};
+
+// Once per frame from the main loop, the only user. True when settings
+// changed.
+pub fn update(config: &mut Config) -> bool {
+    let block = addr_of_mut!(RINGBIT_TUNING);
+    let request = unsafe { addr_of!((*block).request).read_volatile() };
+    let applied = unsafe { addr_of!((*block).applied).read_volatile() };
+    let mut changed = false;
+    if request != applied {
+        let pending = unsafe { addr_of!((*block).pending).read_volatile() };
+        // A second pass takes the thresholds that only fit in order once
+        // their neighbours moved
+        for _ in 0..2 {
+            for (param, value) in SETTINGS.iter().zip(pending) {
+                if param.get(config) != Some(value) && param.set(config, value) {
+                    changed = true;
+                }
+            }
+        }
+    }
+    let values = core::array::from_fn(|i| SETTINGS[i].get(config).unwrap_or(0));
+    unsafe {
+        addr_of_mut!((*block).values).write_volatile(values);
+        addr_of_mut!((*block).applied).write_volatile(request);
+    }
+    changed
+}
@@ -0,0 +1,80 @@
+// Button and motion wakeups through the GPIOTE PORT event.
+// SENSE low on the button pins raises PORT when A or B is pressed, so the
+// main loop can sleep in WFE between servo frames and still sees a press
+// right away. The same SENSE setting wakes the chip from System OFF, there
+// PORT plays no part. The HAL only configures SENSE on degraded pins and the
+// board keeps the typed button pins, the pin config is changed in the PAC.
+// The accelerometer interrupt line only wakes from System OFF, motion.rs
+// sets it up just before.
+
+use microbit::hal::{gpiote::Gpiote, pac};
+
+use crate::pins::port;
+
+#[cfg(feature = "v1")]
+const BUTTON_PINS: [usize; 2] = [17, 26];
+#[cfg(feature = "v2")]
+const BUTTON_PINS: [usize; 2] = [14, 23];
+
+// Accelerometer interrupt, P0.25 on the V2 is shared by the internal devices
+#[cfg(feature = "v1")]
+const MOTION_PIN: usize = 28;
+#[cfg(feature = "v2")]
+const MOTION_PIN: usize = 25;
+
+// Connected input with pullup
+const INPUT_PULLUP: u32 = 3 << 2;
+const SENSE_MASK: u32 = 3 << 16;
+const SENSE_LOW: u32 = 3 << 16;
+// Woken from System OFF by a pin, write 1 to clear
+const RESETREAS_OFF: u32 = 1 << 16;
+
+// Only the config of the button pins and the otherwise unused motion pin
+// changes in this module
+pub fn enable(gpiote: &Gpiote) {
+    let port = port();
+    for pin in BUTTON_PINS {
+        port.pin_cnf[pin].modify(|r, w| unsafe { w.bits(r.bits() & !SENSE_MASK | SENSE_LOW) });
+    }
+    gpiote.port().reset_events();
+    gpiote.port().enable_interrupt();
+}
+
+// From the GPIOTE interrupt. PORT only fires again once both buttons are
+// released, a held button doesn't keep the CPU awake.
+pub fn acknowledge() {
+    // Safe as main only uses GPIOTE channels 0 and 1, audio channel 2
+    let gpiote = unsafe { &*pac::GPIOTE::ptr() };
+    gpiote.events_port.write(|w| unsafe { w.bits(0) });
+    cortex_m::asm::sev();
+}
+
+// System OFF, only a button press or with motion the accelerometer wakes
+// the chip, with a reset. The GPIO outputs keep their levels.
+pub fn power_off(motion: bool) -> ! {
+    if motion {
+        port().pin_cnf[MOTION_PIN].write(|w| unsafe { w.bits(INPUT_PULLUP | SENSE_LOW) });
+    }
+    // Safe as nothing runs after this
+    let power = unsafe { &*pac::POWER::ptr() };
+    power.systemoff.write(|w| unsafe { w.bits(1) });
+    // Under a debugger System OFF is only emulated
+    loop {
+        cortex_m::asm::wfe();
+    }
+}
+
+// True once after a wakeup from System OFF, clears the reset reason
+pub fn woke_from_off() -> bool {
+    // Safe as nothing else reads the reset reason
+    let power = unsafe { &*pac::POWER::ptr() };
+    let off = power.resetreas.read().bits() & RESETREAS_OFF != 0;
+    power.resetreas.write(|w| unsafe { w.bits(RESETREAS_OFF) });
+    off
+}
+
+// Until the next interrupt or event. Interrupts that hand work to the main
+// loop signal an event too, so one raised just before doesn't go missed.
+pub fn sleep() {
+    cortex_m::asm::wfe();
+}
@@ -0,0 +1,50 @@
+// Guided setup for a new car, `wizard` on the console or by itself on the
+// first boot of a board without saved settings. Four steps, each opens with
+// its digit: A starts the step, B skips it and keeps the setting as it is.
+//
+//   1  photocell: the calibration of A+B at reset, digit 1 over the first
+//      surface and A, digit 2 over the second and A
+//   2  deadband: the sensor held still over the line edge, A, and the
+//      reading's noise, sampled as by `noise`, sets the deadband
+//   3  trim: the car on the floor pointing straight ahead, A, and it drives
+//      for two seconds. B if it drifted left, A if right, the car drives
+//      again with the new trim. A and B together once it drives straight.
+//      B or A+B while it drives stops it and ends the step
+//   4  test lap: the car on the line, A, and it follows the line with the
+//      new settings until the end of the line or B
+//
+// At the end the check mark asks to keep the results: A writes them to
+// flash, B throws them away and the settings stay as they were. On a
+// fresh board the wizard then comes back at the next boot.
+
+use crate::noise::Noise;
+
+pub const STEPS: usize = 4;
+// The deadband covers readings within two standard deviations of the
+// center
+const DEADBAND_SIGMAS: i32 = 2;
+pub const NOISE_SAMPLES: u16 = 1000;
+
+// A completed press, the buttons count once both are up
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Press {
+    A,
+    B,
+    Both,
+}
+
+impl Press {
+    pub fn new(a: bool, b: bool) -> Option<Press> {
+        match (a, b) {
+            (true, true) => Some(Press::Both),
+            (true, false) => Some(Press::A),
+            (false, true) => Some(Press::B),
+            (false, false) => None,
+        }
+    }
+}
+
+// From the noise of the still reading, rounded up to whole counts
+pub fn deadband(noise: &Noise) -> u16 {
+    ((DEADBAND_SIGMAS * noise.stddev + 9) / 10).clamp(0, 1023) as u16
+}